@@ -0,0 +1,14 @@
+-- Add migration script here
+-- `totp_pending_secret` holds a freshly generated secret until the user proves
+-- they can produce a code from it, at which point it replaces `totp_secret`.
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+ALTER TABLE users ADD COLUMN totp_pending_secret TEXT NULL;
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+CREATE TABLE user_recovery_codes(
+  user_id uuid NOT NULL
+    REFERENCES users (user_id),
+  code_hash TEXT NOT NULL,
+  used_at timestamptz NULL,
+  PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- Create Audit Log Table
+-- `actor_user_id` is NULL for events without an authenticated user, such as failed logins.
+CREATE TABLE audit_log(
+  id BIGSERIAL PRIMARY KEY,
+  occurred_at timestamptz NOT NULL,
+  actor_user_id uuid NULL
+    REFERENCES users (user_id),
+  action TEXT NOT NULL,
+  target TEXT NULL,
+  request_id uuid NULL,
+  client_ip TEXT NULL,
+  details JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+CREATE INDEX audit_log_actor_user_id_idx ON audit_log (actor_user_id);
+
+-- The log is append-only, so that entries cannot be quietly rewritten after the fact.
+CREATE FUNCTION reject_audit_log_changes() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_is_append_only
+  BEFORE UPDATE OR DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION reject_audit_log_changes();
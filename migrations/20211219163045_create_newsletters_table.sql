@@ -0,0 +1,47 @@
+-- Add migration script here
+-- Create Newsletters Table
+-- Each newsletter is a separate list that people can subscribe to.
+CREATE TABLE newsletters(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  sender_name TEXT NOT NULL,
+  confirmation_copy TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
+
+-- Create Newsletter Subscriptions Table
+-- Whether a subscriber receives a given newsletter, confirmed separately for every newsletter.
+CREATE TABLE newsletter_subscriptions(
+  newsletter_id uuid NOT NULL
+    REFERENCES newsletters (id),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  status TEXT NOT NULL,
+  subscribed_at timestamptz NOT NULL,
+  PRIMARY KEY (newsletter_id, subscriber_id)
+);
+
+BEGIN;
+  -- Everyone who subscribed before there were several newsletters is on the default one.
+  INSERT INTO newsletters (id, slug, name, sender_name, confirmation_copy, created_at)
+    VALUES (
+      'd5ac5d5b-6b5e-4a07-a3c2-6b5d2f6a0c1e',
+      'default',
+      'Newsletter',
+      'Newsletter',
+      'Welcome to our newsletter!',
+      now()
+    );
+  INSERT INTO newsletter_subscriptions (newsletter_id, subscriber_id, status, subscribed_at)
+    SELECT 'd5ac5d5b-6b5e-4a07-a3c2-6b5d2f6a0c1e', id, status, subscribed_at
+    FROM subscriptions;
+
+  -- Tokens now confirm a subscription to a specific newsletter.
+  ALTER TABLE subscription_tokens ADD COLUMN newsletter_id uuid NULL
+    REFERENCES newsletters (id);
+  UPDATE subscription_tokens
+    SET newsletter_id = 'd5ac5d5b-6b5e-4a07-a3c2-6b5d2f6a0c1e';
+  ALTER TABLE subscription_tokens ALTER COLUMN newsletter_id SET NOT NULL;
+COMMIT;
@@ -0,0 +1,25 @@
+-- Add migration script here
+-- Subscribers manage their own preferences through a page only they have the link to.
+BEGIN;
+  ALTER TABLE subscriptions ADD COLUMN preferences_token TEXT NULL UNIQUE;
+  UPDATE subscriptions
+    SET preferences_token = md5(random()::text || id::text);
+  ALTER TABLE subscriptions ALTER COLUMN preferences_token SET NOT NULL;
+
+  -- How often a subscriber wants to hear from us, and whether they asked for a break.
+  ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue';
+  ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz NULL;
+  ALTER TABLE subscriptions ADD COLUMN last_delivered_at timestamptz NULL;
+COMMIT;
+
+-- Create Subscription Changes Table
+-- Every change a subscriber makes to their own subscription.
+CREATE TABLE subscription_changes(
+  id BIGSERIAL PRIMARY KEY,
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  changed_at timestamptz NOT NULL,
+  change TEXT NOT NULL,
+  details JSONB NOT NULL
+);
+CREATE INDEX subscription_changes_subscriber_id_idx ON subscription_changes (subscriber_id);
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Create Email Change Requests Table
+-- A subscriber's new address, kept aside until it is confirmed.
+CREATE TABLE email_change_requests(
+  confirmation_token TEXT NOT NULL,
+  PRIMARY KEY (confirmation_token),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  new_email TEXT NOT NULL,
+  requested_at timestamptz NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Free-form details about subscribers, which newsletters can refer to through merge tags.
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Create Subscriber Tags Table
+-- Labels put on subscribers, so that issues can be sent to some of them only.
+CREATE TABLE subscriber_tags(
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  tag TEXT NOT NULL,
+  tagged_at timestamptz NOT NULL,
+  PRIMARY KEY (subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- Create Newsletter Issues Table
+-- Every issue which was published, so that what happens to it can be recorded.
+CREATE TABLE newsletter_issues(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  newsletter_id uuid NOT NULL
+    REFERENCES newsletters (id),
+  title TEXT NOT NULL,
+  published_at timestamptz NOT NULL
+);
+
+-- Create Issue Opens Table
+-- When a subscriber opened an issue, as told by the tracking pixel in its html.
+CREATE TABLE issue_opens(
+  issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (id),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  first_opened_at timestamptz NOT NULL,
+  last_opened_at timestamptz NOT NULL,
+  open_count INTEGER NOT NULL,
+  PRIMARY KEY (issue_id, subscriber_id)
+);
+
+-- Opens are tracked unless the newsletter or the subscriber turned it off.
+ALTER TABLE newsletters ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE subscriptions ADD COLUMN tracking_opt_out BOOLEAN NOT NULL DEFAULT FALSE;
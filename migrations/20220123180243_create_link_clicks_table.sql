@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Create Link Clicks Table
+-- Which links of an issue a subscriber followed, through the signed redirect they were rewritten to.
+CREATE TABLE link_clicks(
+  issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (id),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  url TEXT NOT NULL,
+  first_clicked_at timestamptz NOT NULL,
+  last_clicked_at timestamptz NOT NULL,
+  click_count INTEGER NOT NULL,
+  PRIMARY KEY (issue_id, subscriber_id, url)
+);
+
+-- How many subscribers an issue was sent to, recorded once it was.
+ALTER TABLE newsletter_issues ADD COLUMN delivered INTEGER NOT NULL DEFAULT 0;
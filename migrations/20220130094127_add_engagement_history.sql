@@ -0,0 +1,18 @@
+-- Add migration script here
+-- When a subscription to a newsletter was confirmed, for growth reports.
+-- Subscriptions confirmed before it was recorded count as confirmed when they started.
+ALTER TABLE newsletter_subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+UPDATE newsletter_subscriptions SET confirmed_at = subscribed_at
+  WHERE status <> 'pending_confirmation';
+
+-- Create Issue Deliveries Table
+-- Who every issue was sent to, so that what they did next can be attributed to it.
+CREATE TABLE issue_deliveries(
+  issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (id),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+  delivered_at timestamptz NOT NULL,
+  PRIMARY KEY (issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_subscriber_id_idx ON issue_deliveries (subscriber_id);
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Published issues are kept, to be read on the web archive at /archive/{slug}.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN html_content TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN preheader TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN public BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- The content of issues published before wasn't kept, so there is nothing to show of them.
+UPDATE newsletter_issues
+SET slug = id::text, html_content = '', public = FALSE
+WHERE slug IS NULL;
+
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN html_content SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+CREATE INDEX newsletter_issues_public_published_at_idx
+  ON newsletter_issues (published_at DESC) WHERE public;
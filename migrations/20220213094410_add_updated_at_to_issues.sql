@@ -0,0 +1,5 @@
+-- Add migration script here
+-- When an issue last changed, as feeds and their conditional requests need to know.
+ALTER TABLE newsletter_issues ADD COLUMN updated_at timestamptz NULL;
+UPDATE newsletter_issues SET updated_at = published_at;
+ALTER TABLE newsletter_issues ALTER COLUMN updated_at SET NOT NULL;
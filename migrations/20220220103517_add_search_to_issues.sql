@@ -0,0 +1,15 @@
+-- Add migration script here
+-- The text of issues, as anyone may read it, for full-text search.
+ALTER TABLE newsletter_issues ADD COLUMN text_content TEXT NOT NULL DEFAULT '';
+-- Issues from before were published with their html only, so a rough text is made of it.
+UPDATE newsletter_issues
+SET text_content = regexp_replace(html_content, '<[^>]*>', ' ', 'g');
+
+-- Titles weigh more than the text when ranking.
+ALTER TABLE newsletter_issues ADD COLUMN search_vector tsvector
+  GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', title), 'A') ||
+    setweight(to_tsvector('english', text_content), 'B')
+  ) STORED;
+CREATE INDEX newsletter_issues_search_vector_idx
+  ON newsletter_issues USING GIN (search_vector);
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How a published issue fared. Rates are relative to the number of recipients, and missing
+/// for issues which reached nobody.
+/// Subscribers who opted out of tracking, or newsletters which don't track opens, make the
+/// counts of opens and clicks lower than the real ones.
+#[derive(Debug, Serialize)]
+pub struct IssueMetrics {
+  pub id: Uuid,
+  /// Slug of the newsletter the issue was published to.
+  pub list: String,
+  pub title: String,
+  pub published_at: DateTime<Utc>,
+  pub delivered: i32,
+  /// Recipients who opened the issue at least once.
+  pub unique_opens: i64,
+  pub total_opens: i64,
+  pub open_rate: Option<f64>,
+  /// Recipients who followed at least one link.
+  pub unique_clicks: i64,
+  pub total_clicks: i64,
+  pub click_through_rate: Option<f64>,
+  /// Recipients who unsubscribed from the newsletter while this was the last issue they got from it.
+  pub unsubscribes: i64,
+}
+
+/// Clicks on one of the links of an issue.
+#[derive(Debug, Serialize)]
+pub struct LinkClicks {
+  pub url: String,
+  /// Recipients who followed the link at least once.
+  pub unique_clicks: i64,
+  pub clicks: i64,
+}
+
+/// What happened to the subscriber base on a given day, in UTC.
+/// Confirmations and unsubscribes count every newsletter separately.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct GrowthDay {
+  pub date: NaiveDate,
+  /// New subscribers, whether or not they confirmed since.
+  pub subscribes: i64,
+  pub confirmations: i64,
+  pub unsubscribes: i64,
+  /// Confirmations minus unsubscribes.
+  pub net: i64,
+}
+
+/// Metrics of the issues, most recent first: only the given one, or only those of the given
+/// newsletter, if asked.
+#[tracing::instrument(name = "Query issue metrics", skip(pool))]
+pub async fn query_issue_metrics(
+  pool: &PgPool,
+  issue_id: Option<Uuid>,
+  list: Option<&str>,
+) -> Result<Vec<IssueMetrics>, anyhow::Error> {
+  let rows = sqlx::query!(
+    r#"
+    SELECT i.id, n.slug, i.title, i.published_at, i.delivered,
+      (SELECT COUNT(*) FROM issue_opens o WHERE o.issue_id = i.id) AS "unique_opens!",
+      (SELECT COALESCE(SUM(o.open_count), 0) FROM issue_opens o WHERE o.issue_id = i.id)
+        AS "total_opens!",
+      (SELECT COUNT(DISTINCT c.subscriber_id) FROM link_clicks c WHERE c.issue_id = i.id)
+        AS "unique_clicks!",
+      (SELECT COALESCE(SUM(c.click_count), 0) FROM link_clicks c WHERE c.issue_id = i.id)
+        AS "total_clicks!",
+      (
+        SELECT COUNT(*) FROM subscription_changes sc
+        WHERE sc.change IN ('unsubscribe', 'lists')
+          AND sc.details->'unsubscribed' @> to_jsonb(n.slug)
+          AND i.id = (
+            SELECT d.issue_id FROM issue_deliveries d
+            JOIN newsletter_issues di ON di.id = d.issue_id
+            WHERE d.subscriber_id = sc.subscriber_id
+              AND di.newsletter_id = i.newsletter_id
+              AND d.delivered_at <= sc.changed_at
+            ORDER BY d.delivered_at DESC
+            LIMIT 1
+          )
+      ) AS "unsubscribes!"
+    FROM newsletter_issues i
+    JOIN newsletters n ON n.id = i.newsletter_id
+    WHERE ($1::uuid IS NULL OR i.id = $1)
+      AND ($2::text IS NULL OR n.slug = $2)
+    ORDER BY i.published_at DESC
+    "#,
+    issue_id,
+    list,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to query the metrics of issues.")?;
+
+  Ok(
+    rows
+      .into_iter()
+      .map(|r| IssueMetrics {
+        id: r.id,
+        list: r.slug,
+        title: r.title,
+        published_at: r.published_at,
+        delivered: r.delivered,
+        unique_opens: r.unique_opens,
+        total_opens: r.total_opens,
+        open_rate: rate(r.unique_opens, r.delivered),
+        unique_clicks: r.unique_clicks,
+        total_clicks: r.total_clicks,
+        click_through_rate: rate(r.unique_clicks, r.delivered),
+        unsubscribes: r.unsubscribes,
+      })
+      .collect(),
+  )
+}
+
+/// The clicks of every link of the issue which was followed, most followed first.
+#[tracing::instrument(name = "Query link clicks", skip(pool))]
+pub async fn query_link_clicks(
+  pool: &PgPool,
+  issue_id: Uuid,
+) -> Result<Vec<LinkClicks>, anyhow::Error> {
+  sqlx::query_as!(
+    LinkClicks,
+    r#"
+    SELECT url, COUNT(*) AS "unique_clicks!", SUM(click_count) AS "clicks!"
+    FROM link_clicks
+    WHERE issue_id = $1
+    GROUP BY url
+    ORDER BY 3 DESC, url
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to query the clicks of an issue.")
+}
+
+/// Every day from `from` to `to`, both included, even those when nothing happened.
+#[tracing::instrument(name = "Query growth", skip(pool))]
+pub async fn query_growth(
+  pool: &PgPool,
+  from: NaiveDate,
+  to: NaiveDate,
+) -> Result<Vec<GrowthDay>, anyhow::Error> {
+  let counts = sqlx::query!(
+    r#"
+    SELECT day AS "day!", event AS "event!", COUNT(*) AS "count!"
+    FROM (
+      SELECT (subscribed_at AT TIME ZONE 'UTC')::date AS day, 'subscribe' AS event
+      FROM subscriptions
+      UNION ALL
+      SELECT (confirmed_at AT TIME ZONE 'UTC')::date, 'confirmation'
+      FROM newsletter_subscriptions
+      WHERE confirmed_at IS NOT NULL
+      UNION ALL
+      SELECT (changed_at AT TIME ZONE 'UTC')::date, 'unsubscribe'
+      FROM subscription_changes, jsonb_array_elements(details->'unsubscribed')
+      WHERE change IN ('unsubscribe', 'lists')
+    ) events
+    WHERE day BETWEEN $1 AND $2
+    GROUP BY day, event
+    "#,
+    from,
+    to,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to query the growth of the subscriber base.")?;
+
+  let mut days: BTreeMap<NaiveDate, GrowthDay> = BTreeMap::new();
+  let mut date = from;
+  while date <= to {
+    days.insert(date, GrowthDay::empty(date));
+    date += Duration::days(1);
+  }
+  for r in counts {
+    if let Some(day) = days.get_mut(&r.day) {
+      match r.event.as_str() {
+        "subscribe" => day.subscribes = r.count,
+        "confirmation" => day.confirmations = r.count,
+        _ => day.unsubscribes = r.count,
+      }
+      day.net = day.confirmations - day.unsubscribes;
+    }
+  }
+  Ok(days.into_values().collect())
+}
+
+impl GrowthDay {
+  fn empty(date: NaiveDate) -> Self {
+    Self {
+      date,
+      subscribes: 0,
+      confirmations: 0,
+      unsubscribes: 0,
+      net: 0,
+    }
+  }
+}
+
+/// Rounded to four decimals, which is plenty for a percentage with two.
+fn rate(count: i64, delivered: i32) -> Option<f64> {
+  (delivered > 0).then(|| (count as f64 / delivered as f64 * 10_000.0).round() / 10_000.0)
+}
+
+/// Writes the rows as CSV, with a header row of their field names.
+pub fn to_csv<T: Serialize>(rows: &[T]) -> Result<Vec<u8>, anyhow::Error> {
+  let mut writer = csv::Writer::from_writer(Vec::new());
+  for row in rows {
+    writer
+      .serialize(row)
+      .context("Failed to write a CSV row.")?;
+  }
+  writer.into_inner().context("Failed to write CSV.")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{rate, to_csv, GrowthDay};
+  use chrono::NaiveDate;
+
+  #[test]
+  fn rates_are_relative_to_deliveries() {
+    assert_eq!(rate(1, 3), Some(0.3333));
+    assert_eq!(rate(0, 0), None);
+  }
+
+  #[test]
+  fn rows_are_written_as_csv_with_a_header() {
+    let csv = to_csv(&[GrowthDay {
+      date: NaiveDate::from_ymd(2022, 1, 30),
+      subscribes: 3,
+      confirmations: 2,
+      unsubscribes: 1,
+      net: 1,
+    }])
+    .unwrap();
+    assert_eq!(
+      String::from_utf8(csv).unwrap(),
+      "date,subscribes,confirmations,unsubscribes,net\n2022-01-30,3,2,1,1\n"
+    );
+  }
+}
@@ -0,0 +1,197 @@
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing_actix_web::RequestId;
+use uuid::Uuid;
+
+use crate::authentication::client_ip;
+
+/// Actions recorded in the audit log.
+#[derive(Debug, Clone, Copy)]
+pub enum AuditAction {
+  Login,
+  LoginFailed,
+  PublishNewsletter,
+  EnrollTotp,
+  ConfirmTotp,
+  ReadAuditLog,
+  CreateNewsletter,
+  UpdateNewsletterTracking,
+  UpdateIssueVisibility,
+  UpdateSubscriberAttributes,
+  TagSubscriber,
+  UntagSubscriber,
+}
+
+impl AuditAction {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AuditAction::Login => "login",
+      AuditAction::LoginFailed => "login_failed",
+      AuditAction::PublishNewsletter => "publish_newsletter",
+      AuditAction::EnrollTotp => "enroll_totp",
+      AuditAction::ConfirmTotp => "confirm_totp",
+      AuditAction::ReadAuditLog => "read_audit_log",
+      AuditAction::CreateNewsletter => "create_newsletter",
+      AuditAction::UpdateNewsletterTracking => "update_newsletter_tracking",
+      AuditAction::UpdateIssueVisibility => "update_issue_visibility",
+      AuditAction::UpdateSubscriberAttributes => "update_subscriber_attributes",
+      AuditAction::TagSubscriber => "tag_subscriber",
+      AuditAction::UntagSubscriber => "untag_subscriber",
+    }
+  }
+}
+
+/// Where a request came from, which is recorded alongside every event it causes.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditContext {
+  pub request_id: Option<Uuid>,
+  pub client_ip: Option<IpAddr>,
+}
+
+impl AuditContext {
+  pub fn from_request(request: &HttpRequest) -> Self {
+    Self {
+      request_id: request.extensions().get::<RequestId>().map(|id| **id),
+      client_ip: client_ip(request),
+    }
+  }
+}
+
+/// Something a user did (or tried to do), and what they did it to.
+pub struct AuditEvent {
+  actor: Option<Uuid>,
+  action: AuditAction,
+  target: Option<String>,
+  details: serde_json::Value,
+}
+
+impl AuditEvent {
+  pub fn new(actor: Option<Uuid>, action: AuditAction) -> Self {
+    Self {
+      actor,
+      action,
+      target: None,
+      details: serde_json::json!({}),
+    }
+  }
+
+  pub fn target(mut self, target: impl Into<String>) -> Self {
+    self.target = Some(target.into());
+    self
+  }
+
+  pub fn details(mut self, details: serde_json::Value) -> Self {
+    self.details = details;
+    self
+  }
+}
+
+#[tracing::instrument(
+  name = "Record audit event",
+  skip(pool, context, event),
+  fields(action = event.action.as_str())
+)]
+pub async fn record_audit_event(
+  pool: &PgPool,
+  context: &AuditContext,
+  event: AuditEvent,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO audit_log (occurred_at, actor_user_id, action, target, request_id, client_ip, details)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    "#,
+    Utc::now(),
+    event.actor,
+    event.action.as_str(),
+    event.target,
+    context.request_id,
+    context.client_ip.map(|ip| ip.to_string()),
+    event.details,
+  )
+  .execute(pool)
+  .await
+  .context("Failed to record an audit event.")?;
+  Ok(())
+}
+
+/// Criteria for reading back the audit log. Every criterion is optional.
+#[derive(Debug, Default, Serialize)]
+pub struct AuditLogFilter {
+  pub actor: Option<Uuid>,
+  pub action: Option<String>,
+  /// Inclusive lower bound on when the event occurred.
+  pub from: Option<DateTime<Utc>>,
+  /// Exclusive upper bound on when the event occurred.
+  pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogEntry {
+  pub id: i64,
+  pub occurred_at: DateTime<Utc>,
+  pub actor_user_id: Option<Uuid>,
+  pub action: String,
+  pub target: Option<String>,
+  pub request_id: Option<Uuid>,
+  pub client_ip: Option<String>,
+  pub details: serde_json::Value,
+}
+
+/// Returns one page of matching entries, most recent first, along with the total number of matches.
+#[tracing::instrument(name = "Query audit log", skip(pool))]
+pub async fn query_audit_log(
+  pool: &PgPool,
+  filter: &AuditLogFilter,
+  limit: i64,
+  offset: i64,
+) -> Result<(Vec<AuditLogEntry>, i64), anyhow::Error> {
+  let entries = sqlx::query_as!(
+    AuditLogEntry,
+    r#"
+    SELECT id, occurred_at, actor_user_id, action, target, request_id, client_ip, details
+    FROM audit_log
+    WHERE ($1::uuid IS NULL OR actor_user_id = $1)
+      AND ($2::text IS NULL OR action = $2)
+      AND ($3::timestamptz IS NULL OR occurred_at >= $3)
+      AND ($4::timestamptz IS NULL OR occurred_at < $4)
+    ORDER BY occurred_at DESC, id DESC
+    LIMIT $5 OFFSET $6
+    "#,
+    filter.actor,
+    filter.action,
+    filter.from,
+    filter.to,
+    limit,
+    offset,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to query the audit log.")?;
+
+  let total = sqlx::query!(
+    r#"
+    SELECT COUNT(*) AS "count!"
+    FROM audit_log
+    WHERE ($1::uuid IS NULL OR actor_user_id = $1)
+      AND ($2::text IS NULL OR action = $2)
+      AND ($3::timestamptz IS NULL OR occurred_at >= $3)
+      AND ($4::timestamptz IS NULL OR occurred_at < $4)
+    "#,
+    filter.actor,
+    filter.action,
+    filter.from,
+    filter.to,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to count audit log entries.")?
+  .count;
+
+  Ok((entries, total))
+}
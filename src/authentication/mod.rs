@@ -0,0 +1,9 @@
+mod password;
+mod request;
+mod throttle;
+mod totp;
+
+pub use password::*;
+pub use request::*;
+pub use throttle::*;
+pub use totp::*;
@@ -0,0 +1,371 @@
+use actix_http::header::HeaderMap;
+use std::net::IpAddr;
+
+use anyhow::Context;
+use argon2::{
+  password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+  PasswordVerifier, Version,
+};
+use sqlx::PgPool;
+
+use crate::{
+  authentication::{verify_second_factor, LoginThrottle},
+  configuration::PasswordHashingSettings,
+  routes::error_chain_fmt,
+  telemetry::spawn_blocking_with_tracing,
+};
+
+/// Header carrying either a TOTP code or a recovery code, for users enrolled in two-factor authentication.
+pub const ONE_TIME_CODE_HEADER: &str = "X-TOTP-Code";
+
+pub struct Credentials {
+  pub username: String,
+  pub password: String,
+  pub one_time_code: Option<String>,
+}
+
+/// Errors which may occur while authenticating a user.
+#[derive(thiserror::Error)]
+pub enum AuthError {
+  #[error("Invalid credentials.")]
+  InvalidCredentials(#[source] anyhow::Error),
+  #[error("Too many failed login attempts, retry after {0:?}.")]
+  TooManyAttempts(std::time::Duration),
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+/// The Argon2 parameters used for new password hashes, which stored hashes are expected to match or exceed.
+#[derive(Clone)]
+pub struct PasswordHashing {
+  params: Params,
+  /// Verified against when the username is unknown, so that those requests take as long as any other.
+  dummy_hash: String,
+}
+
+impl PasswordHashing {
+  pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+    let params = Params::new(
+      settings.memory_kib,
+      settings.iterations,
+      settings.parallelism,
+      None,
+    )
+    .context("Invalid Argon2 parameters.")?;
+    let mut hashing = Self {
+      params,
+      dummy_hash: String::new(),
+    };
+    hashing.dummy_hash = hashing.hash(&uuid::Uuid::new_v4().to_string())?;
+    Ok(hashing)
+  }
+
+  pub fn hash(&self, password: &str) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    Ok(
+      Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+        .hash_password(password.as_bytes(), &salt)
+        .context("Failed to hash password.")?
+        .to_string(),
+    )
+  }
+
+  /// Whether the hash was produced with a different algorithm, or with cheaper parameters than configured.
+  fn is_outdated(&self, hash: &PasswordHash) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+      return true;
+    }
+    match Params::try_from(hash) {
+      Ok(params) => {
+        params.m_cost() < self.params.m_cost()
+          || params.t_cost() < self.params.t_cost()
+          || params.p_cost() < self.params.p_cost()
+      }
+      Err(_) => true,
+    }
+  }
+}
+
+/// Parses the header into user credentials, using Basic Authentication.
+/// https://en.wikipedia.org/wiki/Basic_access_authentication.
+/// The one-time code, if any, is read from a separate header since Basic Authentication has no room for it.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+  let header_value = headers
+    .get("Authorization")
+    .context("'Authorization' header is missing")?
+    .to_str()
+    .context("'Authorization' header is not a valid UTF8 encoded string.")?;
+
+  let encoded_segment = header_value
+    .strip_prefix("Basic ")
+    .context("Authorization scheme is not Basic.")?;
+
+  let decoded_bytes = base64::decode_config(encoded_segment, base64::STANDARD)
+    .context("Failed to decode Credentials using base64.")?;
+
+  let decoded_credentials = String::from_utf8(decoded_bytes)
+    .context("Decoded credential data is not a valid UTF8 encoded string.")?;
+
+  let mut credentials = decoded_credentials.splitn(2, ':');
+
+  let username = credentials
+    .next()
+    .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+    .to_string();
+
+  let password = credentials
+    .next()
+    .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+    .to_string();
+
+  let one_time_code = headers
+    .get(ONE_TIME_CODE_HEADER)
+    .map(|v| v.to_str())
+    .transpose()
+    .with_context(|| {
+      format!(
+        "'{}' header is not a valid UTF8 encoded string.",
+        ONE_TIME_CODE_HEADER
+      )
+    })?
+    .map(str::to_string);
+
+  Ok(Credentials {
+    username,
+    password,
+    one_time_code,
+  })
+}
+
+/// Checks the credentials against the stored password hash, and against the
+/// second factor for users who are enrolled in two-factor authentication.
+/// Usernames and IPs with too many recent failures are turned away before any hashing happens.
+/// Hashes made with weaker parameters than configured are transparently upgraded on success.
+#[tracing::instrument(
+  name = "Validate credentials",
+  skip(credentials, throttle, hashing, pool)
+)]
+pub async fn validate_credentials(
+  credentials: Credentials,
+  client_ip: Option<IpAddr>,
+  throttle: &LoginThrottle,
+  hashing: &PasswordHashing,
+  pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+  let username = credentials.username.clone();
+  if let Err(retry_after) = throttle.check(&username, client_ip) {
+    tracing::warn!(
+      target: "audit",
+      event = "authentication_failure",
+      username = %username,
+      client_ip = ?client_ip,
+      reason = "Too many failed login attempts.",
+      "Locked out login attempt",
+    );
+    return Err(AuthError::TooManyAttempts(retry_after));
+  }
+
+  let outcome = check_credentials(credentials, throttle, hashing, pool).await;
+  match &outcome {
+    Ok(_) => throttle.record_success(&username),
+    Err(AuthError::InvalidCredentials(e)) => {
+      throttle.record_failure(&username, client_ip);
+      tracing::warn!(
+        target: "audit",
+        event = "authentication_failure",
+        username = %username,
+        client_ip = ?client_ip,
+        reason = %e,
+        "Failed login attempt",
+      );
+    }
+    Err(_) => {}
+  }
+  outcome
+}
+
+async fn check_credentials(
+  credentials: Credentials,
+  throttle: &LoginThrottle,
+  hashing: &PasswordHashing,
+  pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+  let (user_id, expected_password_hash) = get_stored_credentials(&credentials.username, pool)
+    .await
+    .map_err(AuthError::UnexpectedError)?
+    .map(|(u, p)| (Some(u), p))
+    .unwrap_or((None, hashing.dummy_hash.clone()));
+
+  let password = credentials.password;
+  let stored_hash = expected_password_hash.clone();
+  let blocking_hashing = hashing.clone();
+  let candidate = password.clone();
+  let is_outdated = {
+    let _permit = throttle.verification_permit().await;
+    spawn_blocking_with_tracing(move || {
+      verify_password_hash(expected_password_hash, candidate, &blocking_hashing)
+    })
+    .await
+    .context("Failed to spawn blocking task.")
+    .map_err(AuthError::UnexpectedError)??
+  };
+
+  let user_id =
+    user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username.")))?;
+
+  verify_second_factor(user_id, credentials.one_time_code, pool).await?;
+
+  if is_outdated {
+    // The user is already authenticated at this point, so failing to upgrade shouldn't lock them out.
+    if let Err(e) =
+      upgrade_password_hash(user_id, &stored_hash, password, throttle, hashing, pool).await
+    {
+      tracing::error!(error.cause_chain = ?e, "Failed to upgrade the password hash.");
+    }
+  }
+
+  Ok(user_id)
+}
+
+/// Returns whether the expected hash uses outdated parameters, once the password matches it.
+#[tracing::instrument(
+  name = "Verify password hash",
+  skip(expected_password_hash, password_candidate, hashing)
+)]
+fn verify_password_hash(
+  expected_password_hash: String,
+  password_candidate: String,
+  hashing: &PasswordHashing,
+) -> Result<bool, AuthError> {
+  let expected_password_hash = PasswordHash::new(&expected_password_hash)
+    .context("Failed to parse hash in PHC string format.")
+    .map_err(AuthError::UnexpectedError)?;
+
+  Argon2::default()
+    .verify_password(password_candidate.as_bytes(), &expected_password_hash)
+    .context("Invalid password.")
+    .map_err(AuthError::InvalidCredentials)?;
+
+  Ok(hashing.is_outdated(&expected_password_hash))
+}
+
+/// Hashes the password again with the configured parameters, and stores the new hash.
+/// Only done once the user is fully authenticated, so that a wrong one-time code doesn't cost a second hash.
+#[tracing::instrument(
+  name = "Upgrade password hash",
+  skip(previous_hash, password, throttle, hashing, pool)
+)]
+async fn upgrade_password_hash(
+  user_id: uuid::Uuid,
+  previous_hash: &str,
+  password: String,
+  throttle: &LoginThrottle,
+  hashing: &PasswordHashing,
+  pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+  let hashing = hashing.clone();
+  let new_hash = {
+    let _permit = throttle.verification_permit().await;
+    spawn_blocking_with_tracing(move || hashing.hash(&password))
+      .await
+      .context("Failed to spawn blocking task.")??
+  };
+  update_password_hash(user_id, previous_hash, &new_hash, pool).await
+}
+
+/// Only replaces the hash it was computed from, in case the password changed in the meantime.
+#[tracing::instrument(name = "Update password hash", skip(previous_hash, new_hash, pool))]
+async fn update_password_hash(
+  user_id: uuid::Uuid,
+  previous_hash: &str,
+  new_hash: &str,
+  pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"UPDATE users SET password_hash = $3 WHERE user_id = $1 AND password_hash = $2"#,
+    user_id,
+    previous_hash,
+    new_hash,
+  )
+  .execute(pool)
+  .await
+  .context("Failed to update the password hash.")?;
+  Ok(())
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+  username: &str,
+  pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, String)>, anyhow::Error> {
+  Ok(
+    sqlx::query!(
+      r#"
+      SELECT user_id, password_hash
+      FROM users
+      WHERE username = $1
+      "#,
+      username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate auth credentials.")?
+    .map(|row| (row.user_id, row.password_hash)),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use argon2::PasswordHash;
+
+  use super::PasswordHashing;
+  use crate::configuration::PasswordHashingSettings;
+
+  fn hashing(memory_kib: u32, iterations: u32, parallelism: u32) -> PasswordHashing {
+    PasswordHashing::new(&PasswordHashingSettings {
+      memory_kib,
+      iterations,
+      parallelism,
+    })
+    .unwrap()
+  }
+
+  #[test]
+  fn hashes_with_the_configured_parameters_are_current() {
+    let hashing = hashing(1024, 1, 1);
+    let hash = hashing.hash("password").unwrap();
+    assert!(!hashing.is_outdated(&PasswordHash::new(&hash).unwrap()));
+  }
+
+  #[test]
+  fn hashes_with_stronger_parameters_are_current() {
+    let hash = hashing(2048, 2, 2).hash("password").unwrap();
+    assert!(!hashing(1024, 1, 1).is_outdated(&PasswordHash::new(&hash).unwrap()));
+  }
+
+  #[test]
+  fn hashes_with_any_weaker_parameter_are_outdated() {
+    let current = hashing(2048, 2, 2);
+    for weaker in [
+      hashing(1024, 2, 2),
+      hashing(2048, 1, 2),
+      hashing(2048, 2, 1),
+    ] {
+      let hash = weaker.hash("password").unwrap();
+      assert!(current.is_outdated(&PasswordHash::new(&hash).unwrap()));
+    }
+  }
+
+  #[test]
+  fn hashes_from_other_argon2_variants_are_outdated() {
+    let hash = "$argon2i$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+    assert!(hashing(1024, 1, 1).is_outdated(&PasswordHash::new(hash).unwrap()));
+  }
+}
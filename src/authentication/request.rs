@@ -0,0 +1,79 @@
+use actix_web::{web, HttpRequest};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  audit::{record_audit_event, AuditAction, AuditContext, AuditEvent},
+  authentication::{
+    basic_authentication, validate_credentials, AuthError, LoginThrottle, PasswordHashing,
+  },
+};
+
+/// A user who has passed every authentication check.
+pub struct AuthenticatedUser {
+  pub user_id: Uuid,
+  pub username: String,
+}
+
+/// Authenticates the request with Basic Authentication (plus a one-time code for enrolled users),
+/// recording the outcome in the audit log.
+#[tracing::instrument(
+  name = "Authenticate request",
+  skip(request, pool),
+  fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn authenticate(
+  request: &HttpRequest,
+  pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+  let credentials =
+    basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+  let username = credentials.username.clone();
+  tracing::Span::current().record("username", &tracing::field::display(&username));
+
+  let login_throttle = request
+    .app_data::<web::Data<LoginThrottle>>()
+    .context("The login throttle is not registered with the application.")?;
+  let password_hashing = request
+    .app_data::<web::Data<PasswordHashing>>()
+    .context("Password hashing is not registered with the application.")?;
+  let audit_context = AuditContext::from_request(request);
+
+  let user_id = match validate_credentials(
+    credentials,
+    audit_context.client_ip,
+    login_throttle,
+    password_hashing,
+    pool,
+  )
+  .await
+  {
+    Ok(user_id) => user_id,
+    Err(e) => {
+      let reason = match &e {
+        AuthError::InvalidCredentials(source) => source.to_string(),
+        AuthError::TooManyAttempts(_) => "too many failed attempts".to_string(),
+        AuthError::UnexpectedError(_) => return Err(e),
+      };
+      let event = AuditEvent::new(None, AuditAction::LoginFailed)
+        .target(&username)
+        .details(serde_json::json!({ "reason": reason }));
+      // The request is rejected either way, so a failure to record it shouldn't change the response.
+      if let Err(audit_error) = record_audit_event(pool, &audit_context, event).await {
+        tracing::error!(error.cause_chain = ?audit_error, "Failed to record a failed login.");
+      }
+      return Err(e);
+    }
+  };
+  tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+  record_audit_event(
+    pool,
+    &audit_context,
+    AuditEvent::new(Some(user_id), AuditAction::Login).target(&username),
+  )
+  .await?;
+
+  Ok(AuthenticatedUser { user_id, username })
+}
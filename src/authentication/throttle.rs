@@ -0,0 +1,358 @@
+use std::{
+  collections::HashMap,
+  hash::Hash,
+  net::{IpAddr, SocketAddr},
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use actix_http::header::{HeaderMap, HeaderValue};
+use actix_web::{web::Data, HttpRequest, HttpResponse};
+use anyhow::Context;
+use ipnet::IpNet;
+use reqwest::header;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::configuration::LoginThrottleSettings;
+
+/// Past this many tracked keys, expired entries are pruned so that
+/// an attacker cycling through usernames cannot grow the maps without bound.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Tracks failed logins per username and per client IP, locking a key out
+/// for exponentially longer periods once it exceeds its free attempts.
+/// Also caps how many password hashes are verified concurrently, since each
+/// verification ties up a blocking thread for a noticeable amount of time.
+pub struct LoginThrottle {
+  usernames: FailureCounter<String>,
+  ips: FailureCounter<IpAddr>,
+  verifications: Semaphore,
+}
+
+impl LoginThrottle {
+  pub fn new(settings: &LoginThrottleSettings) -> Self {
+    Self {
+      usernames: FailureCounter::new(
+        settings.username_free_attempts,
+        settings.base_lockout,
+        settings.max_lockout,
+      ),
+      ips: FailureCounter::new(
+        settings.ip_free_attempts,
+        settings.base_lockout,
+        settings.max_lockout,
+      ),
+      verifications: Semaphore::new(settings.max_concurrent_verifications),
+    }
+  }
+
+  /// Returns how long the caller must wait, if either the username or the IP is locked out.
+  pub fn check(&self, username: &str, ip: Option<IpAddr>) -> Result<(), Duration> {
+    let now = Instant::now();
+    let username_lockout = self.usernames.lockout_remaining(username, now);
+    let ip_lockout = ip.and_then(|ip| self.ips.lockout_remaining(&ip, now));
+
+    match username_lockout.max(ip_lockout) {
+      Some(remaining) => Err(remaining),
+      None => Ok(()),
+    }
+  }
+
+  pub fn record_failure(&self, username: &str, ip: Option<IpAddr>) {
+    let now = Instant::now();
+    self.usernames.record_failure(username.to_string(), now);
+    if let Some(ip) = ip {
+      self.ips.record_failure(ip, now);
+    }
+  }
+
+  /// Only the username is forgiven, otherwise an attacker owning any account
+  /// could reset their IP's counter between guesses at other accounts.
+  pub fn record_success(&self, username: &str) {
+    self.usernames.reset(username);
+  }
+
+  /// Waits for a free slot to verify a password hash in.
+  pub async fn verification_permit(&self) -> SemaphorePermit<'_> {
+    self
+      .verifications
+      .acquire()
+      .await
+      .expect("the verification semaphore is never closed")
+  }
+}
+
+struct FailureRecord {
+  failures: u32,
+  last_failure: Instant,
+}
+
+struct FailureCounter<K> {
+  records: Mutex<HashMap<K, FailureRecord>>,
+  free_attempts: u32,
+  base_lockout: Duration,
+  max_lockout: Duration,
+}
+
+impl<K: Eq + Hash> FailureCounter<K> {
+  fn new(free_attempts: u32, base_lockout: Duration, max_lockout: Duration) -> Self {
+    Self {
+      records: Mutex::new(HashMap::new()),
+      free_attempts,
+      base_lockout,
+      max_lockout,
+    }
+  }
+
+  /// The lockout doubles with every failure past the free attempts, up to the maximum.
+  fn lockout_for(&self, failures: u32) -> Option<Duration> {
+    let excess = failures.checked_sub(self.free_attempts)?;
+    let lockout = self
+      .base_lockout
+      .checked_mul(2u32.saturating_pow(excess))
+      .unwrap_or(self.max_lockout);
+    Some(lockout.min(self.max_lockout))
+  }
+
+  fn lockout_remaining<Q>(&self, key: &Q, now: Instant) -> Option<Duration>
+  where
+    K: std::borrow::Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+  {
+    let records = self.records.lock().unwrap();
+    let record = records.get(key)?;
+    let lockout = self.lockout_for(record.failures)?;
+    lockout
+      .checked_sub(now.saturating_duration_since(record.last_failure))
+      .filter(|remaining| !remaining.is_zero())
+  }
+
+  fn record_failure(&self, key: K, now: Instant) {
+    let mut records = self.records.lock().unwrap();
+    if records.len() > PRUNE_THRESHOLD {
+      records.retain(|_, r| !self.is_expired(r, now));
+    }
+
+    let record = records.entry(key).or_insert(FailureRecord {
+      failures: 0,
+      last_failure: now,
+    });
+    // Failures are forgotten after a quiet period, so that the occasional typo doesn't add up over months.
+    if self.is_expired(record, now) {
+      record.failures = 0;
+    }
+    record.failures = record.failures.saturating_add(1);
+    record.last_failure = now;
+  }
+
+  fn reset<Q>(&self, key: &Q)
+  where
+    K: std::borrow::Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+  {
+    self.records.lock().unwrap().remove(key);
+  }
+
+  fn is_expired(&self, record: &FailureRecord, now: Instant) -> bool {
+    now.saturating_duration_since(record.last_failure) > self.max_lockout
+  }
+}
+
+/// The reverse proxies we are deployed behind. Only they are trusted to tell the address
+/// of the client in `X-Forwarded-For`, since anyone else could send any address in it.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+  /// Proxies are single addresses, e.g. `10.0.0.1`, or networks, e.g. `10.0.0.0/8`.
+  pub fn parse(proxies: &[String]) -> Result<Self, anyhow::Error> {
+    proxies
+      .iter()
+      .map(|proxy| {
+        let proxy = proxy.trim();
+        proxy
+          .parse::<IpNet>()
+          .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+          .with_context(|| format!("{} is not an IP address or network.", proxy))
+      })
+      .collect::<Result<_, _>>()
+      .map(Self)
+  }
+
+  fn contains(&self, ip: &IpAddr) -> bool {
+    self.0.iter().any(|proxy| proxy.contains(ip))
+  }
+
+  /// The address of the peer, unless it is a trusted proxy: then the address it forwarded
+  /// the request for, and so on while that address is also a trusted proxy.
+  /// Proxies append the address they received the request from to `X-Forwarded-For`,
+  /// so the addresses are read from the end; those before the first untrusted one may be forged.
+  pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let forwarded_for: Vec<&str> = headers
+      .get_all("x-forwarded-for")
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .map(str::trim)
+      .collect();
+
+    let mut client = peer;
+    for hop in forwarded_for.into_iter().rev() {
+      if !self.contains(&client) {
+        break;
+      }
+      match parse_ip(hop) {
+        Some(ip) => client = ip,
+        None => break,
+      }
+    }
+    client
+  }
+}
+
+/// An address, with or without a port.
+fn parse_ip(address: &str) -> Option<IpAddr> {
+  address
+    .parse::<IpAddr>()
+    .or_else(|_| address.parse::<SocketAddr>().map(|s| s.ip()))
+    .ok()
+}
+
+/// The address of the client: that of the socket, or the one reported by the trusted
+/// reverse proxy we are deployed behind, see `TrustedProxies`.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+  let peer = request.peer_addr()?.ip();
+  Some(match request.app_data::<Data<TrustedProxies>>() {
+    Some(proxies) => proxies.client_ip(peer, request.headers()),
+    None => peer,
+  })
+}
+
+/// Tells the client how many seconds to wait before trying again, rounded up.
+pub fn insert_retry_after(resp: &mut HttpResponse, retry_after: Duration) {
+  let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+  resp.headers_mut().insert(
+    header::RETRY_AFTER,
+    HeaderValue::from_str(&seconds.to_string()).unwrap(),
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use actix_web::test::TestRequest;
+  use claim::{assert_err, assert_none, assert_some_eq};
+
+  use super::{FailureCounter, TrustedProxies};
+
+  fn counter() -> FailureCounter<&'static str> {
+    FailureCounter::new(3, Duration::from_secs(1), Duration::from_secs(60))
+  }
+
+  #[test]
+  fn free_attempts_do_not_lock_out() {
+    let counter = counter();
+    let now = Instant::now();
+    for _ in 0..2 {
+      counter.record_failure("phil", now);
+    }
+    assert_none!(counter.lockout_remaining("phil", now));
+  }
+
+  #[test]
+  fn lockout_doubles_with_each_failure_up_to_the_maximum() {
+    let counter = counter();
+    assert_none!(counter.lockout_for(2));
+    assert_some_eq!(counter.lockout_for(3), Duration::from_secs(1));
+    assert_some_eq!(counter.lockout_for(4), Duration::from_secs(2));
+    assert_some_eq!(counter.lockout_for(8), Duration::from_secs(32));
+    assert_some_eq!(counter.lockout_for(9), Duration::from_secs(60));
+    assert_some_eq!(counter.lockout_for(u32::MAX), Duration::from_secs(60));
+  }
+
+  #[test]
+  fn lockout_expires() {
+    let counter = counter();
+    let now = Instant::now();
+    for _ in 0..4 {
+      counter.record_failure("phil", now);
+    }
+    assert_some_eq!(
+      counter.lockout_remaining("phil", now),
+      Duration::from_secs(2)
+    );
+    assert_none!(counter.lockout_remaining("phil", now + Duration::from_secs(2)));
+  }
+
+  #[test]
+  fn failures_are_forgotten_after_the_maximum_lockout() {
+    let counter = counter();
+    let now = Instant::now();
+    for _ in 0..3 {
+      counter.record_failure("phil", now);
+    }
+    counter.record_failure("phil", now + Duration::from_secs(61));
+    assert_none!(counter.lockout_remaining("phil", now + Duration::from_secs(61)));
+  }
+
+  #[test]
+  fn keys_are_tracked_independently() {
+    let counter = counter();
+    let now = Instant::now();
+    for _ in 0..3 {
+      counter.record_failure("phil", now);
+    }
+    counter.reset("phil");
+    counter.record_failure("ursula", now);
+    assert_none!(counter.lockout_remaining("phil", now));
+    assert_none!(counter.lockout_remaining("ursula", now));
+  }
+
+  fn client_ip(proxies: &[&str], peer: &str, forwarded_for: &[&str]) -> String {
+    let proxies: Vec<String> = proxies.iter().map(|p| p.to_string()).collect();
+    let mut request = TestRequest::default();
+    for value in forwarded_for {
+      request = request.append_header(("X-Forwarded-For", *value));
+    }
+    TrustedProxies::parse(&proxies)
+      .unwrap()
+      .client_ip(peer.parse().unwrap(), request.to_http_request().headers())
+      .to_string()
+  }
+
+  #[test]
+  fn forwarded_addresses_are_ignored_unless_the_peer_is_a_trusted_proxy() {
+    assert_eq!(
+      client_ip(&[], "203.0.113.7", &["198.51.100.1"]),
+      "203.0.113.7"
+    );
+    assert_eq!(
+      client_ip(&["10.0.0.1"], "203.0.113.7", &["198.51.100.1"]),
+      "203.0.113.7"
+    );
+    assert_eq!(
+      client_ip(&["10.0.0.1"], "10.0.0.1", &["198.51.100.1"]),
+      "198.51.100.1"
+    );
+  }
+
+  #[test]
+  fn addresses_forged_before_the_trusted_proxies_are_ignored() {
+    let proxies = ["10.0.0.0/8"];
+    assert_eq!(
+      client_ip(&proxies, "10.0.0.1", &["1.2.3.4, 198.51.100.1, 10.0.0.2"]),
+      "198.51.100.1"
+    );
+    assert_eq!(
+      client_ip(&proxies, "10.0.0.1", &["1.2.3.4", "198.51.100.1:4321"]),
+      "198.51.100.1"
+    );
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &["garbage"]), "10.0.0.1");
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &[]), "10.0.0.1");
+  }
+
+  #[test]
+  fn trusted_proxies_must_be_addresses_or_networks() {
+    assert_err!(TrustedProxies::parse(&["proxy.internal".to_string()]));
+  }
+}
@@ -0,0 +1,377 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use reqwest::Url;
+use sha1::Sha1;
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{authentication::AuthError, routes::error_chain_fmt};
+
+const TOTP_ISSUER: &str = "Newsletter";
+const TOTP_STEP_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// How many steps either side of the current one are still accepted, to allow for clock drift.
+const TOTP_ALLOWED_SKEW: i64 = 1;
+const TOTP_SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_HALF_LENGTH: usize = 5;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A shared secret used to derive time-based one-time passwords, as described in RFC 6238.
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+  pub fn generate() -> Self {
+    let mut secret = vec![0u8; TOTP_SECRET_BYTES];
+    thread_rng().fill_bytes(&mut secret);
+    Self(secret)
+  }
+
+  /// Parses a base32 encoded secret, which is how secrets are stored and shown to authenticator apps.
+  pub fn parse(encoded: &str) -> Result<Self, anyhow::Error> {
+    base32::decode(BASE32, encoded)
+      .filter(|secret| !secret.is_empty())
+      .map(Self)
+      .context("TOTP secret is not valid base32.")
+  }
+
+  pub fn encode(&self) -> String {
+    base32::encode(BASE32, &self.0)
+  }
+
+  /// The code for the given time step, as described in RFC 4226 (HOTP) with the step as the counter.
+  pub fn code_at(&self, step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+      digest[offset] & 0x7f,
+      digest[offset + 1],
+      digest[offset + 2],
+      digest[offset + 3],
+    ]);
+
+    format!(
+      "{:0width$}",
+      binary % 10u32.pow(TOTP_DIGITS),
+      width = TOTP_DIGITS as usize
+    )
+  }
+
+  /// A URI in the format understood by authenticator apps, which is typically shown as a QR code.
+  /// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+  pub fn provisioning_uri(&self, account_name: &str) -> String {
+    let mut uri = Url::parse("otpauth://totp/").expect("otpauth URI prefix is valid");
+    uri.set_path(&format!("{}:{}", TOTP_ISSUER, account_name));
+    uri
+      .query_pairs_mut()
+      .append_pair("secret", &self.encode())
+      .append_pair("issuer", TOTP_ISSUER)
+      .append_pair("algorithm", "SHA1")
+      .append_pair("digits", &TOTP_DIGITS.to_string())
+      .append_pair("period", &TOTP_STEP_SECONDS.to_string());
+    uri.to_string()
+  }
+
+  /// Returns the time step the code belongs to, if it is valid around the given time.
+  fn matching_step(&self, code: &str, time: DateTime<Utc>) -> Option<i64> {
+    let current = time_step(time);
+    (current - TOTP_ALLOWED_SKEW..=current + TOTP_ALLOWED_SKEW)
+      .find(|step| self.code_at(*step) == code)
+  }
+}
+
+/// The number of TOTP periods elapsed since the Unix epoch at the given time.
+pub fn time_step(time: DateTime<Utc>) -> i64 {
+  time.timestamp().div_euclid(TOTP_STEP_SECONDS)
+}
+
+/// Errors which may occur when confirming a two-factor enrollment.
+#[derive(thiserror::Error)]
+pub enum TotpEnrollmentError {
+  #[error("There is no two-factor enrollment in progress.")]
+  NoPendingEnrollment,
+  #[error("The one-time code does not match the pending secret.")]
+  InvalidCode,
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TotpEnrollmentError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+/// Generates a new secret for the user, which only takes effect once confirmed.
+/// Any enrollment which is already active keeps protecting the account until then.
+#[tracing::instrument(name = "Start TOTP enrollment", skip(pool))]
+pub async fn start_totp_enrollment(
+  user_id: Uuid,
+  pool: &PgPool,
+) -> Result<TotpSecret, anyhow::Error> {
+  let secret = TotpSecret::generate();
+  sqlx::query!(
+    r#"UPDATE users SET totp_pending_secret = $2 WHERE user_id = $1"#,
+    user_id,
+    secret.encode(),
+  )
+  .execute(pool)
+  .await
+  .context("Failed to store the pending TOTP secret.")?;
+  Ok(secret)
+}
+
+/// Activates the pending secret if the code matches it, replacing any previous secret and recovery codes.
+/// The freshly generated recovery codes are returned, as this is the only time they are available in plaintext.
+#[tracing::instrument(name = "Confirm TOTP enrollment", skip(code, pool))]
+pub async fn confirm_totp_enrollment(
+  user_id: Uuid,
+  code: &str,
+  pool: &PgPool,
+) -> Result<Vec<String>, TotpEnrollmentError> {
+  let pending_secret = sqlx::query!(
+    r#"SELECT totp_pending_secret FROM users WHERE user_id = $1"#,
+    user_id,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to fetch the pending TOTP secret.")?
+  .totp_pending_secret
+  .ok_or(TotpEnrollmentError::NoPendingEnrollment)?;
+
+  let step = TotpSecret::parse(&pending_secret)?
+    .matching_step(code, Utc::now())
+    .ok_or(TotpEnrollmentError::InvalidCode)?;
+
+  let recovery_codes = generate_recovery_codes();
+
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  sqlx::query!(
+    r#"
+    UPDATE users
+    SET totp_secret = totp_pending_secret, totp_pending_secret = NULL, totp_last_used_step = $2
+    WHERE user_id = $1
+    "#,
+    user_id,
+    step,
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to activate the TOTP secret.")?;
+  sqlx::query!(
+    r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+    user_id,
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to delete previous recovery codes.")?;
+  for code in &recovery_codes {
+    sqlx::query!(
+      r#"INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)"#,
+      user_id,
+      hash_recovery_code(code),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store a recovery code.")?;
+  }
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to confirm TOTP enrollment.")?;
+
+  Ok(recovery_codes)
+}
+
+/// Checks the one-time code of users who are enrolled in two-factor authentication.
+/// Users who are not enrolled have nothing to check, and are let through.
+/// The code may be either a TOTP code, which can only be used once, or an unused recovery code.
+#[tracing::instrument(name = "Verify second factor", skip(one_time_code, pool))]
+pub async fn verify_second_factor(
+  user_id: Uuid,
+  one_time_code: Option<String>,
+  pool: &PgPool,
+) -> Result<(), AuthError> {
+  let secret = match get_totp_secret(user_id, pool).await? {
+    Some(secret) => TotpSecret::parse(&secret)?,
+    None => return Ok(()),
+  };
+
+  let code = one_time_code.ok_or_else(|| {
+    AuthError::InvalidCredentials(anyhow::anyhow!("A one-time code is required."))
+  })?;
+
+  let is_valid = match secret.matching_step(code.trim(), Utc::now()) {
+    Some(step) => record_used_step(user_id, step, pool).await?,
+    None => consume_recovery_code(user_id, &code, pool).await?,
+  };
+
+  if is_valid {
+    Ok(())
+  } else {
+    Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+      "Invalid or already used one-time code."
+    )))
+  }
+}
+
+#[tracing::instrument(name = "Get TOTP secret", skip(pool))]
+async fn get_totp_secret(user_id: Uuid, pool: &PgPool) -> Result<Option<String>, anyhow::Error> {
+  Ok(
+    sqlx::query!(
+      r#"SELECT totp_secret FROM users WHERE user_id = $1"#,
+      user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the TOTP secret.")?
+    .totp_secret,
+  )
+}
+
+/// Records the step of a successfully used code, so that the same code cannot be replayed.
+/// Returns false if a code from this step (or a later one) was already used.
+#[tracing::instrument(name = "Record used TOTP step", skip(pool))]
+async fn record_used_step(user_id: Uuid, step: i64, pool: &PgPool) -> Result<bool, anyhow::Error> {
+  let result = sqlx::query!(
+    r#"
+    UPDATE users SET totp_last_used_step = $2
+    WHERE user_id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+    "#,
+    user_id,
+    step,
+  )
+  .execute(pool)
+  .await
+  .context("Failed to record the used TOTP step.")?;
+  Ok(result.rows_affected() == 1)
+}
+
+/// Marks the recovery code as used, returning false if it doesn't exist or was already used.
+#[tracing::instrument(name = "Consume recovery code", skip(code, pool))]
+async fn consume_recovery_code(
+  user_id: Uuid,
+  code: &str,
+  pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+  let result = sqlx::query!(
+    r#"
+    UPDATE user_recovery_codes SET used_at = $3
+    WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+    "#,
+    user_id,
+    hash_recovery_code(code),
+    Utc::now(),
+  )
+  .execute(pool)
+  .await
+  .context("Failed to consume a recovery code.")?;
+  Ok(result.rows_affected() == 1)
+}
+
+/// Recovery codes are formatted as `xxxxx-xxxxx` to make them easier to copy down.
+fn generate_recovery_codes() -> Vec<String> {
+  let mut rng = thread_rng();
+  let mut half = || -> String {
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+      .map(|c| char::from(c).to_ascii_lowercase())
+      .take(RECOVERY_CODE_HALF_LENGTH)
+      .collect()
+  };
+  (0..RECOVERY_CODE_COUNT)
+    .map(|_| format!("{}-{}", half(), half()))
+    .collect()
+}
+
+/// Recovery codes are random and long enough that a fast hash is sufficient, unlike passwords.
+/// Formatting is ignored so that `ABCDE-FGHIJ`, `abcdefghij` and `abcde-fghij` are all the same code.
+fn hash_recovery_code(code: &str) -> String {
+  let normalized: String = code
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric())
+    .map(|c| c.to_ascii_lowercase())
+    .collect();
+  format!("{:x}", Sha3_256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{TimeZone, Utc};
+  use claim::{assert_none, assert_some_eq};
+
+  use super::{generate_recovery_codes, hash_recovery_code, time_step, TotpSecret};
+
+  /// The secret used by the test vectors in RFC 6238, Appendix B.
+  fn rfc_secret() -> TotpSecret {
+    TotpSecret(b"12345678901234567890".to_vec())
+  }
+
+  #[test]
+  fn codes_match_the_rfc_test_vectors() {
+    // The RFC lists 8 digit codes, of which we use the last 6.
+    let test_cases = [
+      (59, "287082"),
+      (1111111109, "081804"),
+      (1111111111, "050471"),
+      (1234567890, "005924"),
+      (2000000000, "279037"),
+    ];
+
+    for (timestamp, expected) in test_cases {
+      let step = time_step(Utc.timestamp(timestamp, 0));
+      assert_eq!(rfc_secret().code_at(step), expected, "at {}", timestamp);
+    }
+  }
+
+  #[test]
+  fn codes_from_adjacent_steps_are_accepted() {
+    let time = Utc.timestamp(1234567890, 0);
+    let step = time_step(time);
+    let secret = rfc_secret();
+
+    assert_some_eq!(
+      secret.matching_step(&secret.code_at(step - 1), time),
+      step - 1
+    );
+    assert_some_eq!(
+      secret.matching_step(&secret.code_at(step + 1), time),
+      step + 1
+    );
+    assert_none!(secret.matching_step(&secret.code_at(step + 2), time));
+  }
+
+  #[test]
+  fn secrets_survive_encoding() {
+    let secret = TotpSecret::generate();
+    let decoded = TotpSecret::parse(&secret.encode()).unwrap();
+    assert_eq!(secret.0, decoded.0);
+  }
+
+  #[test]
+  fn provisioning_uri_contains_the_secret_and_issuer() {
+    let secret = rfc_secret();
+    let uri = secret.provisioning_uri("phil nadon");
+    assert!(uri.starts_with("otpauth://totp/Newsletter:phil%20nadon?"));
+    assert!(uri.contains(&format!("secret={}", secret.encode())));
+    assert!(uri.contains("issuer=Newsletter"));
+  }
+
+  #[test]
+  fn recovery_code_formatting_is_ignored_when_hashing() {
+    let code = &generate_recovery_codes()[0];
+    assert_eq!(
+      hash_recovery_code(code),
+      hash_recovery_code(&code.to_uppercase().replace('-', ""))
+    );
+  }
+}
@@ -11,6 +11,9 @@ pub struct Settings {
   pub database: DatabaseSettings,
   pub application: ApplicationSettings,
   pub email_client: EmailClientSettings,
+  pub authentication: AuthenticationSettings,
+  pub sanitization: SanitizationSettings,
+  pub signup_protection: SignupProtectionSettings,
 }
 
 #[derive(Deserialize, Debug)]
@@ -30,6 +33,19 @@ pub struct ApplicationSettings {
   #[serde(deserialize_with = "deserialize_number_from_string")]
   pub port: u16,
   pub base_url: String,
+  /// Templates in this directory replace the built-in email templates of the same name,
+  /// and those in its `pages` directory the built-in page templates.
+  pub templates_directory: Option<String>,
+  /// Key of the signatures on tracking links and subscribe forms, so that they can't be forged.
+  pub hmac_secret: String,
+  /// Origins of the other sites which may subscribe people from a browser, e.g. with the
+  /// embedded subscribe widget, like `https://www.example.com`. `*` allows any site.
+  #[serde(default)]
+  pub allowed_origins: Vec<String>,
+  /// Addresses or networks of the reverse proxies we are deployed behind, e.g. `10.0.0.0/8`.
+  /// The client address is only read from `X-Forwarded-For` when the request comes from one.
+  #[serde(default)]
+  pub trusted_proxies: Vec<String>,
 }
 
 impl DatabaseSettings {
@@ -77,6 +93,74 @@ impl EmailClientSettings {
   }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct AuthenticationSettings {
+  pub login_throttle: LoginThrottleSettings,
+  pub password_hashing: PasswordHashingSettings,
+}
+
+/// Argon2id cost parameters. Raising them upgrades existing hashes as users log in.
+#[derive(Deserialize, Debug)]
+pub struct PasswordHashingSettings {
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub memory_kib: u32,
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub iterations: u32,
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub parallelism: u32,
+}
+
+/// Limits on failed logins, to slow down password guessing.
+#[derive(Deserialize, Debug)]
+pub struct LoginThrottleSettings {
+  /// Failures allowed for a single username before it is locked out.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub username_free_attempts: u32,
+  /// Failures allowed from a single IP before it is locked out, across all usernames.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub ip_free_attempts: u32,
+  /// Lockout after the first failure past the free attempts, doubling with every further failure.
+  pub base_lockout: std::time::Duration,
+  pub max_lockout: std::time::Duration,
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub max_concurrent_verifications: usize,
+}
+
+/// Checks telling bots apart from people on the subscribe endpoint, since every subscription
+/// sends a confirmation email. The honeypot field is checked even when they are disabled.
+#[derive(Deserialize, Debug)]
+pub struct SignupProtectionSettings {
+  pub enabled: bool,
+  /// How long after being served a form can be submitted. People take a few seconds to fill it in.
+  pub min_submit_time: std::time::Duration,
+  /// How long after being served a form can still be submitted.
+  pub max_form_age: std::time::Duration,
+  /// Subscriptions accepted from a single IP in an hour, 0 for no limit.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub ip_signups_per_hour: u32,
+  /// Subscriptions accepted for addresses of a single domain in an hour, across all IPs.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub domain_signups_per_hour: u32,
+  /// Domains exempt from the per domain limit, like the big webmail providers, which too many
+  /// people share for bots to be allowed to use up their limit.
+  #[serde(default)]
+  pub unlimited_domains: Vec<String>,
+  /// Leading zero bits the browser must find in a hash before submitting the form.
+  /// Every bit doubles the work; 0 turns the challenge off.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub proof_of_work_difficulty: u8,
+}
+
+/// What the html of published issues may contain. Anything else is removed, or rejected when strict.
+#[derive(Deserialize, Debug)]
+pub struct SanitizationSettings {
+  pub strict: bool,
+  pub allowed_tags: Vec<String>,
+  /// Allowed on any allowed tag.
+  pub allowed_attributes: Vec<String>,
+  pub allowed_url_schemes: Vec<String>,
+}
+
 /// Merges the base.yaml with the environment-specific config file, and then merges in the environment variables.
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
   let mut settings = config::Config::default();
@@ -0,0 +1,98 @@
+use std::future::Future;
+
+use actix_http::header::{self, HeaderMap, HeaderValue};
+use actix_web::{
+  dev::{Service, ServiceRequest, ServiceResponse},
+  web::Data,
+  Error,
+};
+
+/// Which other sites may call the API from a browser, like the subscribe widget does
+/// once embedded on them. Browsers only let a page read the response of a request to
+/// another origin when the response says that origin is allowed.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+  allowed_origins: Vec<String>,
+}
+
+impl CorsPolicy {
+  /// Origins are scheme, host and port, e.g. `https://www.example.com`. `*` allows any origin.
+  pub fn new(allowed_origins: &[String]) -> Self {
+    Self {
+      allowed_origins: allowed_origins
+        .iter()
+        .map(|origin| origin.trim().trim_end_matches('/').to_lowercase())
+        .collect(),
+    }
+  }
+
+  /// The origin of the request, when it comes from a page of another site which is allowed.
+  pub fn allowed_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+    let origin = headers.get(header::ORIGIN)?;
+    let is_allowed = self.allowed_origins.iter().any(|allowed| {
+      allowed == "*"
+        || origin
+          .to_str()
+          .is_ok_and(|origin| origin.eq_ignore_ascii_case(allowed))
+    });
+    is_allowed.then(|| origin.clone())
+  }
+}
+
+/// Middleware letting the allowed origins read the responses of a resource, errors included.
+/// Responses vary with the origin, so caches are told not to share them between sites.
+pub fn allow_origins<S>(
+  request: ServiceRequest,
+  service: &S,
+) -> impl Future<Output = Result<ServiceResponse, Error>>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>,
+{
+  let origin = request
+    .app_data::<Data<CorsPolicy>>()
+    .and_then(|policy| policy.allowed_origin(request.headers()));
+  let response = service.call(request);
+  async move {
+    let mut response = response.await?;
+    let headers = response.headers_mut();
+    headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    if let Some(origin) = origin {
+      headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+    Ok(response)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CorsPolicy;
+  use actix_web::test::TestRequest;
+
+  fn allowed(policy: &CorsPolicy, origin: &str) -> bool {
+    let request = TestRequest::default()
+      .insert_header(("Origin", origin))
+      .to_http_request();
+    policy.allowed_origin(request.headers()).is_some()
+  }
+
+  #[test]
+  fn only_the_listed_origins_are_allowed() {
+    let policy = CorsPolicy::new(&["https://www.Example.com/".to_string()]);
+    assert!(allowed(&policy, "https://www.example.com"));
+    assert!(!allowed(&policy, "http://www.example.com"));
+    assert!(!allowed(&policy, "https://www.example.com.evil.io"));
+    assert!(policy
+      .allowed_origin(TestRequest::default().to_http_request().headers())
+      .is_none());
+  }
+
+  #[test]
+  fn a_wildcard_allows_any_origin() {
+    let policy = CorsPolicy::new(&["*".to_string()]);
+    assert!(allowed(&policy, "https://anywhere.example.org"));
+    assert!(!allowed(
+      &CorsPolicy::new(&[]),
+      "https://anywhere.example.org"
+    ));
+  }
+}
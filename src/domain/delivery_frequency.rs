@@ -0,0 +1,73 @@
+use chrono::Duration;
+
+/// How often a subscriber wants to receive issues.
+/// Issues published while a subscriber is waiting for their next delivery are skipped, not batched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryFrequency {
+  EveryIssue,
+  Weekly,
+  Monthly,
+}
+
+impl DeliveryFrequency {
+  pub const ALL: [DeliveryFrequency; 3] = [
+    DeliveryFrequency::EveryIssue,
+    DeliveryFrequency::Weekly,
+    DeliveryFrequency::Monthly,
+  ];
+
+  pub fn parse(s: String) -> Result<Self, String> {
+    Self::ALL
+      .iter()
+      .copied()
+      .find(|frequency| frequency.as_str() == s)
+      .ok_or_else(|| format!("{} is not a valid delivery frequency.", s))
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DeliveryFrequency::EveryIssue => "every_issue",
+      DeliveryFrequency::Weekly => "weekly",
+      DeliveryFrequency::Monthly => "monthly",
+    }
+  }
+
+  /// Shown to subscribers when they pick a frequency.
+  pub fn description(&self) -> &'static str {
+    match self {
+      DeliveryFrequency::EveryIssue => "Every issue",
+      DeliveryFrequency::Weekly => "At most one issue a week",
+      DeliveryFrequency::Monthly => "At most one issue a month",
+    }
+  }
+
+  /// The shortest time between two deliveries.
+  pub fn min_interval(&self) -> Duration {
+    match self {
+      DeliveryFrequency::EveryIssue => Duration::zero(),
+      DeliveryFrequency::Weekly => Duration::weeks(1),
+      DeliveryFrequency::Monthly => Duration::days(30),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::DeliveryFrequency;
+  use claim::{assert_err, assert_ok_eq};
+
+  #[test]
+  fn every_frequency_parses_back_from_its_name() {
+    for frequency in DeliveryFrequency::ALL {
+      assert_ok_eq!(
+        DeliveryFrequency::parse(frequency.as_str().to_string()),
+        frequency
+      );
+    }
+  }
+
+  #[test]
+  fn unknown_frequencies_are_rejected() {
+    assert_err!(DeliveryFrequency::parse("daily".to_string()));
+  }
+}
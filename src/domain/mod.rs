@@ -1,7 +1,15 @@
+mod delivery_frequency;
 mod new_subscriber;
+mod newsletter_slug;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
 
+pub use delivery_frequency::DeliveryFrequency;
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_slug::NewsletterSlug;
+pub use subscriber_attributes::SubscriberAttributes;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
@@ -1,7 +1,8 @@
-use super::{SubscriberEmail, SubscriberName};
+use super::{SubscriberAttributes, SubscriberEmail, SubscriberName};
 
 #[derive(Debug)]
 pub struct NewSubscriber {
   pub email: SubscriberEmail,
   pub name: SubscriberName,
+  pub attributes: SubscriberAttributes,
 }
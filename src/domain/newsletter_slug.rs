@@ -0,0 +1,73 @@
+/// Identifies a newsletter in URLs and API requests, e.g. `rust-weekly`.
+#[derive(Debug, Clone)]
+pub struct NewsletterSlug(String);
+
+const MAX_SLUG_LENGTH: usize = 64;
+
+impl NewsletterSlug {
+  /// Slugs are made of lowercase ASCII letters, digits and hyphens, so that they are safe to put in a URL as-is.
+  pub fn parse(s: String) -> Result<Self, String> {
+    let is_valid = !s.is_empty()
+      && s.len() <= MAX_SLUG_LENGTH
+      && !s.starts_with('-')
+      && !s.ends_with('-')
+      && s
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    if is_valid {
+      Ok(Self(s))
+    } else {
+      Err(format!(
+        "{} is not a valid newsletter identifier. \
+        Use up to {} lowercase letters, digits and hyphens",
+        s, MAX_SLUG_LENGTH,
+      ))
+    }
+  }
+}
+
+impl AsRef<str> for NewsletterSlug {
+  fn as_ref(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::NewsletterSlug;
+  use claim::{assert_err, assert_ok};
+
+  #[test]
+  fn a_valid_slug_is_parsed_successfully() {
+    assert_ok!(NewsletterSlug::parse("rust-weekly-2".to_string()));
+  }
+
+  #[test]
+  fn empty_string_is_rejected() {
+    assert_err!(NewsletterSlug::parse("".to_string()));
+  }
+
+  #[test]
+  fn a_64_character_slug_is_valid() {
+    assert_ok!(NewsletterSlug::parse("a".repeat(64)));
+  }
+
+  #[test]
+  fn a_slug_longer_than_64_characters_is_rejected() {
+    assert_err!(NewsletterSlug::parse("a".repeat(65)));
+  }
+
+  #[test]
+  fn slugs_with_uppercase_or_special_characters_are_rejected() {
+    for slug in ["Rust", "rust weekly", "rust_weekly", "rust/weekly", "ñews"] {
+      assert_err!(NewsletterSlug::parse(slug.to_string()));
+    }
+  }
+
+  #[test]
+  fn slugs_starting_or_ending_with_a_hyphen_are_rejected() {
+    assert_err!(NewsletterSlug::parse("-rust".to_string()));
+    assert_err!(NewsletterSlug::parse("rust-".to_string()));
+  }
+}
@@ -0,0 +1,128 @@
+use serde_json::{Map, Value};
+
+/// Free-form details about a subscriber, e.g. their company, plan or city,
+/// which newsletters can refer to through merge tags such as `{{ attributes.city }}`.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+const MAX_ATTRIBUTES: usize = 50;
+const MAX_KEY_LENGTH: usize = 64;
+const MAX_VALUE_LENGTH: usize = 1024;
+
+impl SubscriberAttributes {
+  /// Keys are made of lowercase ASCII letters, digits and underscores, so that they can be used in merge tags.
+  /// Values are strings, numbers or booleans. Every problem is reported at once.
+  pub fn parse(value: Value) -> Result<Self, Vec<String>> {
+    let attributes = match value {
+      Value::Object(attributes) => attributes,
+      _ => return Err(vec!["attributes must be an object!".to_string()]),
+    };
+
+    let mut errors = Vec::new();
+    if attributes.len() > MAX_ATTRIBUTES {
+      errors.push(format!(
+        "there cannot be more than {} attributes!",
+        MAX_ATTRIBUTES
+      ));
+    }
+    for (key, value) in &attributes {
+      let is_valid_key = !key.is_empty()
+        && key.len() <= MAX_KEY_LENGTH
+        && key
+          .chars()
+          .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+      if !is_valid_key {
+        errors.push(format!(
+          "{} is not a valid attribute name. \
+          Use up to {} lowercase letters, digits and underscores",
+          key, MAX_KEY_LENGTH,
+        ));
+      }
+      match value {
+        Value::String(s) if s.len() > MAX_VALUE_LENGTH => errors.push(format!(
+          "attribute {} cannot be more than {} bytes!",
+          key, MAX_VALUE_LENGTH
+        )),
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => {}
+        _ => errors.push(format!(
+          "attribute {} must be a string, a number or a boolean!",
+          key
+        )),
+      }
+    }
+
+    if errors.is_empty() {
+      Ok(Self(attributes))
+    } else {
+      Err(errors)
+    }
+  }
+
+  /// Parses attributes submitted through a form, where every value is a string.
+  pub fn from_strings(
+    attributes: impl IntoIterator<Item = (String, String)>,
+  ) -> Result<Self, Vec<String>> {
+    Self::parse(Value::Object(
+      attributes
+        .into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect(),
+    ))
+  }
+
+  pub fn into_value(self) -> Value {
+    Value::Object(self.0)
+  }
+}
+
+impl AsRef<Map<String, Value>> for SubscriberAttributes {
+  fn as_ref(&self) -> &Map<String, Value> {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SubscriberAttributes;
+  use claim::{assert_err, assert_ok};
+  use serde_json::json;
+
+  #[test]
+  fn scalar_values_are_accepted() {
+    assert_ok!(SubscriberAttributes::parse(
+      json!({ "city": "Montreal", "seats": 3, "trial": false })
+    ));
+  }
+
+  #[test]
+  fn non_objects_are_rejected() {
+    assert_err!(SubscriberAttributes::parse(json!(["city"])));
+  }
+
+  #[test]
+  fn nested_values_are_rejected() {
+    assert_err!(SubscriberAttributes::parse(
+      json!({ "address": { "city": "Montreal" } })
+    ));
+    assert_err!(SubscriberAttributes::parse(json!({ "city": null })));
+  }
+
+  #[test]
+  fn keys_which_cannot_be_used_in_merge_tags_are_rejected() {
+    for key in ["", "City", "home city", "city.name", "{{city}}"] {
+      assert_err!(SubscriberAttributes::parse(json!({ key: "Montreal" })));
+    }
+  }
+
+  #[test]
+  fn too_many_attributes_are_rejected() {
+    let attributes = (0..51).map(|i| (format!("a{}", i), i.to_string()));
+    assert_err!(SubscriberAttributes::from_strings(attributes));
+  }
+
+  #[test]
+  fn all_errors_are_reported_at_once() {
+    let errors = SubscriberAttributes::parse(json!({ "City": "Montreal", "tags": [] }));
+    assert_eq!(errors.err().unwrap().len(), 2);
+  }
+}
@@ -13,6 +13,11 @@ impl SubscriberEmail {
       Err(format!("{} is not a valid subscriber email", s))
     }
   }
+
+  /// The part after the `@`, lowercased.
+  pub fn domain(&self) -> String {
+    self.0.rsplit('@').next().unwrap_or_default().to_lowercase()
+  }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -0,0 +1,61 @@
+/// A label put on subscribers so that issues can be sent to some of them only, e.g. `beta`.
+#[derive(Debug, Clone)]
+pub struct SubscriberTag(String);
+
+const MAX_TAG_LENGTH: usize = 64;
+
+impl SubscriberTag {
+  /// Tags are made of lowercase ASCII letters, digits, hyphens and underscores, so that they are safe to put in a URL as-is.
+  pub fn parse(s: String) -> Result<Self, String> {
+    let is_valid = !s.is_empty()
+      && s.len() <= MAX_TAG_LENGTH
+      && s
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+    if is_valid {
+      Ok(Self(s))
+    } else {
+      Err(format!(
+        "{} is not a valid tag. \
+        Use up to {} lowercase letters, digits, hyphens and underscores",
+        s, MAX_TAG_LENGTH,
+      ))
+    }
+  }
+}
+
+impl AsRef<str> for SubscriberTag {
+  fn as_ref(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SubscriberTag;
+  use claim::{assert_err, assert_ok};
+
+  #[test]
+  fn valid_tags_are_accepted() {
+    for tag in ["beta", "early-adopter", "plan_2022"] {
+      assert_ok!(SubscriberTag::parse(tag.to_string()));
+    }
+  }
+
+  #[test]
+  fn empty_tags_are_rejected() {
+    assert_err!(SubscriberTag::parse("".to_string()));
+  }
+
+  #[test]
+  fn tags_longer_than_64_characters_are_rejected() {
+    assert_err!(SubscriberTag::parse("a".repeat(65)));
+  }
+
+  #[test]
+  fn tags_with_uppercase_letters_or_spaces_are_rejected() {
+    assert_err!(SubscriberTag::parse("Beta".to_string()));
+    assert_err!(SubscriberTag::parse("beta testers".to_string()));
+  }
+}
@@ -1,4 +1,4 @@
-use crate::{configuration::EmailClientSettings, domain::SubscriberEmail};
+use crate::{configuration::EmailClientSettings, domain::SubscriberEmail, html};
 use reqwest::{Client, Url};
 use serde::Serialize;
 
@@ -34,11 +34,55 @@ impl EmailClient {
     subject: &str,
     html_body: &str,
     text_body: &str,
+  ) -> Result<(), reqwest::Error> {
+    self
+      .send(
+        self.sender.as_ref().to_string(),
+        recipient,
+        subject,
+        html_body,
+        text_body,
+      )
+      .await
+  }
+
+  /// Sends an email from the configured sender address, shown under the given display name.
+  /// The name is expected to be a valid `SubscriberName`, so it needs no escaping.
+  pub async fn send_email_as(
+    &self,
+    sender_name: &str,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
+  ) -> Result<(), reqwest::Error> {
+    let from = format!("\"{}\" <{}>", sender_name, self.sender.as_ref());
+    self
+      .send(from, recipient, subject, html_body, text_body)
+      .await
+  }
+
+  async fn send(
+    &self,
+    from: String,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
   ) -> Result<(), reqwest::Error> {
     let url = Url::parse(&self.base_url).unwrap().join("email").unwrap();
 
+    // Every email gets a text part, generated from the HTML when none is given.
+    let generated_text_body;
+    let text_body = if text_body.trim().is_empty() {
+      generated_text_body = html::to_text(html_body);
+      &generated_text_body
+    } else {
+      text_body
+    };
+
     let request_body = SendEmailRequest {
-      from: &self.sender,
+      from: &from,
       to: recipient,
       subject,
       html_body,
@@ -75,7 +119,7 @@ impl TryFrom<EmailClientSettings> for EmailClient {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
-  from: &'a SubscriberEmail,
+  from: &'a str,
   to: &'a SubscriberEmail,
   subject: &'a str,
   html_body: &'a str,
@@ -165,6 +209,57 @@ mod tests {
       .await;
   }
 
+  #[tokio::test]
+  async fn send_email_as_includes_the_sender_name() {
+    let mock_server = MockServer::start().await;
+    let sender = email();
+    let email_client = EmailClient::new(
+      mock_server.uri(),
+      sender.clone(),
+      Faker.fake(),
+      std::time::Duration::from_secs(10),
+    );
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(200))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email_as("Rust Weekly", &email(), &subject(), &content(), &content())
+      .await;
+
+    assert_ok!(outcome);
+    let request = &mock_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(
+      body["From"],
+      format!("\"Rust Weekly\" <{}>", sender.as_ref())
+    );
+  }
+
+  #[tokio::test]
+  async fn empty_text_bodies_are_generated_from_the_html() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(200))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email(&email(), &subject(), "<p>Hello <b>there</b>!</p>", " ")
+      .await;
+
+    assert_ok!(outcome);
+    let request = &mock_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["TextBody"], "Hello there!");
+  }
+
   #[tokio::test]
   async fn send_email_succeeds_if_the_server_returns_200() {
     let mock_server = MockServer::start().await;
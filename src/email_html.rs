@@ -0,0 +1,227 @@
+use css_inline::CSSInliner;
+use reqwest::Url;
+
+use crate::html;
+
+/// Gmail cuts emails larger than this off behind a "View entire message" link.
+pub const GMAIL_CLIPPING_THRESHOLD: usize = 102 * 1024;
+
+/// An HTML body ready to be sent, along with what might still go wrong with it.
+#[derive(Debug)]
+pub struct PreparedHtml {
+  pub html: String,
+  pub warnings: Vec<String>,
+}
+
+/// Prepares an HTML body for email clients, which support much less than browsers do:
+/// - CSS from `<style>` blocks is inlined into style attributes, since many clients strip the blocks.
+///   The blocks are kept for the rules which can't be inlined, such as media queries.
+/// - Relative links and images are made absolute, against the base URL.
+pub fn prepare(html: &str, base_url: &Url) -> Result<PreparedHtml, anyhow::Error> {
+  let inlined = CSSInliner::options()
+    .keep_style_tags(true)
+    .load_remote_stylesheets(false)
+    .build()
+    .inline(html)?;
+  let html = rewrite_relative_urls(&inlined, base_url);
+
+  let mut warnings = Vec::new();
+  if html.len() > GMAIL_CLIPPING_THRESHOLD {
+    warnings.push(format!(
+      "The HTML body is {}KB, so Gmail will clip it after {}KB.",
+      html.len() / 1024,
+      GMAIL_CLIPPING_THRESHOLD / 1024
+    ));
+  }
+  Ok(PreparedHtml { html, warnings })
+}
+
+/// Inserts the preheader, the summary clients show next to the subject, as the first
+/// thing in the body, hidden from view.
+pub fn add_preheader(html: &str, preheader: &str) -> String {
+  let hidden = format!(
+    "<div style=\"display:none;max-height:0;overflow:hidden;mso-hide:all\">{}</div>",
+    html::escape(preheader)
+  );
+  let insert_at = html
+    .find("<body")
+    .and_then(|start| html[start..].find('>').map(|end| start + end + 1))
+    .unwrap_or(0);
+  format!("{}{}{}", &html[..insert_at], hidden, &html[insert_at..])
+}
+
+/// Appends an invisible image at the end of the body, whose loading tells that the email was opened.
+pub fn add_tracking_pixel(html: &str, pixel_url: &str) -> String {
+  let pixel = format!(
+    "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display:block;border:0\">",
+    html::escape(pixel_url)
+  );
+  let insert_at = html.rfind("</body>").unwrap_or(html.len());
+  format!("{}{}{}", &html[..insert_at], pixel, &html[insert_at..])
+}
+
+/// Rewrites every link through `click_url`, which is given the link's URL and returns the URL
+/// to use instead, or `None` to leave it alone.
+pub fn track_clicks(html: &str, click_url: impl Fn(&str) -> Option<String>) -> String {
+  rewrite_url_attributes(html, &["href"], |value| {
+    click_url(&html::unescape(value)).map(|url| html::escape(&url))
+  })
+}
+
+fn rewrite_relative_urls(html: &str, base_url: &Url) -> String {
+  rewrite_url_attributes(html, &["href", "src"], |value| match base_url.join(value) {
+    Ok(url) if is_relative(value) => Some(url.into()),
+    _ => None,
+  })
+}
+
+/// Rewrites the values of the given attributes in serialized HTML, which always quotes
+/// attribute values with `"`. Values for which `rewrite` returns `None` are kept.
+fn rewrite_url_attributes(
+  html: &str,
+  attributes: &[&str],
+  rewrite: impl Fn(&str) -> Option<String>,
+) -> String {
+  let mut rewritten = String::with_capacity(html.len());
+  let mut rest = html;
+  while let Some(start) = find_url_attribute(rest, attributes) {
+    let (before, attribute) = rest.split_at(start);
+    rewritten.push_str(before);
+    let value_start = attribute.find('"').unwrap() + 1;
+    let value_end = value_start + attribute[value_start..].find('"').unwrap_or(0);
+    let value = &attribute[value_start..value_end];
+    rewritten.push_str(&attribute[..value_start]);
+    match rewrite(value) {
+      Some(url) => rewritten.push_str(&url),
+      None => rewritten.push_str(value),
+    }
+    rest = &attribute[value_end..];
+  }
+  rewritten.push_str(rest);
+  rewritten
+}
+
+/// Where the next `name="` inside a tag starts, for any of the attribute names.
+fn find_url_attribute(html: &str, attributes: &[&str]) -> Option<usize> {
+  let mut in_tag = false;
+  let mut in_value = false;
+  for (i, c) in html.char_indices() {
+    match c {
+      '"' if in_tag => in_value = !in_value,
+      '<' if !in_value => in_tag = true,
+      '>' if !in_value => in_tag = false,
+      c if in_tag && !in_value && c.is_ascii_whitespace() => {
+        let attribute = &html[i + 1..];
+        let is_url_attribute = attributes.iter().any(|name| {
+          attribute
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with("=\""))
+        });
+        if is_url_attribute {
+          return Some(i + 1);
+        }
+      }
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Whether the URL depends on the page it is on, unlike `https://...`, `mailto:...` or `#top`.
+fn is_relative(url: &str) -> bool {
+  let has_scheme = url.split_once(':').is_some_and(|(scheme, _)| {
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+      && scheme
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+  });
+  !(url.is_empty() || has_scheme || url.starts_with("//") || url.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{add_preheader, add_tracking_pixel, prepare, track_clicks, GMAIL_CLIPPING_THRESHOLD};
+  use reqwest::Url;
+
+  fn base_url() -> Url {
+    Url::parse("https://news.example.com").unwrap()
+  }
+
+  #[test]
+  fn css_is_inlined() {
+    let prepared = prepare(
+      "<html><head><style>p { color: red; }</style></head><body><p>Hi</p></body></html>",
+      &base_url(),
+    )
+    .unwrap();
+    assert!(prepared.html.contains(r#"<p style="color: red;">Hi</p>"#));
+  }
+
+  #[test]
+  fn relative_urls_are_made_absolute() {
+    let prepared = prepare(
+      r##"<a href="/archive">Archive</a> <img src="logo.png"> <a href="https://example.com/x">x</a>
+      <a href="mailto:hi@example.com">Mail</a> <a href="#top">Top</a>"##,
+      &base_url(),
+    )
+    .unwrap();
+    assert!(prepared
+      .html
+      .contains(r#"href="https://news.example.com/archive""#));
+    assert!(prepared
+      .html
+      .contains(r#"src="https://news.example.com/logo.png""#));
+    assert!(prepared.html.contains(r#"href="https://example.com/x""#));
+    assert!(prepared.html.contains(r#"href="mailto:hi@example.com""#));
+    assert!(prepared.html.contains(r##"href="#top""##));
+  }
+
+  #[test]
+  fn the_preheader_is_the_first_thing_in_the_body() {
+    let html = add_preheader(
+      "<html><body class=\"x\"><p>Hi</p></body></html>",
+      "All the <news>",
+    );
+    let body = html.split("<body class=\"x\">").nth(1).unwrap();
+    assert!(body.starts_with("<div style=\"display:none;"));
+    assert!(body.contains("All the &lt;news&gt;</div><p>Hi</p>"));
+  }
+
+  #[test]
+  fn the_tracking_pixel_is_the_last_thing_in_the_body() {
+    let html = add_tracking_pixel(
+      "<html><body><p>Hi</p></body></html>",
+      "https://news.example.com/tracking/open?issue=1&subscriber=2",
+    );
+    assert!(html.ends_with(
+      "<p>Hi</p><img src=\"https://news.example.com/tracking/open?issue=1&amp;subscriber=2\" \
+      width=\"1\" height=\"1\" alt=\"\" style=\"display:block;border:0\"></body></html>"
+    ));
+  }
+
+  #[test]
+  fn links_are_rewritten_for_click_tracking() {
+    let html = track_clicks(
+      r#"<a href="https://example.com/?a=1&amp;b=2">x</a> <a href="mailto:hi@example.com">y</a> <img src="https://example.com/a.png">"#,
+      |url| {
+        url
+          .starts_with("https:")
+          .then(|| format!("https://t.example.com/?url={}&s=1", url))
+      },
+    );
+    assert_eq!(
+      html,
+      r#"<a href="https://t.example.com/?url=https://example.com/?a=1&amp;b=2&amp;s=1">x</a> <a href="mailto:hi@example.com">y</a> <img src="https://example.com/a.png">"#
+    );
+  }
+
+  #[test]
+  fn large_bodies_are_warned_about() {
+    let small = prepare("<p>Hi</p>", &base_url()).unwrap();
+    assert!(small.warnings.is_empty());
+
+    let large = format!("<p>{}</p>", "a".repeat(GMAIL_CLIPPING_THRESHOLD));
+    let large = prepare(&large, &base_url()).unwrap();
+    assert_eq!(large.warnings.len(), 1);
+  }
+}
@@ -0,0 +1,343 @@
+use anyhow::Context;
+use reqwest::Url;
+use serde::Serialize;
+use std::path::Path;
+use tera::Tera;
+
+use crate::{email_html, html};
+
+/// The templates every email is rendered from, each with an html and a plaintext version.
+/// Templates ending in `.html` escape the values they are given, unless marked `| safe`.
+const BUILT_IN_TEMPLATES: [(&str, &str); 10] = [
+  (
+    "confirmation.html",
+    include_str!("../templates/confirmation.html"),
+  ),
+  (
+    "confirmation.txt",
+    include_str!("../templates/confirmation.txt"),
+  ),
+  (
+    "email_change_confirmation.html",
+    include_str!("../templates/email_change_confirmation.html"),
+  ),
+  (
+    "email_change_confirmation.txt",
+    include_str!("../templates/email_change_confirmation.txt"),
+  ),
+  (
+    "email_change_notice.html",
+    include_str!("../templates/email_change_notice.html"),
+  ),
+  (
+    "email_change_notice.txt",
+    include_str!("../templates/email_change_notice.txt"),
+  ),
+  ("footer.html", include_str!("../templates/footer.html")),
+  ("footer.txt", include_str!("../templates/footer.txt")),
+  (
+    "newsletter.html",
+    include_str!("../templates/newsletter.html"),
+  ),
+  (
+    "newsletter.txt",
+    include_str!("../templates/newsletter.txt"),
+  ),
+];
+
+/// Templates which are included in others, rather than rendered on their own.
+const PARTIALS: [&str; 1] = ["footer"];
+
+/// Both bodies of an email, and anything that might keep it from showing as intended.
+#[derive(Debug)]
+pub struct EmailBody {
+  pub html: String,
+  pub text: String,
+  pub warnings: Vec<String>,
+}
+
+/// Variables of `confirmation.html` and `confirmation.txt`.
+#[derive(Serialize)]
+pub struct ConfirmationEmail<'a> {
+  pub name: &'a str,
+  pub newsletter_name: &'a str,
+  pub confirmation_copy: &'a str,
+  pub confirmation_link: &'a str,
+}
+
+/// Variables of `newsletter.html` and `newsletter.txt`, which include the footer.
+/// The html content is inserted as is, since it is written by the publisher.
+#[derive(Serialize)]
+pub struct NewsletterIssue<'a> {
+  pub title: &'a str,
+  pub newsletter_name: &'a str,
+  pub html_content: &'a str,
+  pub text_content: &'a str,
+  pub preferences_link: &'a str,
+  /// Where the issue can be read in a browser.
+  pub archive_link: &'a str,
+  /// Shown by email clients next to the subject.
+  pub preheader: Option<&'a str>,
+}
+
+/// Variables of `email_change_confirmation.html` and `email_change_confirmation.txt`.
+#[derive(Serialize)]
+pub struct EmailChangeConfirmation<'a> {
+  pub name: &'a str,
+  pub confirmation_link: &'a str,
+}
+
+/// Variables of `email_change_notice.html` and `email_change_notice.txt`.
+#[derive(Serialize)]
+pub struct EmailChangeNotice<'a> {
+  pub new_email: &'a str,
+}
+
+/// The email templates of the deployment: the built-in ones, except for those
+/// replaced by a file of the same name in the templates directory.
+/// Overriding the html version of an email without its text version has the text
+/// generated from the html.
+///
+/// Rendered html goes through `email_html::prepare`, with relative URLs resolved against the base URL.
+#[derive(Debug)]
+pub struct EmailTemplates {
+  tera: Tera,
+  base_url: Url,
+}
+
+impl EmailTemplates {
+  /// Fails if a template doesn't parse, or uses a variable it isn't given,
+  /// so that a broken override is noticed at startup rather than when sending.
+  pub fn new(directory: Option<&Path>, base_url: &str) -> Result<Self, anyhow::Error> {
+    let base_url = Url::parse(base_url).context("The base URL is invalid.")?;
+    if let Some(directory) = directory {
+      anyhow::ensure!(
+        directory.is_dir(),
+        "The templates directory {} does not exist.",
+        directory.display()
+      );
+    }
+    let overrides: Vec<&str> = BUILT_IN_TEMPLATES
+      .iter()
+      .map(|(name, _)| *name)
+      .filter(|name| directory.is_some_and(|d| d.join(name).is_file()))
+      .collect();
+    let mut templates = Vec::with_capacity(BUILT_IN_TEMPLATES.len());
+    for (name, built_in) in BUILT_IN_TEMPLATES {
+      let source = match directory.filter(|_| overrides.contains(&name)) {
+        Some(directory) => {
+          let path = directory.join(name);
+          std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the template {}.", path.display()))?
+        }
+        None if is_outdated_text(name, &overrides) => continue,
+        None => built_in.to_string(),
+      };
+      templates.push((name, source));
+    }
+
+    let mut tera = Tera::default();
+    tera
+      .add_raw_templates(templates)
+      .context("Failed to parse the email templates.")?;
+    let templates = Self { tera, base_url };
+    templates.validate()?;
+    Ok(templates)
+  }
+
+  /// Renders every template with example values, and warns about what could go wrong
+  /// with every email rendered from them.
+  fn validate(&self) -> Result<(), anyhow::Error> {
+    let link = "https://example.com/link";
+    let bodies = [
+      self.confirmation(&ConfirmationEmail {
+        name: "Ursula Le Guin",
+        newsletter_name: "Our newsletter",
+        confirmation_copy: "Welcome to our newsletter!",
+        confirmation_link: link,
+      })?,
+      self.newsletter_issue(&NewsletterIssue {
+        title: "Issue #1",
+        newsletter_name: "Our newsletter",
+        html_content: "<p>Hello!</p>",
+        text_content: "Hello!",
+        preferences_link: link,
+        archive_link: link,
+        preheader: Some("The first issue"),
+      })?,
+      self.email_change_confirmation(&EmailChangeConfirmation {
+        name: "Ursula Le Guin",
+        confirmation_link: link,
+      })?,
+      self.email_change_notice(&EmailChangeNotice {
+        new_email: "ursula@example.com",
+      })?,
+    ];
+    for warning in bodies.iter().flat_map(|body| &body.warnings) {
+      tracing::warn!("An email template is likely to be a problem: {}", warning);
+    }
+    Ok(())
+  }
+
+  pub fn confirmation(&self, email: &ConfirmationEmail) -> Result<EmailBody, anyhow::Error> {
+    self.render("confirmation", email, None)
+  }
+
+  pub fn newsletter_issue(&self, issue: &NewsletterIssue) -> Result<EmailBody, anyhow::Error> {
+    self.render("newsletter", issue, issue.preheader)
+  }
+
+  pub fn email_change_confirmation(
+    &self,
+    email: &EmailChangeConfirmation,
+  ) -> Result<EmailBody, anyhow::Error> {
+    self.render("email_change_confirmation", email, None)
+  }
+
+  pub fn email_change_notice(&self, email: &EmailChangeNotice) -> Result<EmailBody, anyhow::Error> {
+    self.render("email_change_notice", email, None)
+  }
+
+  fn render(
+    &self,
+    name: &str,
+    variables: &impl Serialize,
+    preheader: Option<&str>,
+  ) -> Result<EmailBody, anyhow::Error> {
+    let context = tera::Context::from_serialize(variables)
+      .with_context(|| format!("Failed to prepare the variables of the {} template.", name))?;
+    let render = |extension: &str| {
+      let template = format!("{}.{}", name, extension);
+      self
+        .tera
+        .render(&template, &context)
+        .with_context(|| format!("Failed to render the {} template.", template))
+    };
+    let prepared = email_html::prepare(&render("html")?, &self.base_url)
+      .with_context(|| format!("Failed to prepare the html of the {} template.", name))?;
+    let text = if self
+      .tera
+      .get_template_names()
+      .any(|t| t == format!("{}.txt", name))
+    {
+      render("txt")?
+    } else {
+      html::to_text(&prepared.html)
+    };
+    let html = match preheader.filter(|p| !p.trim().is_empty()) {
+      Some(preheader) => email_html::add_preheader(&prepared.html, preheader),
+      None => prepared.html,
+    };
+    Ok(EmailBody {
+      html,
+      text,
+      warnings: prepared.warnings,
+    })
+  }
+}
+
+/// The built-in text version of an email no longer matches its html once only the html
+/// is overridden, so the text is generated from the html instead.
+/// Partials are left alone, since the emails including them expect both versions.
+fn is_outdated_text(name: &str, overrides: &[&str]) -> bool {
+  match name.strip_suffix(".txt") {
+    Some(email) => {
+      !PARTIALS.contains(&email) && overrides.contains(&format!("{}.html", email).as_str())
+    }
+    None => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{EmailTemplates, NewsletterIssue};
+  use claim::{assert_err, assert_ok};
+  use std::path::PathBuf;
+
+  const BASE_URL: &str = "https://news.example.com";
+
+  /// A fresh directory containing the given templates.
+  fn templates_directory(templates: &[(&str, &str)]) -> PathBuf {
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    for (name, source) in templates {
+      std::fs::write(directory.join(name), source).unwrap();
+    }
+    directory
+  }
+
+  fn issue(templates: &EmailTemplates) -> super::EmailBody {
+    templates
+      .newsletter_issue(&NewsletterIssue {
+        title: "Issue #1",
+        newsletter_name: "Rust & Friends",
+        html_content: "<p>Hello!</p>",
+        text_content: "Hello!",
+        preferences_link: "https://example.com/preferences?token=abc",
+        archive_link: "https://example.com/archive/issue-1",
+        preheader: None,
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn the_built_in_templates_are_valid() {
+    assert_ok!(EmailTemplates::new(None, BASE_URL));
+  }
+
+  #[test]
+  fn issues_are_wrapped_with_the_footer() {
+    let body = issue(&EmailTemplates::new(None, BASE_URL).unwrap());
+    assert!(body.html.contains("<p>Hello!</p>"));
+    assert!(body.html.contains("Rust &amp; Friends"));
+    assert!(body
+      .html
+      .contains("https://example.com/preferences?token=abc"));
+    assert!(body.text.starts_with("Hello!"));
+    assert!(body
+      .text
+      .contains("https://example.com/preferences?token=abc"));
+    assert!(body.html.contains("https://example.com/archive/issue-1"));
+    assert!(body.text.contains("https://example.com/archive/issue-1"));
+  }
+
+  #[test]
+  fn templates_can_be_overridden() {
+    let directory = templates_directory(&[("footer.txt", "Bye from {{ newsletter_name }}")]);
+    let body = issue(&EmailTemplates::new(Some(&directory), BASE_URL).unwrap());
+    assert!(body.text.ends_with("Bye from Rust & Friends\n"));
+    // The templates which aren't overridden are the built-in ones.
+    assert!(body.html.contains("Manage your subscription"));
+  }
+
+  #[test]
+  fn text_is_generated_for_html_only_overrides() {
+    let directory = templates_directory(&[(
+      "newsletter.html",
+      r#"<h1>{{ title }}</h1>{{ html_content | safe }}<a href="{{ preferences_link }}">Preferences</a>"#,
+    )]);
+    let body = issue(&EmailTemplates::new(Some(&directory), BASE_URL).unwrap());
+    assert_eq!(
+      body.text,
+      "# Issue #1\n\nHello!\n\n[Preferences][1]\n\n[1]: https://example.com/preferences?token=abc"
+    );
+  }
+
+  #[test]
+  fn invalid_overrides_are_rejected() {
+    for source in ["{{ newsletter_name", "{{ unknown_variable }}"] {
+      let directory = templates_directory(&[("footer.html", source)]);
+      assert_err!(
+        EmailTemplates::new(Some(&directory), BASE_URL),
+        "{}",
+        source
+      );
+    }
+  }
+
+  #[test]
+  fn a_missing_directory_is_rejected() {
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    assert_err!(EmailTemplates::new(Some(&directory), BASE_URL));
+  }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::html;
+
+/// The formats the public issues of a newsletter are published in, at `/feeds/{list}/{file name}`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedFormat {
+  Rss,
+  Atom,
+  Json,
+}
+
+/// A newsletter and its most recent public issues, as feed readers see them.
+pub struct Feed<'a> {
+  pub base_url: &'a str,
+  /// Slug of the newsletter.
+  pub list: &'a str,
+  pub name: &'a str,
+  pub author: &'a str,
+  /// When any issue of the newsletter last changed, or when it was created if it has none.
+  pub updated_at: DateTime<Utc>,
+  /// Most recent first.
+  pub entries: &'a [FeedEntry],
+}
+
+/// A public issue, in a feed.
+pub struct FeedEntry {
+  pub id: Uuid,
+  pub slug: String,
+  pub title: String,
+  pub html_content: String,
+  pub preheader: Option<String>,
+  pub published_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl FeedFormat {
+  pub const ALL: [FeedFormat; 3] = [FeedFormat::Rss, FeedFormat::Atom, FeedFormat::Json];
+
+  pub fn file_name(&self) -> &'static str {
+    match self {
+      FeedFormat::Rss => "rss.xml",
+      FeedFormat::Atom => "atom.xml",
+      FeedFormat::Json => "feed.json",
+    }
+  }
+
+  pub fn from_file_name(file_name: &str) -> Option<Self> {
+    Self::ALL
+      .iter()
+      .find(|format| format.file_name() == file_name)
+      .copied()
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+      FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+      FeedFormat::Json => "application/feed+json; charset=utf-8",
+    }
+  }
+
+  /// The MIME type feed readers look for in `<link rel="alternate">`.
+  pub fn media_type(&self) -> &'static str {
+    match self {
+      FeedFormat::Rss => "application/rss+xml",
+      FeedFormat::Atom => "application/atom+xml",
+      FeedFormat::Json => "application/feed+json",
+    }
+  }
+
+  pub fn render(&self, feed: &Feed) -> String {
+    match self {
+      FeedFormat::Rss => rss(feed),
+      FeedFormat::Atom => atom(feed),
+      FeedFormat::Json => json_feed(feed),
+    }
+  }
+}
+
+impl Feed<'_> {
+  pub fn url(&self, format: FeedFormat) -> String {
+    feed_url(self.base_url, self.list, format)
+  }
+
+  fn home_page_url(&self) -> String {
+    format!("{}/archive", self.base_url)
+  }
+
+  fn entry_url(&self, entry: &FeedEntry) -> String {
+    format!("{}/archive/{}", self.base_url, entry.slug)
+  }
+}
+
+pub fn feed_url(base_url: &str, list: &str, format: FeedFormat) -> String {
+  format!("{}/feeds/{}/{}", base_url, list, format.file_name())
+}
+
+/// Issue ids never change, unlike the URL of the archive, so they make for the ids of entries.
+fn entry_id(entry: &FeedEntry) -> String {
+  format!("urn:uuid:{}", entry.id)
+}
+
+fn rss(feed: &Feed) -> String {
+  let items: String = feed
+    .entries
+    .iter()
+    .map(|entry| {
+      format!(
+        r#"<item>
+<title>{title}</title>
+<link>{url}</link>
+<guid isPermaLink="false">{id}</guid>
+<pubDate>{published_at}</pubDate>
+<description>{content}</description>
+</item>
+"#,
+        title = html::escape(&entry.title),
+        url = html::escape(&feed.entry_url(entry)),
+        id = entry_id(entry),
+        published_at = entry.published_at.to_rfc2822(),
+        content = html::escape(&entry.html_content),
+      )
+    })
+    .collect();
+  format!(
+    r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+<channel>
+<title>{name}</title>
+<link>{home_page_url}</link>
+<description>The issues of {name}.</description>
+<atom:link href="{feed_url}" rel="self" type="{media_type}"/>
+<lastBuildDate>{updated_at}</lastBuildDate>
+{items}</channel>
+</rss>
+"#,
+    name = html::escape(feed.name),
+    home_page_url = html::escape(&feed.home_page_url()),
+    feed_url = html::escape(&feed.url(FeedFormat::Rss)),
+    media_type = FeedFormat::Rss.media_type(),
+    updated_at = feed.updated_at.to_rfc2822(),
+    items = items,
+  )
+}
+
+fn atom(feed: &Feed) -> String {
+  let entries: String = feed
+    .entries
+    .iter()
+    .map(|entry| {
+      let summary = entry
+        .preheader
+        .as_deref()
+        .filter(|preheader| !preheader.trim().is_empty())
+        .map(|preheader| format!("<summary>{}</summary>\n", html::escape(preheader)))
+        .unwrap_or_default();
+      format!(
+        r#"<entry>
+<id>{id}</id>
+<title>{title}</title>
+<link rel="alternate" type="text/html" href="{url}"/>
+<published>{published_at}</published>
+<updated>{updated_at}</updated>
+{summary}<content type="html">{content}</content>
+</entry>
+"#,
+        id = entry_id(entry),
+        title = html::escape(&entry.title),
+        url = html::escape(&feed.entry_url(entry)),
+        published_at = entry.published_at.to_rfc3339(),
+        updated_at = entry.updated_at.to_rfc3339(),
+        summary = summary,
+        content = html::escape(&entry.html_content),
+      )
+    })
+    .collect();
+  format!(
+    r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<id>{feed_url}</id>
+<title>{name}</title>
+<updated>{updated_at}</updated>
+<author><name>{author}</name></author>
+<link rel="self" type="{media_type}" href="{feed_url}"/>
+<link rel="alternate" type="text/html" href="{home_page_url}"/>
+{entries}</feed>
+"#,
+    feed_url = html::escape(&feed.url(FeedFormat::Atom)),
+    name = html::escape(feed.name),
+    updated_at = feed.updated_at.to_rfc3339(),
+    author = html::escape(feed.author),
+    media_type = FeedFormat::Atom.media_type(),
+    home_page_url = html::escape(&feed.home_page_url()),
+    entries = entries,
+  )
+}
+
+/// See https://www.jsonfeed.org/version/1.1/.
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+  version: &'static str,
+  title: &'a str,
+  home_page_url: String,
+  feed_url: String,
+  description: String,
+  authors: [JsonFeedAuthor<'a>; 1],
+  items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAuthor<'a> {
+  name: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem<'a> {
+  id: String,
+  url: String,
+  title: &'a str,
+  content_html: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  summary: Option<&'a str>,
+  date_published: String,
+  date_modified: String,
+}
+
+fn json_feed(feed: &Feed) -> String {
+  let json_feed = JsonFeed {
+    version: "https://jsonfeed.org/version/1.1",
+    title: feed.name,
+    home_page_url: feed.home_page_url(),
+    feed_url: feed.url(FeedFormat::Json),
+    description: format!("The issues of {}.", feed.name),
+    authors: [JsonFeedAuthor { name: feed.author }],
+    items: feed
+      .entries
+      .iter()
+      .map(|entry| JsonFeedItem {
+        id: entry_id(entry),
+        url: feed.entry_url(entry),
+        title: &entry.title,
+        content_html: &entry.html_content,
+        summary: entry
+          .preheader
+          .as_deref()
+          .filter(|preheader| !preheader.trim().is_empty()),
+        date_published: entry.published_at.to_rfc3339(),
+        date_modified: entry.updated_at.to_rfc3339(),
+      })
+      .collect(),
+  };
+  serde_json::to_string_pretty(&json_feed).expect("A feed can always be serialized")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Feed, FeedEntry, FeedFormat};
+  use chrono::{TimeZone, Utc};
+  use uuid::Uuid;
+
+  fn entry() -> FeedEntry {
+    FeedEntry {
+      id: Uuid::parse_str("8b0c7a0e-6f42-4a47-b8a8-9e1c2f0d6a11").unwrap(),
+      slug: "rust-2021".to_string(),
+      title: "Rust 2021 & you".to_string(),
+      html_content: "<p>It's out!</p>".to_string(),
+      preheader: Some("What changes".to_string()),
+      published_at: Utc.ymd(2022, 2, 1).and_hms(9, 30, 0),
+      updated_at: Utc.ymd(2022, 2, 3).and_hms(10, 0, 0),
+    }
+  }
+
+  fn render(format: FeedFormat, entries: &[FeedEntry]) -> String {
+    format.render(&Feed {
+      base_url: "https://news.example.com",
+      list: "rust-weekly",
+      name: "Rust Weekly",
+      author: "Phil Nadon",
+      updated_at: Utc.ymd(2022, 2, 3).and_hms(10, 0, 0),
+      entries,
+    })
+  }
+
+  #[test]
+  fn formats_are_found_by_file_name() {
+    for format in FeedFormat::ALL {
+      assert_eq!(FeedFormat::from_file_name(format.file_name()), Some(format));
+    }
+    assert_eq!(FeedFormat::from_file_name("feed.txt"), None);
+  }
+
+  #[test]
+  fn rss_items_have_guids_and_absolute_links() {
+    let rss = render(FeedFormat::Rss, &[entry()]);
+    assert!(rss.contains(
+      r#"<guid isPermaLink="false">urn:uuid:8b0c7a0e-6f42-4a47-b8a8-9e1c2f0d6a11</guid>"#
+    ));
+    assert!(rss.contains("<link>https://news.example.com/archive/rust-2021</link>"));
+    assert!(rss.contains("<title>Rust 2021 &amp; you</title>"));
+    assert!(rss.contains("<pubDate>Tue, 01 Feb 2022 09:30:00 +0000</pubDate>"));
+    assert!(rss.contains("<description>&lt;p&gt;It&#39;s out!&lt;/p&gt;</description>"));
+  }
+
+  #[test]
+  fn atom_entries_have_ids_and_updated_timestamps() {
+    let atom = render(FeedFormat::Atom, &[entry()]);
+    assert!(atom.contains("<id>https://news.example.com/feeds/rust-weekly/atom.xml</id>"));
+    assert!(atom.contains("<updated>2022-02-03T10:00:00+00:00</updated>"));
+    assert!(atom.contains("<id>urn:uuid:8b0c7a0e-6f42-4a47-b8a8-9e1c2f0d6a11</id>"));
+    assert!(atom.contains("<published>2022-02-01T09:30:00+00:00</published>"));
+    assert!(atom.contains("<summary>What changes</summary>"));
+  }
+
+  #[test]
+  fn json_feeds_follow_version_1_1() {
+    let json: serde_json::Value =
+      serde_json::from_str(&render(FeedFormat::Json, &[entry()])).unwrap();
+    assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+    assert_eq!(
+      json["feed_url"],
+      "https://news.example.com/feeds/rust-weekly/feed.json"
+    );
+    let item = &json["items"][0];
+    assert_eq!(item["id"], "urn:uuid:8b0c7a0e-6f42-4a47-b8a8-9e1c2f0d6a11");
+    assert_eq!(item["url"], "https://news.example.com/archive/rust-2021");
+    assert_eq!(item["content_html"], "<p>It's out!</p>");
+    assert_eq!(item["date_modified"], "2022-02-03T10:00:00+00:00");
+  }
+}
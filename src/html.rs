@@ -0,0 +1,213 @@
+use std::ops::Range;
+
+/// Escapes text so that it can be put in HTML, both as content and inside quoted attribute values.
+pub fn escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&#39;")
+}
+
+/// Reverses `escape`, for attribute values of serialized HTML.
+pub fn unescape(s: &str) -> String {
+  s.replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&amp;", "&")
+}
+
+/// Renders HTML as plain text, for the text part of emails.
+/// Links are numbered like footnotes, with their URLs listed at the end.
+pub fn to_text(html: &str) -> String {
+  html2text::config::plain()
+    .link_footnotes(true)
+    .no_link_wrapping()
+    .string_from_read(html.as_bytes(), TEXT_WIDTH)
+    // Only fails when the HTML is nested too deeply to fit in the width.
+    .unwrap_or_else(|_| html.to_string())
+    .trim_end()
+    .to_string()
+}
+
+/// Lines are wrapped to stay readable in clients which don't wrap them.
+const TEXT_WIDTH: usize = 78;
+
+/// A tag of an HTML document, as found by `tags`.
+pub struct Tag<'a> {
+  /// Lowercased.
+  pub name: String,
+  /// Lowercased names, with the values as they are written.
+  pub attributes: Vec<(String, &'a str)>,
+  pub closing: bool,
+  /// Where the tag is in the document, from its `<` to its `>`.
+  pub span: Range<usize>,
+}
+
+/// The tags of the HTML, in order. Comments and doctypes are skipped.
+/// This is a lenient scan rather than a parse: it never fails, which is enough to report on the HTML.
+pub fn tags(html: &str) -> impl Iterator<Item = Tag<'_>> {
+  let mut position = 0;
+  std::iter::from_fn(move || loop {
+    let start = position + html[position..].find('<')?;
+    let mut rest = &html[start + 1..];
+    if let Some(comment) = rest.strip_prefix("!--") {
+      position = comment
+        .find("-->")
+        .map_or(html.len(), |end| html.len() - comment.len() + end + 3);
+      continue;
+    }
+    let closing = rest.starts_with('/');
+    rest = rest.trim_start_matches('/');
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+      position = start + 1;
+      continue;
+    }
+    let name_end = rest
+      .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+      .unwrap_or(rest.len());
+    let name = rest[..name_end].to_ascii_lowercase();
+    rest = &rest[name_end..];
+    let mut attributes = Vec::new();
+    loop {
+      rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+      if rest.is_empty() || rest.starts_with('>') {
+        break;
+      }
+      let name_end = rest
+        .find(|c: char| c.is_ascii_whitespace() || "=>/".contains(c))
+        .unwrap_or(rest.len())
+        .max(1);
+      let attribute = rest[..name_end].to_ascii_lowercase();
+      rest = rest[name_end..].trim_start();
+      let mut value = "";
+      if let Some(after_equals) = rest.strip_prefix('=') {
+        let after_equals = after_equals.trim_start();
+        let (found, remaining) = match after_equals.chars().next() {
+          Some(quote @ ('"' | '\'')) => {
+            let inner = &after_equals[1..];
+            let end = inner.find(quote).unwrap_or(inner.len());
+            (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+          }
+          _ => {
+            let end = after_equals
+              .find(|c: char| c.is_ascii_whitespace() || c == '>')
+              .unwrap_or(after_equals.len());
+            (&after_equals[..end], &after_equals[end..])
+          }
+        };
+        value = found;
+        rest = remaining;
+      }
+      attributes.push((attribute, value));
+    }
+    let end = (html.len() - rest.len() + 1).min(html.len());
+    position = end;
+    return Some(Tag {
+      name,
+      attributes,
+      closing,
+      span: start..end,
+    });
+  })
+}
+
+/// The text of the HTML outside of its tags, scripts and styles, with entities left as they are.
+pub fn visible_text(html: &str) -> String {
+  let mut text = String::new();
+  let mut position = 0;
+  let mut hidden_until: Option<String> = None;
+  for tag in tags(html) {
+    if hidden_until.is_none() {
+      text.push_str(&html[position..tag.span.start]);
+    }
+    match &hidden_until {
+      Some(name) if tag.closing && *name == tag.name => hidden_until = None,
+      None if !tag.closing && (tag.name == "script" || tag.name == "style") => {
+        hidden_until = Some(tag.name.clone())
+      }
+      _ => {}
+    }
+    position = tag.span.end;
+  }
+  if hidden_until.is_none() {
+    text.push_str(&html[position..]);
+  }
+  text
+}
+
+/// The scheme of an absolute URL, lowercased, e.g. `https` or `mailto`.
+pub fn url_scheme(url: &str) -> Option<String> {
+  let (scheme, _) = url.trim().split_once(':')?;
+  let is_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+    && scheme
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+  is_scheme.then(|| scheme.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{tags, to_text, visible_text};
+
+  #[test]
+  fn links_become_footnotes() {
+    assert_eq!(
+      to_text(r#"<p>Read <a href="https://example.com/post">the post</a>.</p>"#),
+      "Read [the post][1].\n\n[1]: https://example.com/post"
+    );
+  }
+
+  #[test]
+  fn structure_is_kept() {
+    let text = to_text(
+      "<h1>News</h1><ul><li>first</li><li>second</li></ul>\
+      <table><tr><th>Plan</th><th>Price</th></tr><tr><td>Pro</td><td>10</td></tr></table>",
+    );
+    assert!(text.starts_with("# News\n"));
+    assert!(text.contains("* first\n* second"));
+    assert!(text.contains("Plan│Price"));
+    assert!(text.contains("Pro │10"));
+  }
+
+  #[test]
+  fn merge_tags_are_kept() {
+    assert_eq!(
+      to_text(r#"<p>Hi {{ attributes.nickname | default: &quot;friend&quot; }}</p>"#),
+      r#"Hi {{ attributes.nickname | default: "friend" }}"#
+    );
+  }
+
+  #[test]
+  fn tags_are_found_with_their_attributes() {
+    let html = r#"<!-- <b> --><p class=intro data-x='a > b'>Hi</p><br/>"#;
+    let found: Vec<_> = tags(html)
+      .map(|tag| (tag.name, tag.closing, tag.attributes, &html[tag.span]))
+      .collect();
+    assert_eq!(
+      found,
+      vec![
+        (
+          "p".to_string(),
+          false,
+          vec![
+            ("class".to_string(), "intro"),
+            ("data-x".to_string(), "a > b")
+          ],
+          r#"<p class=intro data-x='a > b'>"#
+        ),
+        ("p".to_string(), true, vec![], "</p>"),
+        ("br".to_string(), false, vec![], "<br/>"),
+      ]
+    );
+  }
+
+  #[test]
+  fn visible_text_skips_tags_scripts_and_styles() {
+    assert_eq!(
+      visible_text("<style>p { color: red; }</style><p>Hi <b>there</b></p><script>x()</script>!"),
+      "Hi there!"
+    );
+  }
+}
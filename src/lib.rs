@@ -1,6 +1,25 @@
+pub mod analytics;
+pub mod audit;
+pub mod authentication;
 pub mod configuration;
+pub mod cors;
 pub mod domain;
 pub mod email_client;
+pub mod email_html;
+pub mod email_templates;
+pub mod feeds;
+pub mod html;
+pub mod lint;
+pub mod markdown;
+pub mod merge_tags;
+pub mod newsletters;
+pub mod page_templates;
+pub mod problem;
 pub mod routes;
+pub mod sanitization;
+pub mod search;
+pub mod segments;
+pub mod signup_protection;
 pub mod startup;
 pub mod telemetry;
+pub mod tracking;
@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+
+use crate::html;
+
+/// A problem found in an issue, e.g. `{ "code": "insecure_link", "message": "..." }`.
+#[derive(Debug, Serialize)]
+pub struct Finding {
+  pub code: &'static str,
+  pub message: String,
+}
+
+/// Everything the linter found. Errors are very likely to get the issue filtered or
+/// mishandled, while warnings are worth a second look.
+#[derive(Debug, Default, Serialize)]
+pub struct LintReport {
+  pub errors: Vec<Finding>,
+  pub warnings: Vec<Finding>,
+}
+
+/// The findings which keep an issue from being published, when publishing asks for it.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintGate {
+  Errors,
+  Warnings,
+}
+
+/// What is linted: the issue as submitted, and the email it is sent as.
+pub struct LintedIssue<'a> {
+  pub subject: &'a str,
+  /// The html as submitted, before sanitization.
+  pub content_html: &'a str,
+  /// The email, including its layout and footer.
+  pub email_html: &'a str,
+  pub email_text: &'a str,
+}
+
+const MAX_SUBJECT_CHARS: usize = 100;
+const MAX_LINKS: usize = 30;
+/// Bodies with images and less text than this look like image-only spam.
+const MIN_TEXT_CHARS_WITH_IMAGES: usize = 50;
+const SPAMMY_PHRASES: [&str; 12] = [
+  "100% free",
+  "act now",
+  "buy now",
+  "cash bonus",
+  "click here",
+  "free money",
+  "guaranteed",
+  "limited time",
+  "no cost",
+  "risk-free",
+  "winner",
+  "$$$",
+];
+/// Elements which have no closing tag.
+const VOID_ELEMENTS: [&str; 14] = [
+  "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+  "track", "wbr",
+];
+
+impl LintReport {
+  pub fn blocks(&self, gate: LintGate) -> bool {
+    match gate {
+      LintGate::Errors => !self.errors.is_empty(),
+      LintGate::Warnings => !self.errors.is_empty() || !self.warnings.is_empty(),
+    }
+  }
+
+  fn error(&mut self, code: &'static str, message: String) {
+    self.errors.push(Finding { code, message });
+  }
+
+  fn warning(&mut self, code: &'static str, message: String) {
+    self.warnings.push(Finding { code, message });
+  }
+}
+
+pub fn lint(issue: &LintedIssue) -> LintReport {
+  let mut report = LintReport::default();
+  lint_subject(issue.subject, &mut report);
+  lint_content(issue.content_html, &mut report);
+
+  let links_to_preferences = html::tags(issue.email_html)
+    .filter(|tag| tag.name == "a" && !tag.closing)
+    .flat_map(|tag| tag.attributes)
+    .any(|(name, value)| {
+      name == "href"
+        && (value.contains("/subscriptions/preferences") || value.contains("unsubscribe"))
+    });
+  if !links_to_preferences {
+    report.error(
+      "missing_unsubscribe_link",
+      "The email has no link to unsubscribe or manage the subscription.".into(),
+    );
+  }
+  if issue.email_text.trim().is_empty() {
+    report.error(
+      "empty_text_part",
+      "The text part of the email is empty.".into(),
+    );
+  }
+  report
+}
+
+fn lint_subject(subject: &str, report: &mut LintReport) {
+  let letters: Vec<char> = subject.chars().filter(|c| c.is_alphabetic()).collect();
+  let uppercase = letters.iter().filter(|c| c.is_uppercase()).count();
+  if letters.len() >= 5 && uppercase * 10 >= letters.len() * 7 {
+    report.warning(
+      "all_caps_subject",
+      "The subject is mostly in capital letters.".into(),
+    );
+  }
+  let lowercase_subject = subject.to_lowercase();
+  let spammy: Vec<&str> = SPAMMY_PHRASES
+    .iter()
+    .copied()
+    .filter(|phrase| lowercase_subject.contains(phrase))
+    .collect();
+  if !spammy.is_empty() || subject.contains("!!") {
+    let mut phrases: Vec<String> = spammy.iter().map(|p| format!("\"{}\"", p)).collect();
+    if subject.contains("!!") {
+      phrases.push("repeated exclamation marks".into());
+    }
+    report.warning(
+      "spammy_subject",
+      format!(
+        "The subject contains {}, which spam filters look for.",
+        phrases.join(", ")
+      ),
+    );
+  }
+  let length = subject.chars().count();
+  if length > MAX_SUBJECT_CHARS {
+    report.warning(
+      "long_subject",
+      format!(
+        "The subject is {} characters long, and will be cut off by most clients.",
+        length
+      ),
+    );
+  }
+}
+
+fn lint_content(content: &str, report: &mut LintReport) {
+  let mut links = 0;
+  let mut images = 0;
+  let mut open_elements: Vec<String> = Vec::new();
+  let mut unbalanced = Vec::new();
+  for tag in html::tags(content) {
+    if tag.closing {
+      match open_elements.iter().rposition(|name| *name == tag.name) {
+        Some(position) => {
+          unbalanced.extend(
+            open_elements
+              .drain(position + 1..)
+              .map(|n| format!("<{}>", n)),
+          );
+          open_elements.pop();
+        }
+        None => unbalanced.push(format!("</{}>", tag.name)),
+      }
+      continue;
+    }
+    match tag.name.as_str() {
+      "a" => links += 1,
+      "img" => images += 1,
+      _ => {}
+    }
+    if !VOID_ELEMENTS.contains(&tag.name.as_str()) {
+      open_elements.push(tag.name.clone());
+    }
+    for (name, value) in &tag.attributes {
+      if name == "href" || name == "src" {
+        lint_url(value, report);
+      }
+    }
+  }
+  unbalanced.extend(open_elements.into_iter().map(|n| format!("<{}>", n)));
+
+  if links > MAX_LINKS {
+    report.warning(
+      "too_many_links",
+      format!(
+        "The issue has {} links, more than the {} spam filters tolerate.",
+        links, MAX_LINKS
+      ),
+    );
+  }
+  let text_chars = html::visible_text(content)
+    .chars()
+    .filter(|c| !c.is_whitespace())
+    .count();
+  if images > 0 && text_chars < MIN_TEXT_CHARS_WITH_IMAGES {
+    report.warning(
+      "image_only_body",
+      "The issue is almost only images, which spam filters distrust and which many clients hide."
+        .into(),
+    );
+  }
+  if !unbalanced.is_empty() {
+    report.warning(
+      "unbalanced_html",
+      format!(
+        "Some tags are not closed or not opened: {}.",
+        unbalanced.join(", ")
+      ),
+    );
+  }
+}
+
+fn lint_url(url: &str, report: &mut LintReport) {
+  let scheme = match html::url_scheme(url) {
+    Some(scheme) if scheme == "http" || scheme == "https" => scheme,
+    _ => return,
+  };
+  let host = url
+    .trim()
+    .split_once("://")
+    .and_then(|(_, rest)| rest.split(['/', '?', '#']).next())
+    .map(|authority| {
+      let host_and_port = authority.rsplit('@').next().unwrap_or(authority);
+      match host_and_port.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(ipv6),
+        None => host_and_port.split(':').next().unwrap_or(host_and_port),
+      }
+      .to_ascii_lowercase()
+    })
+    .unwrap_or_default();
+  let is_local = host == "localhost"
+    || host.ends_with(".localhost")
+    || host.ends_with(".local")
+    || host.starts_with("127.")
+    || host == "0.0.0.0"
+    || host == "::1";
+  if is_local {
+    report.error(
+      "local_link",
+      format!(
+        "{} points to this machine, not somewhere subscribers can reach.",
+        url
+      ),
+    );
+  } else if scheme == "http" {
+    report.warning("insecure_link", format!("{} doesn't use HTTPS.", url));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{lint, LintGate, LintReport, LintedIssue};
+
+  const FOOTER: &str =
+    r#"<a href="https://news.example.com/subscriptions/preferences?token=x">Manage</a>"#;
+
+  fn lint_issue(subject: &str, content: &str) -> LintReport {
+    lint(&LintedIssue {
+      subject,
+      content_html: content,
+      email_html: &format!("{}{}", content, FOOTER),
+      email_text: "Hello",
+    })
+  }
+
+  fn codes(report: &LintReport) -> (Vec<&str>, Vec<&str>) {
+    (
+      report.errors.iter().map(|f| f.code).collect(),
+      report.warnings.iter().map(|f| f.code).collect(),
+    )
+  }
+
+  #[test]
+  fn a_good_issue_has_no_findings() {
+    let report = lint_issue(
+      "What's new in January",
+      r#"<h1>News</h1><p>Read <a href="https://example.com/post">the post</a>.</p>"#,
+    );
+    assert_eq!(codes(&report), (vec![], vec![]));
+  }
+
+  #[test]
+  fn missing_unsubscribe_links_and_empty_text_are_errors() {
+    let report = lint(&LintedIssue {
+      subject: "Hello",
+      content_html: "<p>Hello</p>",
+      email_html: "<p>Hello</p>",
+      email_text: " ",
+    });
+    assert_eq!(
+      codes(&report),
+      (vec!["missing_unsubscribe_link", "empty_text_part"], vec![])
+    );
+  }
+
+  #[test]
+  fn spammy_subjects_are_warned_about() {
+    for subject in [
+      "BIG NEWS FOR YOU",
+      "Click here now",
+      "Wow!!",
+      &"long ".repeat(30),
+    ] {
+      assert_eq!(
+        lint_issue(subject, "<p>Hello</p>").warnings.len(),
+        1,
+        "{}",
+        subject
+      );
+    }
+  }
+
+  #[test]
+  fn content_problems_are_found() {
+    let many_links = r#"<a href="https://example.com">x</a>"#.repeat(31);
+    for (content, code) in [
+      (many_links.as_str(), "too_many_links"),
+      (
+        r#"<img src="https://example.com/a.png">"#,
+        "image_only_body",
+      ),
+      ("<div><p>Hello</div>", "unbalanced_html"),
+      (r#"<a href="http://example.com">x</a>"#, "insecure_link"),
+    ] {
+      assert_eq!(
+        codes(&lint_issue("Hello", content)).1,
+        vec![code],
+        "{}",
+        content
+      );
+    }
+  }
+
+  #[test]
+  fn local_links_are_errors() {
+    for url in [
+      "http://localhost:8000/x",
+      "https://127.0.0.1/x",
+      "http://[::1]/",
+    ] {
+      let content = format!(r#"<a href="{}">x</a>"#, url);
+      assert_eq!(codes(&lint_issue("Hello", &content)).0, vec!["local_link"]);
+    }
+  }
+
+  #[test]
+  fn gates_block_on_their_severity() {
+    let warned = lint_issue("Hello", r#"<a href="http://example.com">x</a>"#);
+    assert!(!warned.blocks(LintGate::Errors));
+    assert!(warned.blocks(LintGate::Warnings));
+  }
+}
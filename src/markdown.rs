@@ -0,0 +1,392 @@
+use pulldown_cmark::{CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+
+/// A newsletter issue written in Markdown, which may start with YAML front matter:
+///
+/// ```text
+/// ---
+/// title: "Issue #1"
+/// preheader: The first issue
+/// ---
+/// # Hello {{ name }}!
+/// ```
+///
+/// Merge tags are left as they are, to be filled in for every recipient afterwards.
+#[derive(Debug)]
+pub struct MarkdownIssue {
+  pub front_matter: FrontMatter,
+  pub html: String,
+  pub text: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FrontMatter {
+  pub title: Option<String>,
+  /// Shown by email clients next to the title.
+  pub preheader: Option<String>,
+}
+
+impl MarkdownIssue {
+  pub fn parse(source: &str) -> Result<Self, String> {
+    let (front_matter, markdown) = match split_front_matter(source) {
+      Some((yaml, markdown)) => (
+        serde_yaml::from_str::<Option<FrontMatter>>(yaml)
+          .map_err(|e| format!("invalid front matter: {}", e))?
+          .unwrap_or_default(),
+        markdown,
+      ),
+      None => (FrontMatter::default(), source),
+    };
+    Ok(Self {
+      front_matter,
+      html: to_html(markdown),
+      text: to_text(markdown),
+    })
+  }
+}
+
+/// Splits `---`-delimited front matter from the rest of the document, if there is any.
+fn split_front_matter(source: &str) -> Option<(&str, &str)> {
+  let rest = source
+    .strip_prefix("---\n")
+    .or_else(|| source.strip_prefix("---\r\n"))?;
+  let mut offset = 0;
+  for line in rest.split_inclusive('\n') {
+    if line.trim_end() == "---" {
+      return Some((&rest[..offset], &rest[offset + line.len()..]));
+    }
+    offset += line.len();
+  }
+  None
+}
+
+fn parser(markdown: &str) -> Parser<'_> {
+  Parser::new_ext(
+    markdown,
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS,
+  )
+}
+
+/// Renders the Markdown to HTML. Raw HTML in the Markdown is escaped rather than
+/// passed through, and links or images with a scriptable URL lose it.
+pub fn to_html(markdown: &str) -> String {
+  let events = parser(markdown).map(|event| match event {
+    Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+    Event::Start(Tag::Link {
+      link_type,
+      dest_url,
+      title,
+      id,
+    }) => Event::Start(Tag::Link {
+      link_type,
+      dest_url: safe_url(dest_url),
+      title,
+      id,
+    }),
+    Event::Start(Tag::Image {
+      link_type,
+      dest_url,
+      title,
+      id,
+    }) => Event::Start(Tag::Image {
+      link_type,
+      dest_url: safe_url(dest_url),
+      title,
+      id,
+    }),
+    event => event,
+  });
+  let mut html = String::new();
+  pulldown_cmark::html::push_html(&mut html, events);
+  html
+}
+
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+  let scheme = url
+    .split_once(':')
+    .map(|(scheme, _)| scheme.trim().to_ascii_lowercase());
+  match scheme.as_deref() {
+    Some("javascript" | "vbscript" | "data") => CowStr::Borrowed(""),
+    _ => url,
+  }
+}
+
+/// Renders the Markdown as plain text which reads well on its own: paragraphs are
+/// separated by blank lines, lists and quotes keep their markers, and links are followed
+/// by their URL.
+pub fn to_text(markdown: &str) -> String {
+  let mut writer = TextWriter::default();
+  for event in parser(markdown) {
+    writer.handle(event);
+  }
+  writer.out.trim_end().to_string()
+}
+
+#[derive(Default)]
+struct TextWriter {
+  out: String,
+  /// The next number of every list being written, `None` for bulleted lists.
+  lists: Vec<Option<u64>>,
+  quote_depth: usize,
+  /// The URL of every link or image being written, and where its text starts.
+  links: Vec<(String, usize)>,
+  /// Where the heading being written starts.
+  heading_start: usize,
+  in_code_block: bool,
+  /// Whether nothing was written since the last list item's marker.
+  at_item_start: bool,
+}
+
+impl TextWriter {
+  fn handle(&mut self, event: Event) {
+    match event {
+      Event::Start(tag) => self.start(tag),
+      Event::End(tag) => self.end(tag),
+      Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => self.write(&text),
+      Event::Code(code) => self.write(&code),
+      Event::SoftBreak | Event::HardBreak => self.write("\n"),
+      Event::Rule => {
+        self.start_block();
+        self.write("----------");
+      }
+      Event::TaskListMarker(checked) => self.write(if checked { "[x] " } else { "[ ] " }),
+      _ => {}
+    }
+  }
+
+  fn start(&mut self, tag: Tag) {
+    match tag {
+      // The first paragraph of a list item goes right after its marker.
+      Tag::Paragraph if self.at_item_start => {}
+      Tag::Paragraph | Tag::BlockQuote(_) | Tag::HtmlBlock | Tag::Table(_) => {
+        self.start_block();
+        if matches!(tag, Tag::BlockQuote(_)) {
+          self.quote_depth += 1;
+        }
+      }
+      Tag::Heading { level, .. } => {
+        self.start_block();
+        let prefix = match level {
+          HeadingLevel::H1 | HeadingLevel::H2 => "",
+          HeadingLevel::H3 => "### ",
+          HeadingLevel::H4 => "#### ",
+          HeadingLevel::H5 => "##### ",
+          HeadingLevel::H6 => "###### ",
+        };
+        self.write(prefix);
+        self.heading_start = self.out.len();
+      }
+      Tag::CodeBlock(_) => {
+        self.start_block();
+        self.in_code_block = true;
+      }
+      Tag::List(start) => {
+        if self.lists.is_empty() {
+          self.start_block();
+        }
+        self.lists.push(start);
+      }
+      Tag::Item => {
+        self.start_line();
+        let depth = self.lists.len();
+        let marker = match self.lists.last_mut() {
+          Some(Some(number)) => {
+            *number += 1;
+            format!("{}. ", *number - 1)
+          }
+          _ => "- ".to_string(),
+        };
+        self.out.push_str(&"> ".repeat(self.quote_depth));
+        self.out.push_str(&"  ".repeat(depth - 1));
+        self.out.push_str(&marker);
+        self.at_item_start = true;
+      }
+      Tag::TableRow | Tag::TableHead => self.start_line(),
+      Tag::TableCell if !self.out.ends_with('\n') && !self.out.is_empty() => self.write(" | "),
+      Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+        self.links.push((dest_url.to_string(), self.out.len()));
+      }
+      _ => {}
+    }
+  }
+
+  fn end(&mut self, tag: TagEnd) {
+    match tag {
+      TagEnd::BlockQuote(_) => self.quote_depth -= 1,
+      TagEnd::Heading(level) => {
+        let width = self.out[self.heading_start..].chars().count();
+        match level {
+          HeadingLevel::H1 => self.write(&format!("\n{}", "=".repeat(width))),
+          HeadingLevel::H2 => self.write(&format!("\n{}", "-".repeat(width))),
+          _ => {}
+        }
+      }
+      TagEnd::CodeBlock => self.in_code_block = false,
+      TagEnd::List(_) => {
+        self.lists.pop();
+      }
+      TagEnd::Link | TagEnd::Image => {
+        if let Some((url, start)) = self.links.pop() {
+          let text = &self.out[start..];
+          let is_autolink = text == url || url.strip_prefix("mailto:") == Some(text);
+          if !url.is_empty() && !is_autolink {
+            self.write(&format!(" ({})", url));
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+
+  /// Leaves a blank line after whatever was written before.
+  fn start_block(&mut self) {
+    if self.out.is_empty() {
+      return;
+    }
+    while !self.out.ends_with("\n\n") {
+      self.out.push('\n');
+    }
+  }
+
+  fn start_line(&mut self) {
+    if !self.out.is_empty() && !self.out.ends_with('\n') {
+      self.out.push('\n');
+    }
+  }
+
+  /// Writes the text, starting every new line with the markers of the quotes
+  /// and lists it is in.
+  fn write(&mut self, text: &str) {
+    self.at_item_start = false;
+    for (i, line) in text.split('\n').enumerate() {
+      if i > 0 {
+        self.out.push('\n');
+      }
+      if self.out.ends_with('\n') && !line.is_empty() {
+        self.write_prefix();
+      }
+      self.out.push_str(line);
+    }
+  }
+
+  fn write_prefix(&mut self) {
+    self.out.push_str(&"> ".repeat(self.quote_depth));
+    if self.in_code_block {
+      self.out.push_str("    ");
+    } else if !self.lists.is_empty() {
+      self.out.push_str(&"  ".repeat(self.lists.len()));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{to_html, to_text, MarkdownIssue};
+  use claim::assert_err;
+
+  #[test]
+  fn front_matter_is_read_and_removed() {
+    let issue = MarkdownIssue::parse(
+      "---\ntitle: \"Issue #1\"\npreheader: The first issue\n---\nHello *there*!\n",
+    )
+    .unwrap();
+    assert_eq!(issue.front_matter.title.as_deref(), Some("Issue #1"));
+    assert_eq!(
+      issue.front_matter.preheader.as_deref(),
+      Some("The first issue")
+    );
+    assert_eq!(issue.html, "<p>Hello <em>there</em>!</p>\n");
+    assert_eq!(issue.text, "Hello there!");
+  }
+
+  #[test]
+  fn front_matter_is_optional() {
+    let issue = MarkdownIssue::parse("Hello!").unwrap();
+    assert!(issue.front_matter.title.is_none());
+    assert_eq!(issue.text, "Hello!");
+  }
+
+  #[test]
+  fn invalid_front_matter_is_rejected() {
+    assert_err!(MarkdownIssue::parse("---\ntitle: [\n---\nHello!"));
+    assert_err!(MarkdownIssue::parse("---\nsubject: Hello\n---\nHello!"));
+    // Issues are sent when published, there is no scheduling.
+    assert_err!(MarkdownIssue::parse(
+      "---\nsend_at: 2022-01-30T09:00:00Z\n---\nHello!"
+    ));
+  }
+
+  #[test]
+  fn raw_html_is_escaped() {
+    let html = to_html("Hi <script>alert(1)</script>\n\n<div onclick=\"x()\">block</div>\n");
+    assert!(!html.contains("<script>"));
+    assert!(!html.contains("<div"));
+    assert!(html.contains("&lt;script&gt;"));
+  }
+
+  #[test]
+  fn scriptable_urls_are_removed() {
+    let html = to_html("[click](javascript:alert(1)) ![x](data:text/html,hi)");
+    assert!(!html.contains("javascript:"));
+    assert!(!html.contains("data:"));
+  }
+
+  #[test]
+  fn merge_tags_survive_rendering() {
+    let markdown = r#"Hi **{{ attributes.nickname | default: "friend" }}**"#;
+    assert!(to_html(markdown).contains(r#"{{ attributes.nickname | default: "friend" }}"#));
+    assert_eq!(
+      to_text(markdown),
+      r#"Hi {{ attributes.nickname | default: "friend" }}"#
+    );
+  }
+
+  #[test]
+  fn plain_text_keeps_the_structure() {
+    let markdown = "\
+# Issue one
+
+Read [the post](https://example.com/post) or <https://example.com>.
+
+## News
+
+- first
+- second
+  - nested
+
+1. one
+2. two
+
+> quoted
+> text
+
+```
+let x = 1;
+```
+";
+    assert_eq!(
+      to_text(markdown),
+      "\
+Issue one
+=========
+
+Read the post (https://example.com/post) or https://example.com.
+
+News
+----
+
+- first
+- second
+  - nested
+
+1. one
+2. two
+
+> quoted
+> text
+
+    let x = 1;"
+    );
+  }
+}
@@ -0,0 +1,281 @@
+use serde_json::{Map, Value};
+
+use crate::html;
+
+/// Newsletter content with merge tags, such as `{{ name }}` or `{{ attributes.city | default: "friend" }}`,
+/// which are filled in separately for every recipient.
+///
+/// The available variables are `name`, `email` and `attributes.<key>`.
+/// A tag without a default is required: rendering it for a recipient who lacks the variable fails.
+#[derive(Debug)]
+pub struct MergeTemplate {
+  segments: Vec<Segment>,
+}
+
+#[derive(Debug)]
+enum Segment {
+  Text(String),
+  Tag(MergeTag),
+}
+
+#[derive(Debug)]
+struct MergeTag {
+  variable: Variable,
+  default: Option<String>,
+}
+
+#[derive(Debug)]
+enum Variable {
+  Name,
+  Email,
+  Attribute(String),
+}
+
+impl Variable {
+  fn parse(s: &str) -> Result<Self, String> {
+    match s.split('.').collect::<Vec<_>>().as_slice() {
+      ["name"] => Ok(Variable::Name),
+      ["email"] => Ok(Variable::Email),
+      ["attributes", key] if !key.is_empty() => Ok(Variable::Attribute(key.to_string())),
+      _ => Err(format!(
+        "{} is not a known variable. Use name, email or attributes.<name>",
+        s
+      )),
+    }
+  }
+}
+
+impl std::fmt::Display for Variable {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Variable::Name => write!(f, "name"),
+      Variable::Email => write!(f, "email"),
+      Variable::Attribute(key) => write!(f, "attributes.{}", key),
+    }
+  }
+}
+
+/// What merge tags are filled in with.
+pub struct Recipient<'a> {
+  pub name: &'a str,
+  pub email: &'a str,
+  pub attributes: &'a Map<String, Value>,
+}
+
+/// How values are written into the content.
+#[derive(Debug, Clone, Copy)]
+pub enum Escaping {
+  Html,
+  None,
+}
+
+impl MergeTemplate {
+  pub fn parse(s: &str) -> Result<Self, String> {
+    let mut segments = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+      if start > 0 {
+        segments.push(Segment::Text(rest[..start].to_string()));
+      }
+      let after_open = &rest[start + 2..];
+      let end = after_open
+        .find("}}")
+        .ok_or_else(|| "a merge tag is missing its closing }}".to_string())?;
+      segments.push(Segment::Tag(MergeTag::parse(&after_open[..end])?));
+      rest = &after_open[end + 2..];
+    }
+    if !rest.is_empty() {
+      segments.push(Segment::Text(rest.to_string()));
+    }
+    Ok(Self { segments })
+  }
+
+  /// Fills in every merge tag for the recipient, or returns the variables the recipient is missing.
+  pub fn render(&self, recipient: &Recipient, escaping: Escaping) -> Result<String, Vec<String>> {
+    let mut rendered = String::new();
+    let mut missing = Vec::new();
+    for segment in &self.segments {
+      match segment {
+        Segment::Text(text) => rendered.push_str(text),
+        Segment::Tag(tag) => match tag.value_for(recipient) {
+          Some(value) => rendered.push_str(&match escaping {
+            Escaping::Html => html::escape(&value),
+            Escaping::None => value,
+          }),
+          None => missing.push(tag.variable.to_string()),
+        },
+      }
+    }
+    if missing.is_empty() {
+      Ok(rendered)
+    } else {
+      Err(missing)
+    }
+  }
+
+  /// Fills in every merge tag for nobody in particular, as on the web archive:
+  /// tags with a default get it, the others are left out.
+  pub fn render_public(&self, escaping: Escaping) -> String {
+    self
+      .segments
+      .iter()
+      .map(|segment| match segment {
+        Segment::Text(text) => text.clone(),
+        Segment::Tag(tag) => match (&tag.default, escaping) {
+          (Some(default), Escaping::Html) => html::escape(default),
+          (Some(default), Escaping::None) => default.clone(),
+          (None, _) => String::new(),
+        },
+      })
+      .collect()
+  }
+}
+
+impl MergeTag {
+  fn parse(s: &str) -> Result<Self, String> {
+    let (variable, filter) = match s.split_once('|') {
+      Some((variable, filter)) => (variable, Some(filter)),
+      None => (s, None),
+    };
+    let variable = Variable::parse(variable.trim())?;
+    let default = filter.map(parse_default_filter).transpose()?;
+    Ok(Self { variable, default })
+  }
+
+  /// Missing and empty values both fall back to the default.
+  fn value_for(&self, recipient: &Recipient) -> Option<String> {
+    let value = match &self.variable {
+      Variable::Name => Some(recipient.name.to_string()),
+      Variable::Email => Some(recipient.email.to_string()),
+      Variable::Attribute(key) => match recipient.attributes.get(key) {
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(Value::Null) | None => None,
+        Some(value) => Some(value.to_string()),
+      },
+    };
+    value
+      .filter(|value| !value.is_empty())
+      .or_else(|| self.default.clone())
+  }
+}
+
+/// Parses `default: "value"`, the only filter there is.
+fn parse_default_filter(s: &str) -> Result<String, String> {
+  let invalid = || format!("{} is not a valid filter. Use default: \"value\"", s.trim());
+  let argument = s
+    .trim()
+    .strip_prefix("default")
+    .and_then(|rest| rest.trim_start().strip_prefix(':'))
+    .map(str::trim)
+    .ok_or_else(invalid)?;
+  ['"', '\'']
+    .iter()
+    .find_map(|quote| {
+      argument
+        .strip_prefix(*quote)
+        .and_then(|rest| rest.strip_suffix(*quote))
+    })
+    .map(str::to_string)
+    .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Escaping, MergeTemplate, Recipient};
+  use claim::{assert_err, assert_ok};
+  use serde_json::{json, Map, Value};
+
+  fn attributes(value: Value) -> Map<String, Value> {
+    value.as_object().unwrap().clone()
+  }
+
+  fn render(template: &str, attributes: &Map<String, Value>) -> Result<String, Vec<String>> {
+    let recipient = Recipient {
+      name: "Phil",
+      email: "phil@nadon.io",
+      attributes,
+    };
+    MergeTemplate::parse(template)
+      .unwrap()
+      .render(&recipient, Escaping::None)
+  }
+
+  #[test]
+  fn content_without_merge_tags_is_unchanged() {
+    assert_eq!(render("Hello there!", &Map::new()).unwrap(), "Hello there!");
+  }
+
+  #[test]
+  fn name_email_and_attributes_are_filled_in() {
+    let attributes = attributes(json!({ "city": "Montreal", "seats": 3 }));
+    assert_eq!(
+      render(
+        "{{ name }} <{{email}}> from {{ attributes.city }}, {{ attributes.seats }} seats",
+        &attributes
+      )
+      .unwrap(),
+      "Phil <phil@nadon.io> from Montreal, 3 seats"
+    );
+  }
+
+  #[test]
+  fn defaults_are_used_for_missing_or_empty_attributes() {
+    let template = r#"Hi {{ attributes.nickname | default: "friend" }} from {{ attributes.city | default: 'nowhere' }}"#;
+    assert_eq!(
+      render(template, &attributes(json!({ "city": "" }))).unwrap(),
+      "Hi friend from nowhere"
+    );
+  }
+
+  #[test]
+  fn missing_required_attributes_are_all_reported() {
+    let missing = render("{{ attributes.city }} {{ attributes.plan }}", &Map::new());
+    assert_eq!(
+      missing.unwrap_err(),
+      vec!["attributes.city".to_string(), "attributes.plan".to_string()]
+    );
+  }
+
+  #[test]
+  fn values_are_escaped_in_html() {
+    let attributes = attributes(json!({ "company": "<Nadon & Co>" }));
+    let recipient = Recipient {
+      name: "Phil",
+      email: "phil@nadon.io",
+      attributes: &attributes,
+    };
+    let rendered = MergeTemplate::parse("<p>{{ attributes.company }}</p>")
+      .unwrap()
+      .render(&recipient, Escaping::Html)
+      .unwrap();
+    assert_eq!(rendered, "<p>&lt;Nadon &amp; Co&gt;</p>");
+  }
+
+  #[test]
+  fn public_renderings_use_defaults_and_leave_out_the_rest() {
+    let rendered = MergeTemplate::parse(r#"Hi {{ name | default: "R&D" }}{{ email }}!"#)
+      .unwrap()
+      .render_public(Escaping::Html);
+    assert_eq!(rendered, "Hi R&amp;D!");
+  }
+
+  #[test]
+  fn invalid_merge_tags_are_rejected() {
+    for template in [
+      "{{ name",
+      "{{ company }}",
+      "{{ attributes }}",
+      "{{ attributes.city | upcase }}",
+      "{{ attributes.city | default: friend }}",
+    ] {
+      assert_err!(MergeTemplate::parse(template), "{}", template);
+    }
+  }
+
+  #[test]
+  fn valid_merge_tags_are_accepted() {
+    assert_ok!(MergeTemplate::parse(
+      r#"{{name}} {{ attributes.city|default:"x" }}"#
+    ));
+  }
+}
@@ -0,0 +1,324 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{NewsletterSlug, SubscriberName};
+
+/// The newsletter people are subscribed to when they don't pick one, and which every
+/// subscriber from before there were several newsletters belongs to.
+pub const DEFAULT_NEWSLETTER_SLUG: &str = "default";
+
+/// A newsletter (or list) which people can subscribe to, with its own subscribers and issues.
+#[derive(Debug, Serialize)]
+pub struct Newsletter {
+  pub id: Uuid,
+  pub slug: String,
+  pub name: String,
+  /// Shown as the display name of the sender, alongside the configured sender address.
+  pub sender_name: String,
+  /// Opening line of the confirmation email, e.g. "Welcome to our newsletter!".
+  pub confirmation_copy: String,
+  /// Whether issues carry a pixel which records when subscribers open them.
+  pub track_opens: bool,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A validated request to create a newsletter.
+#[derive(Debug)]
+pub struct NewNewsletter {
+  pub slug: NewsletterSlug,
+  pub name: String,
+  pub sender_name: SubscriberName,
+  pub confirmation_copy: String,
+}
+
+impl NewNewsletter {
+  /// Like `SubscriberName::parse`, every problem is reported at once.
+  /// The sender name follows the same rules as a subscriber's name, since both end up in email headers.
+  pub fn parse(
+    slug: String,
+    name: String,
+    sender_name: String,
+    confirmation_copy: String,
+  ) -> Result<Self, Vec<String>> {
+    let slug = NewsletterSlug::parse(slug).map_err(|e| vec![e]);
+    let sender_name = SubscriberName::parse(sender_name).map_err(|es| {
+      es.into_iter()
+        .map(|e| format!("sender {}", e))
+        .collect::<Vec<_>>()
+    });
+    let mut errors: Vec<String> = Vec::new();
+    if name.trim().is_empty() {
+      errors.push("newsletter name cannot be empty!".to_string());
+    }
+    if confirmation_copy.trim().is_empty() {
+      errors.push("confirmation copy cannot be empty!".to_string());
+    }
+
+    match (slug, sender_name) {
+      (Ok(slug), Ok(sender_name)) if errors.is_empty() => Ok(Self {
+        slug,
+        name,
+        sender_name,
+        confirmation_copy,
+      }),
+      (slug, sender_name) => {
+        let mut all_errors = slug.err().unwrap_or_default();
+        all_errors.extend(sender_name.err().unwrap_or_default());
+        all_errors.extend(errors);
+        Err(all_errors)
+      }
+    }
+  }
+}
+
+#[tracing::instrument(name = "Get newsletter by slug", skip(pool))]
+pub async fn get_newsletter_by_slug(
+  pool: &PgPool,
+  slug: &str,
+) -> Result<Option<Newsletter>, anyhow::Error> {
+  sqlx::query_as!(
+    Newsletter,
+    r#"
+    SELECT id, slug, name, sender_name, confirmation_copy, track_opens, created_at
+    FROM newsletters
+    WHERE slug = $1
+    "#,
+    slug,
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to fetch a newsletter.")
+}
+
+#[tracing::instrument(name = "List newsletters", skip(pool))]
+pub async fn list_newsletters(pool: &PgPool) -> Result<Vec<Newsletter>, anyhow::Error> {
+  sqlx::query_as!(
+    Newsletter,
+    r#"
+    SELECT id, slug, name, sender_name, confirmation_copy, track_opens, created_at
+    FROM newsletters
+    ORDER BY created_at
+    "#,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to list newsletters.")
+}
+
+/// Returns `None` if there is already a newsletter with the same slug.
+#[tracing::instrument(name = "Insert newsletter", skip(pool, newsletter))]
+pub async fn insert_newsletter(
+  pool: &PgPool,
+  newsletter: &NewNewsletter,
+) -> Result<Option<Newsletter>, anyhow::Error> {
+  sqlx::query_as!(
+    Newsletter,
+    r#"
+    INSERT INTO newsletters (id, slug, name, sender_name, confirmation_copy, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (slug) DO NOTHING
+    RETURNING id, slug, name, sender_name, confirmation_copy, track_opens, created_at
+    "#,
+    Uuid::new_v4(),
+    newsletter.slug.as_ref(),
+    newsletter.name,
+    newsletter.sender_name.as_ref(),
+    newsletter.confirmation_copy,
+    Utc::now(),
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to insert a newsletter.")
+}
+
+/// Returns `None` if there is no such newsletter.
+#[tracing::instrument(name = "Set open tracking", skip(pool))]
+pub async fn set_open_tracking(
+  pool: &PgPool,
+  slug: &str,
+  track_opens: bool,
+) -> Result<Option<Newsletter>, anyhow::Error> {
+  sqlx::query_as!(
+    Newsletter,
+    r#"
+    UPDATE newsletters SET track_opens = $2
+    WHERE slug = $1
+    RETURNING id, slug, name, sender_name, confirmation_copy, track_opens, created_at
+    "#,
+    slug,
+    track_opens,
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to update the open tracking of a newsletter.")
+}
+
+/// An issue to record as published, with what the web archive shows of it.
+#[derive(Debug)]
+pub struct NewIssue<'a> {
+  pub newsletter_id: Uuid,
+  pub title: &'a str,
+  /// The html as anyone may read it, with merge tags filled in for nobody in particular.
+  pub html_content: &'a str,
+  /// The text part, filled in the same way, which issues are searched by.
+  pub text_content: &'a str,
+  pub preheader: Option<&'a str>,
+  /// Whether the issue is listed on the web archive, and readable by anyone there.
+  pub public: bool,
+  pub published_at: DateTime<Utc>,
+}
+
+/// An issue which was recorded as published.
+#[derive(Debug)]
+pub struct PublishedIssue {
+  pub id: Uuid,
+  /// Where the issue is on the web archive, at `/archive/{slug}`. It never changes.
+  pub slug: String,
+}
+
+/// Records that an issue of the newsletter was published.
+/// Its slug comes from its title, numbered when another issue already has it.
+#[tracing::instrument(name = "Insert issue", skip(pool))]
+pub async fn insert_issue(
+  pool: &PgPool,
+  issue: &NewIssue<'_>,
+) -> Result<PublishedIssue, anyhow::Error> {
+  let issue_id = Uuid::new_v4();
+  let base_slug = issue_slug(issue.title);
+  for n in 1.. {
+    let slug = match n {
+      1 => base_slug.clone(),
+      n => format!("{}-{}", base_slug, n),
+    };
+    let inserted = sqlx::query!(
+      r#"
+      INSERT INTO newsletter_issues
+        (id, newsletter_id, title, slug, html_content, text_content, preheader, public,
+          published_at, updated_at)
+      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)
+      ON CONFLICT (slug) DO NOTHING
+      "#,
+      issue_id,
+      issue.newsletter_id,
+      issue.title,
+      slug,
+      issue.html_content,
+      issue.text_content,
+      issue.preheader,
+      issue.public,
+      issue.published_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a published issue.")?;
+    if inserted.rows_affected() == 1 {
+      return Ok(PublishedIssue { id: issue_id, slug });
+    }
+  }
+  unreachable!("There is always a free slug")
+}
+
+/// Returns `None` if there is no such issue.
+#[tracing::instrument(name = "Set issue visibility", skip(pool))]
+pub async fn set_issue_visibility(
+  pool: &PgPool,
+  issue_id: Uuid,
+  public: bool,
+) -> Result<Option<PublishedIssue>, anyhow::Error> {
+  sqlx::query_as!(
+    PublishedIssue,
+    r#"
+    UPDATE newsletter_issues SET public = $2, updated_at = $3
+    WHERE id = $1
+    RETURNING id, slug
+    "#,
+    issue_id,
+    public,
+    Utc::now(),
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to update the visibility of an issue.")
+}
+
+/// Lowercase ASCII letters and digits of the title, with a hyphen for whatever is in between,
+/// e.g. `rust-2021-is-out` for "Rust 2021 is out!".
+fn issue_slug(title: &str) -> String {
+  const MAX_LENGTH: usize = 60;
+  let mut slug = String::new();
+  for word in title
+    .split(|c: char| !c.is_ascii_alphanumeric())
+    .filter(|word| !word.is_empty())
+  {
+    if !slug.is_empty() && slug.len() + 1 + word.len() > MAX_LENGTH {
+      break;
+    }
+    if !slug.is_empty() {
+      slug.push('-');
+    }
+    slug.push_str(&word.to_ascii_lowercase());
+  }
+  slug.truncate(MAX_LENGTH);
+  if slug.is_empty() {
+    "issue".to_string()
+  } else {
+    slug
+  }
+}
+
+#[tracing::instrument(name = "Set issue delivered", skip(pool))]
+pub async fn set_issue_delivered(
+  pool: &PgPool,
+  issue_id: Uuid,
+  delivered: i32,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    "UPDATE newsletter_issues SET delivered = $1 WHERE id = $2",
+    delivered,
+    issue_id,
+  )
+  .execute(pool)
+  .await
+  .context("Failed to record the deliveries of an issue.")?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{issue_slug, NewNewsletter};
+  use claim::{assert_err, assert_ok};
+
+  #[test]
+  fn a_valid_newsletter_is_parsed_successfully() {
+    assert_ok!(NewNewsletter::parse(
+      "rust-weekly".to_string(),
+      "Rust Weekly".to_string(),
+      "Phil Nadon".to_string(),
+      "Welcome to Rust Weekly!".to_string(),
+    ));
+  }
+
+  #[test]
+  fn all_errors_are_reported_at_once() {
+    let errors = NewNewsletter::parse(
+      "Rust Weekly".to_string(),
+      " ".to_string(),
+      "<Phil>".to_string(),
+      "".to_string(),
+    );
+    assert_err!(&errors);
+    assert_eq!(errors.err().unwrap().len(), 4);
+  }
+
+  #[test]
+  fn issue_slugs_are_made_of_the_words_of_the_title() {
+    assert_eq!(issue_slug("Rust 2021 is out!"), "rust-2021-is-out");
+    assert_eq!(issue_slug("  Café & crème -- #12 "), "caf-cr-me-12");
+    assert_eq!(issue_slug("🦀"), "issue");
+    let slug = issue_slug(&"word ".repeat(30));
+    assert!(slug.len() <= 60 && !slug.ends_with('-'), "{}", slug);
+  }
+}
@@ -0,0 +1,425 @@
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tera::Tera;
+
+use crate::html;
+use crate::routes::{PreferencesFormErrors, SubscribeFormErrors};
+
+/// The templates of the pages subscribers see in their browser.
+/// Every page extends `layout.html`, so overriding it alone is enough to restyle them all.
+const BUILT_IN_TEMPLATES: [(&str, &str); 10] = [
+  (
+    "archive.html",
+    include_str!("../templates/pages/archive.html"),
+  ),
+  (
+    "archived_issue.html",
+    include_str!("../templates/pages/archived_issue.html"),
+  ),
+  (
+    "email_change_confirmed.html",
+    include_str!("../templates/pages/email_change_confirmed.html"),
+  ),
+  (
+    "email_change_taken.html",
+    include_str!("../templates/pages/email_change_taken.html"),
+  ),
+  (
+    "invalid_link.html",
+    include_str!("../templates/pages/invalid_link.html"),
+  ),
+  (
+    "layout.html",
+    include_str!("../templates/pages/layout.html"),
+  ),
+  (
+    "preferences.html",
+    include_str!("../templates/pages/preferences.html"),
+  ),
+  (
+    "subscribe.html",
+    include_str!("../templates/pages/subscribe.html"),
+  ),
+  (
+    "subscribe_pending.html",
+    include_str!("../templates/pages/subscribe_pending.html"),
+  ),
+  (
+    "subscription_confirmed.html",
+    include_str!("../templates/pages/subscription_confirmed.html"),
+  ),
+];
+
+/// Where overrides of the page templates are looked for, within the templates directory.
+const PAGES_DIRECTORY: &str = "pages";
+
+/// A newsletter which can be picked on the subscribe form.
+#[derive(Serialize)]
+pub struct NewsletterChoice<'a> {
+  pub slug: &'a str,
+  pub name: &'a str,
+}
+
+/// Variables of `subscribe.html`: the form, with what was submitted if it was invalid.
+#[derive(Serialize)]
+pub struct SubscribePage<'a> {
+  pub newsletters: &'a [NewsletterChoice<'a>],
+  /// Slug of the selected newsletter.
+  pub list: &'a str,
+  pub name: &'a str,
+  pub email: &'a str,
+  /// Attributes submitted along with the form, which are submitted again.
+  pub attributes: &'a BTreeMap<String, String>,
+  pub errors: &'a SubscribeFormErrors,
+  /// Submitted along with the form, see `SignupGuard`.
+  pub form_token: &'a str,
+  /// Leading zero bits of the proof of work the form must be submitted with, 0 for none.
+  pub proof_of_work_difficulty: u8,
+}
+
+/// Variables of `subscribe_pending.html`, shown once the confirmation email is sent.
+#[derive(Serialize)]
+pub struct SubscribePendingPage<'a> {
+  pub name: &'a str,
+  pub email: &'a str,
+  pub newsletter_name: &'a str,
+}
+
+/// Variables of `subscription_confirmed.html`.
+#[derive(Serialize)]
+pub struct SubscriptionConfirmedPage<'a> {
+  pub newsletter_name: &'a str,
+  pub preferences_link: &'a str,
+}
+
+/// A delivery frequency which can be picked on the preferences page.
+#[derive(Serialize)]
+pub struct FrequencyChoice {
+  pub value: &'static str,
+  pub description: &'static str,
+  pub selected: bool,
+}
+
+/// A newsletter which can be (un)checked on the preferences page.
+#[derive(Serialize)]
+pub struct ListChoice<'a> {
+  pub slug: &'a str,
+  pub name: &'a str,
+  pub subscribed: bool,
+}
+
+/// Variables of `preferences.html`: the stored preferences, or what was submitted if it was invalid.
+#[derive(Serialize)]
+pub struct PreferencesPage<'a> {
+  /// The preferences token, submitted along with every form.
+  pub token: &'a str,
+  pub name: &'a str,
+  pub email: &'a str,
+  pub frequencies: Vec<FrequencyChoice>,
+  pub lists: Vec<ListChoice<'a>>,
+  /// When deliveries resume, e.g. `March 1, 2022`, if the subscription is paused.
+  pub paused_until: Option<String>,
+  pub tracking_opt_out: bool,
+  pub max_pause_weeks: i64,
+  /// What the last change did, e.g. that the preferences were saved.
+  pub message: Option<&'a str>,
+  pub errors: &'a PreferencesFormErrors,
+}
+
+/// Variables of `email_change_confirmed.html` and `email_change_taken.html`,
+/// shown once the new address of a subscriber is confirmed, or found to be subscribed already.
+#[derive(Serialize)]
+pub struct EmailChangePage<'a> {
+  pub new_email: &'a str,
+}
+
+/// An issue in the list of the archive.
+#[derive(Serialize)]
+pub struct ArchiveItem<'a> {
+  pub slug: &'a str,
+  pub title: &'a str,
+  pub newsletter_name: &'a str,
+  /// RFC 3339.
+  pub published_at: String,
+  /// e.g. `March 1, 2022`.
+  pub date: String,
+  /// HTML of the text which matches the search, if any.
+  pub snippet: Option<&'a str>,
+}
+
+/// Variables of `archive.html`: a page of the public issues, or of the results of a search.
+#[derive(Serialize)]
+pub struct ArchivePage<'a> {
+  /// What was searched for, empty when listing every issue.
+  pub query: &'a str,
+  pub issues: Vec<ArchiveItem<'a>>,
+  pub newer_link: Option<String>,
+  pub older_link: Option<String>,
+}
+
+/// A feed of the newsletter an archived issue belongs to.
+#[derive(Serialize)]
+pub struct FeedLink {
+  pub media_type: &'static str,
+  pub url: String,
+}
+
+/// Variables of `archived_issue.html`, which also fill in the tags of link previews.
+#[derive(Serialize)]
+pub struct ArchivedIssuePage<'a> {
+  pub title: &'a str,
+  pub newsletter_name: &'a str,
+  /// The canonical URL of the issue.
+  pub url: &'a str,
+  pub description: &'a str,
+  /// RFC 3339.
+  pub published_at: String,
+  /// e.g. `March 1, 2022`.
+  pub date: String,
+  /// HTML of the issue, as it was sent.
+  pub content: &'a str,
+  pub feeds: Vec<FeedLink>,
+  /// Private issues are kept out of search engines.
+  pub public: bool,
+}
+
+/// The page templates of the deployment: the built-in ones, except for those replaced
+/// by a file of the same name in the `pages` directory of the templates directory.
+/// Values are escaped, unless marked `| safe`.
+#[derive(Debug)]
+pub struct PageTemplates {
+  tera: Tera,
+}
+
+impl PageTemplates {
+  /// Fails if a template doesn't parse, or uses a variable it isn't given,
+  /// so that a broken override is noticed at startup rather than by a visitor.
+  pub fn new(directory: Option<&Path>) -> Result<Self, anyhow::Error> {
+    let directory = directory.map(|d| d.join(PAGES_DIRECTORY));
+    let mut templates = Vec::with_capacity(BUILT_IN_TEMPLATES.len());
+    for (name, built_in) in BUILT_IN_TEMPLATES {
+      let path = directory.as_ref().map(|d| d.join(name));
+      let source = match path.filter(|p| p.is_file()) {
+        Some(path) => std::fs::read_to_string(&path)
+          .with_context(|| format!("Failed to read the template {}.", path.display()))?,
+        None => built_in.to_string(),
+      };
+      templates.push((name, source));
+    }
+
+    let mut tera = Tera::default();
+    // Tera's own escaping turns `/` into `&#x2F;`, which makes links unreadable.
+    tera.set_escape_fn(html::escape);
+    tera
+      .add_raw_templates(templates)
+      .context("Failed to parse the page templates.")?;
+    let templates = Self { tera };
+    templates.validate()?;
+    Ok(templates)
+  }
+
+  /// Renders every template with example values.
+  fn validate(&self) -> Result<(), anyhow::Error> {
+    let mut errors = SubscribeFormErrors::default();
+    errors.name.push("name cannot be empty!".to_string());
+    self.subscribe(&SubscribePage {
+      newsletters: &[
+        NewsletterChoice {
+          slug: "default",
+          name: "Our newsletter",
+        },
+        NewsletterChoice {
+          slug: "announcements",
+          name: "Announcements",
+        },
+      ],
+      list: "default",
+      name: "",
+      email: "ursula@example.com",
+      attributes: &BTreeMap::from([("city".to_string(), "Portland".to_string())]),
+      errors: &errors,
+      form_token: "1700000000000.nonce.signature",
+      proof_of_work_difficulty: 16,
+    })?;
+    self.subscribe_pending(&SubscribePendingPage {
+      name: "Ursula Le Guin",
+      email: "ursula@example.com",
+      newsletter_name: "Our newsletter",
+    })?;
+    self.subscription_confirmed(&SubscriptionConfirmedPage {
+      newsletter_name: "Our newsletter",
+      preferences_link: "https://example.com/link",
+    })?;
+    let mut errors = PreferencesFormErrors::default();
+    errors
+      .email
+      .push("That address is already subscribed.".to_string());
+    self.preferences(&PreferencesPage {
+      token: "token",
+      name: "Ursula Le Guin",
+      email: "ursula@example.com",
+      frequencies: vec![FrequencyChoice {
+        value: "every_issue",
+        description: "Every issue",
+        selected: true,
+      }],
+      lists: vec![ListChoice {
+        slug: "default",
+        name: "Our newsletter",
+        subscribed: true,
+      }],
+      paused_until: Some("March 1, 2022".to_string()),
+      tracking_opt_out: false,
+      max_pause_weeks: 52,
+      message: Some("Your preferences have been saved."),
+      errors: &errors,
+    })?;
+    self.email_change_confirmed(&EmailChangePage {
+      new_email: "ursula@example.com",
+    })?;
+    self.email_change_taken(&EmailChangePage {
+      new_email: "ursula@example.com",
+    })?;
+    self.archive(&ArchivePage {
+      query: "rust",
+      issues: vec![ArchiveItem {
+        slug: "our-first-issue",
+        title: "Our first issue",
+        newsletter_name: "Our newsletter",
+        published_at: "2022-03-01T09:00:00+00:00".to_string(),
+        date: "March 1, 2022".to_string(),
+        snippet: Some("Why <mark>Rust</mark>?"),
+      }],
+      newer_link: Some("/archive?q=rust&page=1".to_string()),
+      older_link: Some("/archive?q=rust&page=3".to_string()),
+    })?;
+    self.archived_issue(&ArchivedIssuePage {
+      title: "Our first issue",
+      newsletter_name: "Our newsletter",
+      url: "https://example.com/archive/our-first-issue",
+      description: "What's new this week",
+      published_at: "2022-03-01T09:00:00+00:00".to_string(),
+      date: "March 1, 2022".to_string(),
+      content: "<p>Hello reader</p>",
+      feeds: vec![FeedLink {
+        media_type: "application/rss+xml",
+        url: "https://example.com/feeds/default/rss.xml".to_string(),
+      }],
+      public: false,
+    })?;
+    self.invalid_link()?;
+    Ok(())
+  }
+
+  pub fn subscribe(&self, page: &SubscribePage) -> Result<String, anyhow::Error> {
+    self.render("subscribe.html", page)
+  }
+
+  pub fn subscribe_pending(&self, page: &SubscribePendingPage) -> Result<String, anyhow::Error> {
+    self.render("subscribe_pending.html", page)
+  }
+
+  pub fn subscription_confirmed(
+    &self,
+    page: &SubscriptionConfirmedPage,
+  ) -> Result<String, anyhow::Error> {
+    self.render("subscription_confirmed.html", page)
+  }
+
+  pub fn preferences(&self, page: &PreferencesPage) -> Result<String, anyhow::Error> {
+    self.render("preferences.html", page)
+  }
+
+  pub fn email_change_confirmed(&self, page: &EmailChangePage) -> Result<String, anyhow::Error> {
+    self.render("email_change_confirmed.html", page)
+  }
+
+  pub fn email_change_taken(&self, page: &EmailChangePage) -> Result<String, anyhow::Error> {
+    self.render("email_change_taken.html", page)
+  }
+
+  pub fn archive(&self, page: &ArchivePage) -> Result<String, anyhow::Error> {
+    self.render("archive.html", page)
+  }
+
+  pub fn archived_issue(&self, page: &ArchivedIssuePage) -> Result<String, anyhow::Error> {
+    self.render("archived_issue.html", page)
+  }
+
+  pub fn invalid_link(&self) -> Result<String, anyhow::Error> {
+    self.render("invalid_link.html", &serde_json::json!({}))
+  }
+
+  fn render(&self, name: &str, variables: &impl Serialize) -> Result<String, anyhow::Error> {
+    let context = tera::Context::from_serialize(variables)
+      .with_context(|| format!("Failed to prepare the variables of the {} template.", name))?;
+    self
+      .tera
+      .render(name, &context)
+      .with_context(|| format!("Failed to render the {} template.", name))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{PageTemplates, SubscribePendingPage};
+  use claim::{assert_err, assert_ok};
+  use std::path::PathBuf;
+
+  /// A fresh templates directory containing the given page templates.
+  fn templates_directory(templates: &[(&str, &str)]) -> PathBuf {
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(directory.join("pages")).unwrap();
+    for (name, source) in templates {
+      std::fs::write(directory.join("pages").join(name), source).unwrap();
+    }
+    directory
+  }
+
+  fn pending(templates: &PageTemplates) -> String {
+    templates
+      .subscribe_pending(&SubscribePendingPage {
+        name: "Ursula",
+        email: "ursula@example.com",
+        newsletter_name: "Rust & Friends",
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn the_built_in_templates_are_valid() {
+    assert_ok!(PageTemplates::new(None));
+  }
+
+  #[test]
+  fn pages_are_rendered_in_the_layout_with_escaped_values() {
+    let page = pending(&PageTemplates::new(None).unwrap());
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("<title>Check your inbox</title>"));
+    assert!(page.contains("Rust &amp; Friends"));
+  }
+
+  #[test]
+  fn the_layout_can_be_overridden() {
+    let directory = templates_directory(&[(
+      "layout.html",
+      "<body class=\"branded\">{% block content %}{% endblock content %}</body>",
+    )]);
+    let page = pending(&PageTemplates::new(Some(&directory)).unwrap());
+    assert!(page.starts_with("<body class=\"branded\">"));
+    assert!(page.contains("Check your inbox"));
+  }
+
+  #[test]
+  fn invalid_overrides_are_rejected() {
+    for source in [
+      "{% extends \"layout.html\" %",
+      "<p>{{ unknown_variable }}</p>",
+    ] {
+      let directory = templates_directory(&[("subscribe_pending.html", source)]);
+      assert_err!(PageTemplates::new(Some(&directory)), "{}", source);
+    }
+  }
+}
@@ -0,0 +1,157 @@
+use actix_http::{header, StatusCode};
+use actix_web::{
+  error::{InternalError, JsonPayloadError},
+  HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// An error as API clients see it: an RFC 7807 problem, with a machine-readable `code`
+/// on top of the standard members. The `type` of a problem is a relative URI made of its code.
+/// Anything more a client needs to act on the error, like which fields are invalid,
+/// goes in extension members.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+  #[serde(rename = "type")]
+  problem_type: String,
+  title: String,
+  status: u16,
+  code: &'static str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  detail: Option<String>,
+  #[serde(flatten)]
+  extensions: Map<String, Value>,
+}
+
+impl Problem {
+  /// `code` is in snake case, e.g. `invalid_subscription`.
+  pub fn new(status: StatusCode, code: &'static str, title: &str) -> Self {
+    Self {
+      problem_type: format!("/problems/{}", code.replace('_', "-")),
+      title: title.to_string(),
+      status: status.as_u16(),
+      code,
+      detail: None,
+      extensions: Map::new(),
+    }
+  }
+
+  /// Explains this occurrence of the problem, unlike the title which is the same for all.
+  pub fn detail(mut self, detail: impl ToString) -> Self {
+    self.detail = Some(detail.to_string());
+    self
+  }
+
+  /// Adds the fields of `members`, which must serialize as an object, as extension members.
+  pub fn extend(mut self, members: &impl Serialize) -> Self {
+    match serde_json::to_value(members) {
+      Ok(Value::Object(members)) => self.extensions.extend(members),
+      _ => tracing::error!(code = self.code, "Problem extensions are not an object"),
+    }
+    self
+  }
+
+  pub fn status(&self) -> StatusCode {
+    StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+  }
+
+  pub fn response(&self) -> HttpResponse {
+    HttpResponse::build(self.status())
+      .content_type(PROBLEM_CONTENT_TYPE)
+      .body(serde_json::to_string(self).expect("A problem can always be serialized"))
+  }
+}
+
+/// The problem for any error the client can do nothing about, which keeps the cause
+/// out of the response since it is only meant for our logs.
+pub fn unexpected_problem() -> Problem {
+  Problem::new(
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "unexpected_error",
+    "Something went wrong on our side.",
+  )
+}
+
+/// Answers JSON bodies which can't be read, e.g. with a missing field, with a problem
+/// rather than a plain text error. See `JsonConfig::error_handler`.
+pub fn json_error_handler(error: JsonPayloadError, _request: &HttpRequest) -> actix_web::Error {
+  let problem = Problem::new(
+    error.status_code(),
+    "malformed_request",
+    "The request body is invalid.",
+  )
+  .detail(&error);
+  InternalError::from_response(error, problem.response()).into()
+}
+
+/// Whether the body of the request is JSON, e.g. `application/json`.
+pub fn is_json(request: &HttpRequest) -> bool {
+  is_json_media_type(request.content_type())
+}
+
+/// Whether the client would rather have JSON than a page: it lists a JSON media type
+/// in `Accept` before any html one. Browsers list html first, or only send `*/*`.
+pub fn accepts_json(request: &HttpRequest) -> bool {
+  let accept = match request.headers().get(header::ACCEPT).map(|a| a.to_str()) {
+    Some(Ok(accept)) => accept,
+    _ => return false,
+  };
+  accept
+    .split(',')
+    .map(|media_range| media_range.split(';').next().unwrap_or_default().trim())
+    .find(|media_type| is_json_media_type(media_type) || *media_type == "text/html")
+    .is_some_and(is_json_media_type)
+}
+
+fn is_json_media_type(media_type: &str) -> bool {
+  media_type == "application/json" || media_type.ends_with("+json")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{accepts_json, Problem};
+  use actix_http::StatusCode;
+  use actix_web::test::TestRequest;
+
+  #[test]
+  fn problems_have_the_members_of_rfc_7807_and_their_extensions() {
+    let problem = Problem::new(
+      StatusCode::BAD_REQUEST,
+      "invalid_subscription",
+      "The subscription is invalid.",
+    )
+    .detail("name cannot be empty!")
+    .extend(&serde_json::json!({ "errors": { "name": ["name cannot be empty!"] } }));
+    assert_eq!(
+      serde_json::to_value(&problem).unwrap(),
+      serde_json::json!({
+        "type": "/problems/invalid-subscription",
+        "title": "The subscription is invalid.",
+        "status": 400,
+        "code": "invalid_subscription",
+        "detail": "name cannot be empty!",
+        "errors": { "name": ["name cannot be empty!"] },
+      })
+    );
+  }
+
+  #[test]
+  fn json_is_accepted_when_listed_before_html() {
+    let accepts = |accept: &str| {
+      accepts_json(
+        &TestRequest::default()
+          .insert_header(("Accept", accept))
+          .to_http_request(),
+      )
+    };
+    assert!(accepts("application/json"));
+    assert!(accepts("application/problem+json, text/html;q=0.9"));
+    assert!(!accepts(
+      "text/html,application/xhtml+xml,application/json;q=0.8"
+    ));
+    assert!(!accepts("*/*"));
+    assert!(!accepts_json(&TestRequest::default().to_http_request()));
+  }
+}
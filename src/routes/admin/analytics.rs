@@ -0,0 +1,185 @@
+use actix_http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  analytics::{
+    query_growth, query_issue_metrics, query_link_clicks, to_csv, IssueMetrics, LinkClicks,
+  },
+  authentication::authenticate,
+  routes::admin::AdminError,
+};
+
+/// The longest period growth is reported over at once.
+const MAX_GROWTH_DAYS: i64 = 366;
+/// The period growth is reported over when none is given, today included.
+const DEFAULT_GROWTH_DAYS: i64 = 30;
+
+/// Every report is JSON, unless asked for as CSV with `?format=csv`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+  #[default]
+  Json,
+  Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssuesParameters {
+  /// Slug of a newsletter, to report on its issues only.
+  list: Option<String>,
+  #[serde(default)]
+  format: ReportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportParameters {
+  #[serde(default)]
+  format: ReportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GrowthParameters {
+  /// First day of the period, in UTC.
+  from: Option<NaiveDate>,
+  /// Last day of the period, in UTC, today if missing.
+  to: Option<NaiveDate>,
+  #[serde(default)]
+  format: ReportFormat,
+}
+
+/// The metrics of an issue, and the clicks of each of its links.
+/// As CSV, the report has a row per link, each starting with the metrics of the issue.
+#[derive(Serialize)]
+pub struct IssueReport {
+  #[serde(flatten)]
+  metrics: IssueMetrics,
+  links: Vec<LinkClicks>,
+}
+
+/// The columns of a link in the CSV issue report, empty on the only row of an issue without links.
+#[derive(Serialize)]
+struct LinkColumns<'a> {
+  url: Option<&'a str>,
+  link_unique_clicks: Option<i64>,
+  link_clicks: Option<i64>,
+}
+
+impl IssueReport {
+  fn csv_rows(&self) -> Vec<(&IssueMetrics, LinkColumns<'_>)> {
+    if self.links.is_empty() {
+      let no_link = LinkColumns {
+        url: None,
+        link_unique_clicks: None,
+        link_clicks: None,
+      };
+      return vec![(&self.metrics, no_link)];
+    }
+    self
+      .links
+      .iter()
+      .map(|link| {
+        let columns = LinkColumns {
+          url: Some(&link.url),
+          link_unique_clicks: Some(link.unique_clicks),
+          link_clicks: Some(link.clicks),
+        };
+        (&self.metrics, columns)
+      })
+      .collect()
+  }
+}
+
+/// Lists the metrics of every issue, most recent first.
+#[tracing::instrument(name = "Get issue metrics", skip(request, pool))]
+pub async fn get_issues(
+  parameters: web::Query<IssuesParameters>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  authenticate(&request, &pool).await?;
+  let issues = query_issue_metrics(&pool, None, parameters.list.as_deref()).await?;
+  report_response(parameters.format, "issues", &issues, &issues)
+}
+
+/// Reports on a published issue, with the opens, clicks and unsubscribes recorded since.
+#[tracing::instrument(name = "Get issue report", skip(request, pool))]
+pub async fn get_issue_report(
+  issue_id: web::Path<Uuid>,
+  parameters: web::Query<ReportParameters>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  authenticate(&request, &pool).await?;
+  let issue_id = issue_id.into_inner();
+  let metrics = query_issue_metrics(&pool, Some(issue_id), None)
+    .await?
+    .pop()
+    .ok_or_else(|| AdminError::NotFound(format!("There is no issue {}.", issue_id)))?;
+  let report = IssueReport {
+    metrics,
+    links: query_link_clicks(&pool, issue_id).await?,
+  };
+  report_response(
+    parameters.format,
+    &format!("issue-{}", issue_id),
+    &report.csv_rows(),
+    &report,
+  )
+}
+
+/// Reports how the subscriber base changed every day, over the last 30 days unless asked otherwise.
+#[tracing::instrument(name = "Get growth", skip(request, pool))]
+pub async fn get_growth(
+  parameters: web::Query<GrowthParameters>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  authenticate(&request, &pool).await?;
+  let to = parameters.to.unwrap_or_else(|| Utc::today().naive_utc());
+  let from = parameters
+    .from
+    .unwrap_or(to - Duration::days(DEFAULT_GROWTH_DAYS - 1));
+  if from > to {
+    return Err(AdminError::ValidationError(
+      "from cannot be after to".to_string(),
+    ));
+  }
+  if (to - from).num_days() >= MAX_GROWTH_DAYS {
+    return Err(AdminError::ValidationError(format!(
+      "Growth can be reported over at most {} days.",
+      MAX_GROWTH_DAYS
+    )));
+  }
+  let growth = query_growth(&pool, from, to).await?;
+  report_response(
+    parameters.format,
+    &format!("growth-{}-{}", from, to),
+    &growth,
+    &growth,
+  )
+}
+
+/// Responds with the JSON body, or with the rows as a CSV file of the given name.
+fn report_response<T: Serialize>(
+  format: ReportFormat,
+  filename: &str,
+  rows: &[T],
+  json: &impl Serialize,
+) -> Result<HttpResponse, AdminError> {
+  match format {
+    ReportFormat::Json => Ok(HttpResponse::Ok().json(json)),
+    ReportFormat::Csv => Ok(
+      HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+          header::CONTENT_DISPOSITION,
+          format!("attachment; filename=\"{}.csv\"", filename),
+        ))
+        .body(to_csv(rows)?),
+    ),
+  }
+}
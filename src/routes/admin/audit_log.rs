@@ -0,0 +1,84 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  audit::{
+    query_audit_log, record_audit_event, AuditAction, AuditContext, AuditEvent, AuditLogEntry,
+    AuditLogFilter,
+  },
+  authentication::authenticate,
+  routes::admin::AdminError,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogParameters {
+  actor: Option<Uuid>,
+  action: Option<String>,
+  from: Option<DateTime<Utc>>,
+  to: Option<DateTime<Utc>>,
+  /// Pages start at 1.
+  page: Option<i64>,
+  per_page: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogPage {
+  entries: Vec<AuditLogEntry>,
+  page: i64,
+  per_page: i64,
+  total: i64,
+}
+
+/// Lists audit log entries, most recent first, optionally filtered by actor, action and date range.
+#[tracing::instrument(name = "Read audit log", skip(request, pool))]
+pub async fn read_audit_log(
+  parameters: web::Query<AuditLogParameters>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+
+  let parameters = parameters.into_inner();
+  let page = parameters.page.unwrap_or(1);
+  let per_page = parameters.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+  if page < 1 {
+    return Err(AdminError::ValidationError(
+      "page must be at least 1".to_string(),
+    ));
+  }
+  if !(1..=MAX_PAGE_SIZE).contains(&per_page) {
+    return Err(AdminError::ValidationError(format!(
+      "per_page must be between 1 and {}",
+      MAX_PAGE_SIZE
+    )));
+  }
+
+  let filter = AuditLogFilter {
+    actor: parameters.actor,
+    action: parameters.action,
+    from: parameters.from,
+    to: parameters.to,
+  };
+  let (entries, total) = query_audit_log(&pool, &filter, per_page, (page - 1) * per_page).await?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::ReadAuditLog)
+      .details(serde_json::json!({ "filter": filter, "page": page })),
+  )
+  .await?;
+
+  Ok(HttpResponse::Ok().json(AuditLogPage {
+    entries,
+    page,
+    per_page,
+    total,
+  }))
+}
@@ -0,0 +1,106 @@
+mod analytics;
+mod audit_log;
+mod newsletters;
+mod search;
+mod subscribers;
+mod totp;
+
+pub use analytics::*;
+pub use audit_log::*;
+pub use newsletters::*;
+pub use search::*;
+pub use subscribers::*;
+pub use totp::*;
+
+use actix_http::{header::HeaderValue, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use reqwest::header;
+
+use crate::{
+  authentication::{insert_retry_after, AuthError},
+  problem::{unexpected_problem, Problem},
+  routes::error_chain_fmt,
+};
+
+/// Errors which may occur in any of the administrative endpoints.
+#[derive(thiserror::Error)]
+pub enum AdminError {
+  #[error("Authentication failed.")]
+  AuthError(#[source] anyhow::Error),
+  #[error("Too many failed login attempts.")]
+  TooManyAttempts(std::time::Duration),
+  #[error("{0}")]
+  ValidationError(String),
+  #[error("{0}")]
+  NotFound(String),
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl From<AuthError> for AdminError {
+  fn from(e: AuthError) -> Self {
+    match e {
+      AuthError::InvalidCredentials(_) => AdminError::AuthError(e.into()),
+      AuthError::TooManyAttempts(retry_after) => AdminError::TooManyAttempts(retry_after),
+      AuthError::UnexpectedError(_) => AdminError::UnexpectedError(e.into()),
+    }
+  }
+}
+
+impl ResponseError for AdminError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      AdminError::AuthError(_) => StatusCode::UNAUTHORIZED,
+      AdminError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+      AdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      AdminError::NotFound(_) => StatusCode::NOT_FOUND,
+      AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    let status_code = self.status_code();
+    match self {
+      AdminError::AuthError(_) => {
+        let mut resp = Problem::new(
+          status_code,
+          "authentication_failed",
+          "Authentication failed.",
+        )
+        .response();
+        let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+        resp
+          .headers_mut()
+          .insert(header::WWW_AUTHENTICATE, header_value);
+        resp
+      }
+      AdminError::TooManyAttempts(retry_after) => {
+        let mut resp = Problem::new(
+          status_code,
+          "too_many_attempts",
+          "Too many failed login attempts.",
+        )
+        .response();
+        insert_retry_after(&mut resp, *retry_after);
+        resp
+      }
+      AdminError::ValidationError(message) => {
+        Problem::new(status_code, "invalid_request", "The request is invalid.")
+          .detail(message)
+          .response()
+      }
+      AdminError::NotFound(message) => {
+        Problem::new(status_code, "not_found", "There is no such resource.")
+          .detail(message)
+          .response()
+      }
+      AdminError::UnexpectedError(_) => unexpected_problem().response(),
+    }
+  }
+}
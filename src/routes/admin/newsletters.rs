@@ -0,0 +1,134 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  audit::{record_audit_event, AuditAction, AuditContext, AuditEvent},
+  authentication::authenticate,
+  newsletters::{
+    insert_newsletter, list_newsletters, set_issue_visibility, set_open_tracking, NewNewsletter,
+  },
+  routes::admin::AdminError,
+};
+
+#[derive(Deserialize)]
+pub struct NewsletterData {
+  slug: String,
+  name: String,
+  sender_name: String,
+  confirmation_copy: String,
+}
+
+#[derive(Deserialize)]
+pub struct TrackingData {
+  track_opens: bool,
+}
+
+#[derive(Deserialize)]
+pub struct VisibilityData {
+  public: bool,
+}
+
+/// Creates a newsletter, which people can then subscribe to separately from the others.
+#[tracing::instrument(name = "Create newsletter", skip(body, request, pool))]
+pub async fn create_newsletter(
+  body: web::Json<NewsletterData>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+
+  let body = body.into_inner();
+  let new_newsletter = NewNewsletter::parse(
+    body.slug,
+    body.name,
+    body.sender_name,
+    body.confirmation_copy,
+  )
+  .map_err(|es| AdminError::ValidationError(es.join(", ")))?;
+  let newsletter = insert_newsletter(&pool, &new_newsletter)
+    .await?
+    .ok_or_else(|| {
+      AdminError::ValidationError(format!(
+        "There already is a newsletter {}.",
+        new_newsletter.slug.as_ref()
+      ))
+    })?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::CreateNewsletter).target(&newsletter.slug),
+  )
+  .await?;
+
+  Ok(HttpResponse::Created().json(newsletter))
+}
+
+/// Lists every newsletter, oldest first.
+#[tracing::instrument(name = "List newsletters", skip(request, pool))]
+pub async fn get_newsletters(
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  authenticate(&request, &pool).await?;
+  Ok(HttpResponse::Ok().json(list_newsletters(&pool).await?))
+}
+
+/// Turns the open tracking of a newsletter's issues on or off.
+/// Subscribers who opted out are never tracked either way.
+#[tracing::instrument(name = "Update newsletter tracking", skip(body, request, pool))]
+pub async fn update_newsletter_tracking(
+  slug: web::Path<String>,
+  body: web::Json<TrackingData>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let newsletter = set_open_tracking(&pool, &slug, body.track_opens)
+    .await?
+    .ok_or_else(|| AdminError::NotFound(format!("There is no newsletter {}.", slug)))?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::UpdateNewsletterTracking)
+      .target(&newsletter.slug)
+      .details(serde_json::json!({ "track_opens": newsletter.track_opens })),
+  )
+  .await?;
+
+  Ok(HttpResponse::Ok().json(newsletter))
+}
+
+/// Lists a published issue on the web archive for anyone to read, or takes it off.
+/// Its recipients can still read a private issue from the link in their email.
+#[tracing::instrument(name = "Update issue visibility", skip(body, request, pool))]
+pub async fn update_issue_visibility(
+  issue_id: web::Path<Uuid>,
+  body: web::Json<VisibilityData>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let issue_id = issue_id.into_inner();
+  let issue = set_issue_visibility(&pool, issue_id, body.public)
+    .await?
+    .ok_or_else(|| AdminError::NotFound(format!("There is no issue {}.", issue_id)))?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::UpdateIssueVisibility)
+      .target(&issue.slug)
+      .details(serde_json::json!({ "issue_id": issue.id, "public": body.public })),
+  )
+  .await?;
+
+  Ok(HttpResponse::Ok().json(serde_json::json!({
+    "id": issue.id,
+    "slug": issue.slug,
+    "public": body.public,
+  })))
+}
@@ -0,0 +1,59 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+  authentication::authenticate,
+  routes::admin::AdminError,
+  search::{self, SearchResult, SearchScope},
+};
+
+const PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParameters {
+  /// See `search::search_issues` for how to write it.
+  q: String,
+  /// Pages start at 1.
+  page: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchPage {
+  results: Vec<SearchResult>,
+  page: i64,
+  per_page: i64,
+}
+
+/// Searches every issue, private ones included, best matches first.
+#[tracing::instrument(name = "Search issues as an admin", skip(request, pool))]
+pub async fn search_issues(
+  parameters: web::Query<SearchParameters>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  authenticate(&request, &pool).await?;
+  let query = parameters.q.trim();
+  if query.is_empty() {
+    return Err(AdminError::ValidationError("q cannot be empty".to_string()));
+  }
+  let page = parameters.page.unwrap_or(1);
+  if page < 1 {
+    return Err(AdminError::ValidationError(
+      "page must be at least 1".to_string(),
+    ));
+  }
+  let results = search::search_issues(
+    &pool,
+    query,
+    SearchScope::All,
+    (page - 1) * PAGE_SIZE,
+    PAGE_SIZE,
+  )
+  .await?;
+  Ok(HttpResponse::Ok().json(SearchPage {
+    results,
+    page,
+    per_page: PAGE_SIZE,
+  }))
+}
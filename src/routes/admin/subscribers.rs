@@ -0,0 +1,130 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  audit::{record_audit_event, AuditAction, AuditContext, AuditEvent},
+  authentication::authenticate,
+  domain::{SubscriberAttributes, SubscriberTag},
+  routes::admin::AdminError,
+};
+
+/// Replaces every attribute of a subscriber with the given ones.
+#[tracing::instrument(name = "Update subscriber attributes", skip(body, request, pool))]
+pub async fn update_subscriber_attributes(
+  subscriber_id: web::Path<Uuid>,
+  body: web::Json<serde_json::Value>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let subscriber_id = subscriber_id.into_inner();
+  let attributes = SubscriberAttributes::parse(body.into_inner())
+    .map_err(|es| AdminError::ValidationError(es.join(", ")))?;
+
+  let updated = sqlx::query!(
+    r#"
+    UPDATE subscriptions SET attributes = $1 WHERE id = $2
+    RETURNING attributes
+    "#,
+    attributes.into_value(),
+    subscriber_id,
+  )
+  .fetch_optional(pool.get_ref())
+  .await
+  .context("Failed to update a subscriber's attributes.")?
+  .ok_or_else(|| AdminError::NotFound(format!("There is no subscriber {}.", subscriber_id)))?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::UpdateSubscriberAttributes)
+      .target(subscriber_id.to_string())
+      .details(serde_json::json!({ "attributes": updated.attributes })),
+  )
+  .await?;
+
+  Ok(HttpResponse::Ok().json(updated.attributes))
+}
+
+/// Tags a subscriber. Tagging a subscriber twice with the same tag has no further effect.
+#[tracing::instrument(name = "Tag subscriber", skip(request, pool))]
+pub async fn tag_subscriber(
+  path: web::Path<(Uuid, String)>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let (subscriber_id, tag) = path.into_inner();
+  let tag = SubscriberTag::parse(tag).map_err(AdminError::ValidationError)?;
+  ensure_subscriber_exists(&pool, subscriber_id).await?;
+
+  sqlx::query!(
+    r#"
+    INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (subscriber_id, tag) DO NOTHING
+    "#,
+    subscriber_id,
+    tag.as_ref(),
+    Utc::now(),
+  )
+  .execute(pool.get_ref())
+  .await
+  .context("Failed to tag a subscriber.")?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::TagSubscriber)
+      .target(subscriber_id.to_string())
+      .details(serde_json::json!({ "tag": tag.as_ref() })),
+  )
+  .await?;
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Removes a tag from a subscriber, whether they had it or not.
+#[tracing::instrument(name = "Untag subscriber", skip(request, pool))]
+pub async fn untag_subscriber(
+  path: web::Path<(Uuid, String)>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let (subscriber_id, tag) = path.into_inner();
+  let tag = SubscriberTag::parse(tag).map_err(AdminError::ValidationError)?;
+  ensure_subscriber_exists(&pool, subscriber_id).await?;
+
+  sqlx::query!(
+    "DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = $2",
+    subscriber_id,
+    tag.as_ref(),
+  )
+  .execute(pool.get_ref())
+  .await
+  .context("Failed to untag a subscriber.")?;
+
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::UntagSubscriber)
+      .target(subscriber_id.to_string())
+      .details(serde_json::json!({ "tag": tag.as_ref() })),
+  )
+  .await?;
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+async fn ensure_subscriber_exists(pool: &PgPool, subscriber_id: Uuid) -> Result<(), AdminError> {
+  sqlx::query!("SELECT id FROM subscriptions WHERE id = $1", subscriber_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a subscriber.")?
+    .map(|_| ())
+    .ok_or_else(|| AdminError::NotFound(format!("There is no subscriber {}.", subscriber_id)))
+}
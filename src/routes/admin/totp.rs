@@ -0,0 +1,75 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+  audit::{record_audit_event, AuditAction, AuditContext, AuditEvent},
+  authentication::{
+    authenticate, confirm_totp_enrollment, start_totp_enrollment, TotpEnrollmentError,
+  },
+  routes::admin::AdminError,
+};
+
+#[derive(Serialize)]
+pub struct TotpEnrollment {
+  secret: String,
+  provisioning_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpConfirmation {
+  code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+  recovery_codes: Vec<String>,
+}
+
+/// Starts two-factor enrollment for the authenticated user.
+/// The returned URI is meant to be scanned into an authenticator app (usually as a QR code),
+/// after which the enrollment must be confirmed with a code from the app.
+#[tracing::instrument(name = "Enroll in TOTP", skip(request, pool))]
+pub async fn enroll_totp(
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let secret = start_totp_enrollment(user.user_id, &pool).await?;
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::EnrollTotp).target(&user.username),
+  )
+  .await?;
+
+  Ok(HttpResponse::Ok().json(TotpEnrollment {
+    secret: secret.encode(),
+    provisioning_uri: secret.provisioning_uri(&user.username),
+  }))
+}
+
+/// Confirms the pending enrollment, after which a one-time code is required to log in.
+/// Responds with recovery codes, which are never shown again.
+#[tracing::instrument(name = "Confirm TOTP enrollment", skip(body, request, pool))]
+pub async fn confirm_totp(
+  body: web::Json<TotpConfirmation>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+  let user = authenticate(&request, &pool).await?;
+  let recovery_codes = confirm_totp_enrollment(user.user_id, body.code.trim(), &pool)
+    .await
+    .map_err(|e| match e {
+      TotpEnrollmentError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+      e => AdminError::ValidationError(e.to_string()),
+    })?;
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::ConfirmTotp).target(&user.username),
+  )
+  .await?;
+
+  Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
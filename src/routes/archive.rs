@@ -0,0 +1,304 @@
+use actix_http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  feeds::{feed_url, FeedFormat},
+  html,
+  page_templates::{ArchiveItem, ArchivePage, ArchivedIssuePage, FeedLink, PageTemplates},
+  routes::{error_chain_fmt, html_page},
+  search::{search_issues, SearchScope},
+  startup::ApplicationBaseUrl,
+  tracking::TrackingKey,
+};
+
+/// Issues listed on each page of the archive.
+const PAGE_SIZE: i64 = 20;
+/// The longest description shown in link previews, in characters.
+const MAX_DESCRIPTION_LENGTH: usize = 200;
+
+#[derive(Deserialize)]
+pub struct ArchiveParameters {
+  /// Starting at 1, with the most recent issues or the best results.
+  page: Option<i64>,
+  /// Words to search the issues for. See `search::search_issues`.
+  q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ArchivedIssueParameters {
+  /// Signature from the link in the email, which lets its recipients read a private issue.
+  key: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+  #[error("There is no such page.")]
+  NotFound,
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ArchiveError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl ResponseError for ArchiveError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      ArchiveError::NotFound => StatusCode::NOT_FOUND,
+      ArchiveError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+}
+
+struct ArchivedIssue {
+  id: Uuid,
+  slug: String,
+  title: String,
+  html_content: String,
+  preheader: Option<String>,
+  public: bool,
+  published_at: DateTime<Utc>,
+  newsletter_slug: String,
+  newsletter_name: String,
+}
+
+struct ArchiveEntry {
+  slug: String,
+  title: String,
+  published_at: DateTime<Utc>,
+  newsletter_name: String,
+}
+
+/// Lists the public issues of every newsletter, most recent first, or those matching
+/// the search in `q`, best first.
+#[tracing::instrument(name = "Get archive", skip(parameters, pool, page_templates))]
+pub async fn archive(
+  parameters: web::Query<ArchiveParameters>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, ArchiveError> {
+  let page = parameters.page.unwrap_or(1);
+  if page < 1 {
+    return Err(ArchiveError::NotFound);
+  }
+  let query = parameters
+    .q
+    .as_deref()
+    .map(str::trim)
+    .filter(|q| !q.is_empty());
+  let offset = (page - 1) * PAGE_SIZE;
+  let entries;
+  let results;
+  let mut issues: Vec<ArchiveItem> = match query {
+    None => {
+      entries = get_public_issues(&pool, offset, PAGE_SIZE + 1).await?;
+      entries
+        .iter()
+        .map(|entry| ArchiveItem {
+          slug: &entry.slug,
+          title: &entry.title,
+          newsletter_name: &entry.newsletter_name,
+          published_at: entry.published_at.to_rfc3339(),
+          date: entry.published_at.format("%B %-d, %Y").to_string(),
+          snippet: None,
+        })
+        .collect()
+    }
+    Some(query) => {
+      results = search_issues(&pool, query, SearchScope::Public, offset, PAGE_SIZE + 1).await?;
+      results
+        .iter()
+        .map(|result| ArchiveItem {
+          slug: &result.slug,
+          title: &result.title,
+          newsletter_name: &result.newsletter,
+          published_at: result.published_at.to_rfc3339(),
+          date: result.published_at.format("%B %-d, %Y").to_string(),
+          snippet: Some(&result.snippet),
+        })
+        .collect()
+    }
+  };
+  if issues.is_empty() && page > 1 {
+    return Err(ArchiveError::NotFound);
+  }
+  let has_more = issues.len() as i64 > PAGE_SIZE;
+  issues.truncate(PAGE_SIZE as usize);
+
+  let page = page_templates.archive(&ArchivePage {
+    query: query.unwrap_or_default(),
+    issues,
+    newer_link: (page > 1).then(|| archive_page_link(query, page - 1)),
+    older_link: has_more.then(|| archive_page_link(query, page + 1)),
+  })?;
+  Ok(html_page(StatusCode::OK, page))
+}
+
+fn archive_page_link(query: Option<&str>, page: i64) -> String {
+  let mut url = Url::parse("http://localhost/archive").expect("The URL is valid");
+  {
+    let mut pairs = url.query_pairs_mut();
+    if let Some(query) = query {
+      pairs.append_pair("q", query);
+    }
+    pairs.append_pair("page", &page.to_string());
+  }
+  format!("/archive?{}", url.query().unwrap_or_default())
+}
+
+/// Shows a published issue as it was sent, merge tags aside.
+/// Private issues are only shown to those who have the signed link from the email.
+#[tracing::instrument(
+  name = "Get archived issue",
+  skip(parameters, pool, page_templates, base_url, tracking_key)
+)]
+pub async fn archived_issue(
+  slug: web::Path<String>,
+  parameters: web::Query<ArchivedIssueParameters>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+  base_url: web::Data<ApplicationBaseUrl>,
+  tracking_key: web::Data<TrackingKey>,
+) -> Result<HttpResponse, ArchiveError> {
+  let issue = get_archived_issue(&pool, &slug)
+    .await?
+    .ok_or(ArchiveError::NotFound)?;
+  let has_key = parameters
+    .key
+    .as_deref()
+    .is_some_and(|key| tracking_key.verify_archive(issue.id, key));
+  if !issue.public && !has_key {
+    return Err(ArchiveError::NotFound);
+  }
+
+  let url = format!("{}/archive/{}", base_url.get_ref().as_ref(), issue.slug);
+  let description = issue
+    .preheader
+    .clone()
+    .filter(|preheader| !preheader.trim().is_empty())
+    .unwrap_or_else(|| excerpt(&issue.html_content));
+  let page = page_templates.archived_issue(&ArchivedIssuePage {
+    title: &issue.title,
+    newsletter_name: &issue.newsletter_name,
+    url: &url,
+    description: &description,
+    published_at: issue.published_at.to_rfc3339(),
+    date: issue.published_at.format("%B %-d, %Y").to_string(),
+    content: &issue.html_content,
+    feeds: FeedFormat::ALL
+      .iter()
+      .map(|format| FeedLink {
+        media_type: format.media_type(),
+        url: feed_url(base_url.get_ref().as_ref(), &issue.newsletter_slug, *format),
+      })
+      .collect(),
+    public: issue.public,
+  })?;
+  Ok(html_page(StatusCode::OK, page))
+}
+
+/// The start of the text of the html, on one line, for link previews.
+fn excerpt(content: &str) -> String {
+  let mut text = String::new();
+  let mut position = 0;
+  let mut hidden = false;
+  for tag in html::tags(content) {
+    if !hidden {
+      text.push_str(&content[position..tag.span.start]);
+    }
+    let is_inline = matches!(
+      tag.name.as_str(),
+      "a" | "b" | "code" | "em" | "i" | "s" | "small" | "span" | "strong" | "sub" | "sup" | "u"
+    );
+    if !is_inline {
+      text.push(' ');
+    }
+    if matches!(tag.name.as_str(), "style" | "script") {
+      hidden = !tag.closing;
+    }
+    position = tag.span.end;
+  }
+  if !hidden {
+    text.push_str(&content[position..]);
+  }
+  let text = html::unescape(&text)
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ");
+  match text.char_indices().nth(MAX_DESCRIPTION_LENGTH) {
+    Some((end, _)) => format!("{}…", text[..end].trim_end()),
+    None => text,
+  }
+}
+
+#[tracing::instrument(name = "Get public issues", skip(pool))]
+async fn get_public_issues(
+  pool: &PgPool,
+  offset: i64,
+  limit: i64,
+) -> Result<Vec<ArchiveEntry>, anyhow::Error> {
+  sqlx::query_as!(
+    ArchiveEntry,
+    r#"
+    SELECT i.slug, i.title, i.published_at, n.name AS newsletter_name
+    FROM newsletter_issues i
+    JOIN newsletters n ON n.id = i.newsletter_id
+    WHERE i.public
+    ORDER BY i.published_at DESC, i.id
+    OFFSET $1 LIMIT $2
+    "#,
+    offset,
+    limit,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to list the public issues.")
+}
+
+#[tracing::instrument(name = "Get archived issue", skip(pool))]
+async fn get_archived_issue(
+  pool: &PgPool,
+  slug: &str,
+) -> Result<Option<ArchivedIssue>, anyhow::Error> {
+  sqlx::query_as!(
+    ArchivedIssue,
+    r#"
+    SELECT i.id, i.slug, i.title, i.html_content, i.preheader, i.public, i.published_at,
+      n.slug AS newsletter_slug, n.name AS newsletter_name
+    FROM newsletter_issues i
+    JOIN newsletters n ON n.id = i.newsletter_id
+    WHERE i.slug = $1
+    "#,
+    slug,
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to fetch an archived issue.")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::excerpt;
+
+  #[test]
+  fn excerpts_are_the_start_of_the_text() {
+    assert_eq!(
+      excerpt(
+        r#"<style>p { color: red; }</style><h1>Hi</h1><p>Read <a href="https://example.com">this</a>&amp;<b>that</b>.</p>"#
+      ),
+      "Hi Read this&that."
+    );
+    let long = excerpt(&format!("<p>{}</p>", "é".repeat(300)));
+    assert_eq!(long.chars().count(), 201);
+    assert!(long.ends_with('…'));
+  }
+}
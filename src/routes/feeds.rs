@@ -0,0 +1,152 @@
+use actix_http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  feeds::{Feed, FeedEntry, FeedFormat},
+  newsletters::get_newsletter_by_slug,
+  routes::error_chain_fmt,
+  startup::ApplicationBaseUrl,
+};
+
+/// Issues listed in every feed, the most recent ones.
+const FEED_SIZE: i64 = 20;
+
+#[derive(thiserror::Error)]
+pub enum FeedError {
+  #[error("There is no such feed.")]
+  NotFound,
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for FeedError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl ResponseError for FeedError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      FeedError::NotFound => StatusCode::NOT_FOUND,
+      FeedError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+}
+
+/// Serves the public issues of a newsletter as RSS (`rss.xml`), Atom (`atom.xml`)
+/// or JSON Feed (`feed.json`).
+/// Feed readers poll, so whether the feed changed is worked out before rendering it:
+/// requests with a matching `If-None-Match` or a recent enough `If-Modified-Since`
+/// get a 304 with no body.
+#[tracing::instrument(name = "Get feed", skip(request, pool, base_url))]
+pub async fn feed(
+  path: web::Path<(String, String)>,
+  request: HttpRequest,
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, FeedError> {
+  let (list, file_name) = path.into_inner();
+  let format = FeedFormat::from_file_name(&file_name).ok_or(FeedError::NotFound)?;
+  let newsletter = get_newsletter_by_slug(&pool, &list)
+    .await?
+    .ok_or(FeedError::NotFound)?;
+
+  let updated_at = get_last_issue_change(&pool, newsletter.id)
+    .await?
+    .unwrap_or(newsletter.created_at);
+  let etag = format!(
+    "\"{}-{}\"",
+    format.file_name(),
+    updated_at.timestamp_nanos()
+  );
+  let last_modified = updated_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+  if is_fresh(&request, &etag, updated_at) {
+    return Ok(
+      HttpResponse::NotModified()
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::LAST_MODIFIED, last_modified))
+        .finish(),
+    );
+  }
+
+  let entries = get_feed_entries(&pool, newsletter.id).await?;
+  let body = format.render(&Feed {
+    base_url: base_url.get_ref().as_ref(),
+    list: &newsletter.slug,
+    name: &newsletter.name,
+    author: &newsletter.sender_name,
+    updated_at,
+    entries: &entries,
+  });
+  Ok(
+    HttpResponse::Ok()
+      .content_type(format.content_type())
+      .insert_header((header::ETAG, etag))
+      .insert_header((header::LAST_MODIFIED, last_modified))
+      .body(body),
+  )
+}
+
+/// Whether the client already has the feed, as told by its conditional headers.
+/// `If-None-Match` takes precedence over `If-Modified-Since`, as RFC 7232 asks.
+fn is_fresh(request: &HttpRequest, etag: &str, updated_at: DateTime<Utc>) -> bool {
+  let headers = request.headers();
+  if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+    return if_none_match.to_str().is_ok_and(|tags| {
+      tags.split(',').map(str::trim).any(|tag| {
+        // Weak comparison, which is what is asked for on a GET.
+        tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+      })
+    });
+  }
+  headers
+    .get(header::IF_MODIFIED_SINCE)
+    .and_then(|since| since.to_str().ok())
+    .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+    // Dates in headers have no fractions of a second.
+    .is_some_and(|since| updated_at.timestamp() <= since.timestamp())
+}
+
+/// When an issue of the newsletter was last published or changed, private ones included,
+/// since making an issue private takes it off the feed.
+#[tracing::instrument(name = "Get last issue change", skip(pool))]
+async fn get_last_issue_change(
+  pool: &PgPool,
+  newsletter_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+  let row = sqlx::query!(
+    "SELECT MAX(updated_at) AS updated_at FROM newsletter_issues WHERE newsletter_id = $1",
+    newsletter_id,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to fetch when the issues of a newsletter last changed.")?;
+  Ok(row.updated_at)
+}
+
+#[tracing::instrument(name = "Get feed entries", skip(pool))]
+async fn get_feed_entries(
+  pool: &PgPool,
+  newsletter_id: Uuid,
+) -> Result<Vec<FeedEntry>, anyhow::Error> {
+  sqlx::query_as!(
+    FeedEntry,
+    r#"
+    SELECT id, slug, title, html_content, preheader, published_at, updated_at
+    FROM newsletter_issues
+    WHERE newsletter_id = $1 AND public
+    ORDER BY published_at DESC, id
+    LIMIT $2
+    "#,
+    newsletter_id,
+    FEED_SIZE,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to list the issues of a feed.")
+}
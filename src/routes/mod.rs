@@ -1,9 +1,22 @@
+pub mod admin;
+mod archive;
+mod feeds;
 mod health_check;
 mod newsletters;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_email_change;
+mod subscriptions_preferences;
+mod subscriptions_widget;
+mod tracking;
 
+pub use archive::*;
+pub use feeds::*;
 pub use health_check::*;
 pub use newsletters::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_email_change::*;
+pub use subscriptions_preferences::*;
+pub use subscriptions_widget::*;
+pub use tracking::*;
@@ -1,33 +1,68 @@
-use actix_http::{
-  header::{HeaderMap, HeaderValue},
-  StatusCode,
-};
+use actix_http::{header::HeaderValue, StatusCode};
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use chrono::{DateTime, Utc};
 use reqwest::header;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
 
 use crate::{
-  domain::SubscriberEmail, email_client::EmailClient, routes::error_chain_fmt,
-  telemetry::spawn_blocking_with_tracing,
+  audit::{record_audit_event, AuditAction, AuditContext, AuditEvent},
+  authentication::{authenticate, insert_retry_after, AuthError, AuthenticatedUser},
+  domain::{DeliveryFrequency, SubscriberEmail},
+  email_client::EmailClient,
+  email_html,
+  email_templates::{EmailTemplates, NewsletterIssue},
+  html,
+  lint::{lint, LintGate, LintReport, LintedIssue},
+  markdown::MarkdownIssue,
+  merge_tags::{Escaping, MergeTemplate, Recipient},
+  newsletters::{
+    get_newsletter_by_slug, insert_issue, set_issue_delivered, NewIssue, Newsletter,
+    DEFAULT_NEWSLETTER_SLUG,
+  },
+  problem::{unexpected_problem, Problem},
+  routes::error_chain_fmt,
+  sanitization::HtmlSanitizer,
+  segments::{Segment, SubscriberProfile},
+  startup::ApplicationBaseUrl,
+  tracking::TrackingKey,
 };
 
 /// Data contained in the body of the request.
 /// The request is for Postmark's API, and thus title corresponds
 /// to the email subject, and content corresponds to the email's body.
+/// The content can be written in Markdown instead, from which both bodies are generated.
 #[derive(Deserialize)]
 pub struct BodyData {
-  title: String,
-  content: Content,
+  /// Taken from the Markdown's front matter when missing.
+  title: Option<String>,
+  /// Used instead of the Markdown's rendering when both are given.
+  content: Option<Content>,
+  /// Markdown, optionally starting with YAML front matter. See `MarkdownIssue`.
+  markdown: Option<String>,
+  /// Shown by email clients next to the title. Taken from the Markdown's front matter when missing.
+  preheader: Option<String>,
+  /// Slug of the newsletter to publish to, the default newsletter if missing.
+  list: Option<String>,
+  /// Sends to the newsletter's subscribers who match the segment only, instead of all of them.
+  segment: Option<Segment>,
+  /// Refuses to publish an issue with lint errors, or with any finding at all.
+  block_on: Option<LintGate>,
+  /// Whether the issue is listed on the web archive for anyone to read, which it is unless false.
+  /// Recipients can read it there either way.
+  public: Option<bool>,
 }
 
-/// Content of the email, which is in plaintext and/or html.
-#[derive(Deserialize)]
+/// Content of the email, which is in html and optionally plaintext.
+/// The plaintext is generated from the html when missing.
+#[derive(Deserialize, Clone)]
 pub struct Content {
   html: String,
-  text: String,
+  text: Option<String>,
 }
 
 /// Errors which may occur during the publishing step.
@@ -35,6 +70,12 @@ pub struct Content {
 pub enum PublishError {
   #[error("Authentication failed.")]
   AuthError(#[source] anyhow::Error),
+  #[error("Too many failed login attempts.")]
+  TooManyAttempts(std::time::Duration),
+  #[error("{0}")]
+  ValidationError(String),
+  #[error("The issue has lint findings which block publishing.")]
+  LintFailed(LintReport),
   #[error(transparent)]
   UnexpectedError(#[from] anyhow::Error),
 }
@@ -49,6 +90,9 @@ impl ResponseError for PublishError {
   fn status_code(&self) -> StatusCode {
     match self {
       PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+      PublishError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+      PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      PublishError::LintFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
       PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
   }
@@ -57,7 +101,12 @@ impl ResponseError for PublishError {
     let status_code = self.status_code();
     match self {
       PublishError::AuthError(_) => {
-        let mut resp = HttpResponse::new(status_code);
+        let mut resp = Problem::new(
+          status_code,
+          "authentication_failed",
+          "Authentication failed.",
+        )
+        .response();
 
         let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
 
@@ -67,48 +116,118 @@ impl ResponseError for PublishError {
 
         resp
       }
-      PublishError::UnexpectedError(_) => HttpResponse::new(status_code),
+      PublishError::TooManyAttempts(retry_after) => {
+        let mut resp = Problem::new(
+          status_code,
+          "too_many_attempts",
+          "Too many failed login attempts.",
+        )
+        .response();
+        insert_retry_after(&mut resp, *retry_after);
+        resp
+      }
+      PublishError::ValidationError(message) => {
+        Problem::new(status_code, "invalid_issue", "The issue is invalid.")
+          .detail(message)
+          .response()
+      }
+      // The findings are members of the problem, as they were of the report before.
+      PublishError::LintFailed(report) => Problem::new(
+        status_code,
+        "lint_failed",
+        "The issue has lint findings which block publishing.",
+      )
+      .extend(report)
+      .response(),
+      PublishError::UnexpectedError(_) => unexpected_problem().response(),
     }
   }
 }
 
-/// Publishes a newsletter to subscribers.
+/// Checks an issue for what is likely to keep it from reaching or pleasing subscribers,
+/// without sending it. See `lint::lint`.
+#[tracing::instrument(
+  name = "Linting newsletter issue",
+  skip(body, pool, email_templates, sanitizer, base_url, request),
+  fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn lint_newsletter(
+  body: web::Json<BodyData>,
+  pool: web::Data<PgPool>,
+  email_templates: web::Data<EmailTemplates>,
+  sanitizer: web::Data<HtmlSanitizer>,
+  base_url: web::Data<ApplicationBaseUrl>,
+  request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+  authenticate_publisher(&request, &pool).await?;
+  let prepared = prepare_issue(&body, &pool, &email_templates, &sanitizer, &base_url).await?;
+  Ok(HttpResponse::Ok().json(LintResponse {
+    stripped: prepared.stripped,
+    report: prepared.lint,
+  }))
+}
+
+/// Publishes an issue of a newsletter to its confirmed subscribers.
 /// This endpoint requires authentication due to the risk of abuse.
+/// The html is sanitized first, and the response lists what was removed, along with
+/// the lint findings. With `block_on`, findings keep the issue from being sent at all.
+/// Unless the newsletter or the subscriber turned it off, every email carries a pixel
+/// which records when it is opened. Its links go through signed redirects which record
+/// clicks, unless the subscriber opted out of tracking.
+/// The issue is kept for the web archive, with its merge tags filled in for nobody in particular,
+/// and every email links to it there.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
   name = "Publishing newsletter to confirmed subscribers",
-  skip(body, pool, email_client, request),
+  skip(body, pool, email_client, email_templates, sanitizer, base_url, tracking_key, request),
   fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
   body: web::Json<BodyData>,
   pool: web::Data<PgPool>,
   email_client: web::Data<EmailClient>,
+  email_templates: web::Data<EmailTemplates>,
+  sanitizer: web::Data<HtmlSanitizer>,
+  base_url: web::Data<ApplicationBaseUrl>,
+  tracking_key: web::Data<TrackingKey>,
   request: web::HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-  let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-
-  let _user_id = validate_credentials(credentials, &pool).await?;
+  let user = authenticate_publisher(&request, &pool).await?;
+  let PreparedIssue {
+    newsletter,
+    source,
+    stripped,
+    lint,
+  } = prepare_issue(&body, &pool, &email_templates, &sanitizer, &base_url).await?;
+  if sanitizer.strict && !stripped.is_empty() {
+    return Err(PublishError::ValidationError(format!(
+      "The html contains what isn't allowed: {}.",
+      stripped.join(", ")
+    )));
+  }
+  if body.block_on.is_some_and(|gate| lint.blocks(gate)) {
+    return Err(PublishError::LintFailed(lint));
+  }
+  let issue =
+    IssueTemplate::parse(&source.title, &source.content).map_err(PublishError::ValidationError)?;
+  if let Some(segment) = &body.segment {
+    segment
+      .validate()
+      .map_err(|es| PublishError::ValidationError(format!("segment: {}", es.join(", "))))?;
+  }
 
-  let subscribers = get_confirmed_subscribers(&pool).await?;
-  for subscriber in subscribers {
+  let now = Utc::now();
+  let mut outside_segment = 0;
+  let mut held_back = 0;
+  let mut skipped = 0;
+  let mut recipients = Vec::new();
+  for subscriber in get_confirmed_subscribers(&pool, newsletter.id).await? {
     match subscriber {
-      Ok(subscriber) => {
-        email_client
-          .send_email(
-            &subscriber.email,
-            &body.title,
-            &body.content.html,
-            &body.content.text,
-          )
-          .await
-          .with_context(|| {
-            format!(
-              "Failed to send newsletter issue to {}",
-              subscriber.email.as_ref()
-            )
-          })?;
-      }
+      Ok(subscriber) if !subscriber.is_in(body.segment.as_ref(), now) => outside_segment += 1,
+      Ok(subscriber) if !subscriber.is_due(now) => held_back += 1,
+      Ok(subscriber) => recipients.push(subscriber),
       Err(e) => {
+        skipped += 1;
         tracing::warn!(
           error.cause_chain = ?e,
           "Skipping a confirmed subscriber. \
@@ -117,131 +236,440 @@ pub async fn publish_newsletter(
       }
     }
   }
-  Ok(HttpResponse::Ok().finish())
+
+  // Every issue is rendered before any is sent, so that nobody gets an issue
+  // unless everybody can.
+  let mut issues = Vec::with_capacity(recipients.len());
+  let mut missing: BTreeMap<String, usize> = BTreeMap::new();
+  for subscriber in &recipients {
+    match issue.render(subscriber) {
+      Ok(rendered) => issues.push(rendered),
+      Err(variables) => {
+        for variable in variables.into_iter().collect::<BTreeSet<_>>() {
+          *missing.entry(variable).or_default() += 1;
+        }
+      }
+    }
+  }
+  if !missing.is_empty() {
+    let missing: Vec<String> = missing
+      .into_iter()
+      .map(|(variable, count)| format!("{} (missing for {} recipients)", variable, count))
+      .collect();
+    return Err(PublishError::ValidationError(format!(
+      "Some merge tags have no value and no default: {}.",
+      missing.join(", ")
+    )));
+  }
+
+  let public_issue = issue.render_public();
+  let published = insert_issue(
+    &pool,
+    &NewIssue {
+      newsletter_id: newsletter.id,
+      title: &public_issue.title,
+      html_content: &public_issue.html,
+      text_content: &public_issue.text,
+      preheader: source.preheader.as_deref(),
+      public: body.public.unwrap_or(true),
+      published_at: now,
+    },
+  )
+  .await?;
+  let issue_id = published.id;
+  // Recorded before anything is sent, so that a failure partway through the sending
+  // still leaves a trace, and a failure to record doesn't invite a retry resending everything.
+  record_audit_event(
+    &pool,
+    &AuditContext::from_request(&request),
+    AuditEvent::new(Some(user.user_id), AuditAction::PublishNewsletter)
+      .target(&source.title)
+      .details(serde_json::json!({
+        "list": newsletter.slug,
+        "issue_id": issue_id,
+        "public": body.public.unwrap_or(true),
+        "segment": body.segment,
+        "recipients": recipients.len(),
+        "outside_segment": outside_segment,
+        "held_back": held_back,
+        "skipped": skipped,
+        "stripped": stripped,
+      })),
+  )
+  .await?;
+  let archive_link =
+    tracking_key.archive_url(base_url.get_ref().as_ref(), issue_id, &published.slug);
+  let mut delivered = 0;
+  let mut warnings = BTreeSet::new();
+  for (subscriber, rendered) in recipients.iter().zip(issues) {
+    let preferences_link = format!(
+      "{}/subscriptions/preferences?token={}",
+      base_url.get_ref().as_ref(),
+      subscriber.preferences_token,
+    );
+    let email_body = email_templates.newsletter_issue(&NewsletterIssue {
+      title: &rendered.title,
+      newsletter_name: &newsletter.name,
+      html_content: &rendered.html,
+      text_content: &rendered.text,
+      preferences_link: &preferences_link,
+      archive_link: &archive_link,
+      preheader: source.preheader.as_deref(),
+    })?;
+    warnings.extend(email_body.warnings);
+    let mut html = email_body.html;
+    if !subscriber.tracking_opt_out {
+      let base_url = base_url.get_ref().as_ref();
+      html = email_html::track_clicks(&html, |url| {
+        is_tracked_link(url, base_url)
+          .then(|| tracking_key.click_url(base_url, issue_id, subscriber.id, url))
+      });
+      if newsletter.track_opens {
+        let pixel_url = tracking_key.open_pixel_url(base_url, issue_id, subscriber.id);
+        html = email_html::add_tracking_pixel(&html, &pixel_url);
+      }
+    }
+    email_client
+      .send_email_as(
+        &newsletter.sender_name,
+        &subscriber.email,
+        &rendered.title,
+        &html,
+        &email_body.text,
+      )
+      .await
+      .with_context(|| {
+        format!(
+          "Failed to send newsletter issue to {}",
+          subscriber.email.as_ref()
+        )
+      })?;
+    record_delivery(&pool, issue_id, subscriber.id, now).await?;
+    delivered += 1;
+  }
+
+  set_issue_delivered(&pool, issue_id, delivered).await?;
+  for warning in &warnings {
+    tracing::warn!("A published issue is likely to be a problem: {}", warning);
+  }
+
+  Ok(HttpResponse::Ok().json(PublishReport {
+    issue_id,
+    slug: published.slug,
+    stripped,
+    warnings: warnings.into_iter().collect(),
+    lint,
+  }))
+}
+
+/// Web links are tracked, except those to manage the subscription, which should work
+/// the same whether or not we are reachable, and the link to the issue on the archive,
+/// which isn't part of its content.
+fn is_tracked_link(url: &str, base_url: &str) -> bool {
+  let is_web_link = matches!(html::url_scheme(url).as_deref(), Some("http" | "https"));
+  is_web_link
+    && !url.starts_with(&format!("{}/subscriptions/", base_url))
+    && !url.starts_with(&format!("{}/archive/", base_url))
+}
+
+async fn authenticate_publisher(
+  request: &web::HttpRequest,
+  pool: &PgPool,
+) -> Result<AuthenticatedUser, PublishError> {
+  authenticate(request, pool).await.map_err(|e| match e {
+    AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+    AuthError::TooManyAttempts(retry_after) => PublishError::TooManyAttempts(retry_after),
+    AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+  })
+}
+
+/// An issue, sanitized and linted, which is ready to be rendered for every recipient.
+struct PreparedIssue {
+  newsletter: Newsletter,
+  source: IssueSource,
+  /// What the sanitizer removed from the html.
+  stripped: Vec<String>,
+  lint: LintReport,
+}
+
+async fn prepare_issue(
+  body: &BodyData,
+  pool: &PgPool,
+  email_templates: &EmailTemplates,
+  sanitizer: &HtmlSanitizer,
+  base_url: &ApplicationBaseUrl,
+) -> Result<PreparedIssue, PublishError> {
+  let slug = body.list.as_deref().unwrap_or(DEFAULT_NEWSLETTER_SLUG);
+  let newsletter = get_newsletter_by_slug(pool, slug)
+    .await?
+    .ok_or_else(|| PublishError::ValidationError(format!("There is no newsletter {}.", slug)))?;
+
+  let mut source = issue_source(body).map_err(PublishError::ValidationError)?;
+  let submitted_html = source.content.html.clone();
+  let sanitized = sanitizer.sanitize(&submitted_html);
+  source.content.html = sanitized.html;
+
+  // The issue is linted as an example subscriber would get it, merge tags aside.
+  let preferences_link = format!(
+    "{}/subscriptions/preferences?token=example",
+    base_url.as_ref()
+  );
+  let archive_link = format!("{}/archive/example", base_url.as_ref());
+  let email_body = email_templates.newsletter_issue(&NewsletterIssue {
+    title: &source.title,
+    newsletter_name: &newsletter.name,
+    html_content: &source.content.html,
+    text_content: &source.content.text(),
+    preferences_link: &preferences_link,
+    archive_link: &archive_link,
+    preheader: source.preheader.as_deref(),
+  })?;
+  let lint = lint(&LintedIssue {
+    subject: &source.title,
+    content_html: &submitted_html,
+    email_html: &email_body.html,
+    email_text: &email_body.text,
+  });
+  Ok(PreparedIssue {
+    newsletter,
+    source,
+    stripped: sanitized.stripped,
+    lint,
+  })
+}
+
+/// The findings of `POST /newsletters/lint`.
+#[derive(Serialize)]
+struct LintResponse {
+  /// What the sanitizer would remove from the html.
+  stripped: Vec<String>,
+  #[serde(flatten)]
+  report: LintReport,
 }
 
-struct Credentials {
-  username: String,
-  password: String,
+/// What publishers should know about an issue which was sent.
+#[derive(Serialize)]
+struct PublishReport {
+  issue_id: Uuid,
+  /// Where the issue is on the web archive, at `/archive/{slug}`.
+  slug: String,
+  /// What the sanitizer removed from the html.
+  stripped: Vec<String>,
+  /// What might keep the issue from showing as intended, e.g. its size.
+  warnings: Vec<String>,
+  lint: LintReport,
 }
 
-/// Parses the header into user credentials, using Basic Authentication.
-/// https://en.wikipedia.org/wiki/Basic_access_authentication.
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-  let header_value = headers
-    .get("Authorization")
-    .context("'Authorization' header is missing")?
-    .to_str()
-    .context("'Authorization' header is not a valid UTF8 encoded string.")?;
+impl Content {
+  /// Merge tags in the html are kept in the generated text, to be filled in like any other.
+  fn text(&self) -> String {
+    match &self.text {
+      Some(text) if !text.trim().is_empty() => text.clone(),
+      _ => html::to_text(&self.html),
+    }
+  }
+}
 
-  let encoded_segment = header_value
-    .strip_prefix("Basic ")
-    .context("Authorization scheme is not Basic.")?;
+/// What to publish, from the explicit fields or else the Markdown.
+struct IssueSource {
+  title: String,
+  content: Content,
+  preheader: Option<String>,
+}
 
-  let decoded_bytes = base64::decode_config(encoded_segment, base64::STANDARD)
-    .context("Failed to decode Credentials using base64.")?;
+fn issue_source(body: &BodyData) -> Result<IssueSource, String> {
+  let markdown = body
+    .markdown
+    .as_deref()
+    .map(MarkdownIssue::parse)
+    .transpose()?;
+  let title = body
+    .title
+    .clone()
+    .or_else(|| markdown.as_ref()?.front_matter.title.clone())
+    .ok_or("The issue has no title.")?;
+  let preheader = body
+    .preheader
+    .clone()
+    .or_else(|| markdown.as_ref()?.front_matter.preheader.clone());
+  let content = match (&body.content, markdown) {
+    (Some(content), _) => content.clone(),
+    (None, Some(markdown)) => Content {
+      html: markdown.html,
+      text: Some(markdown.text),
+    },
+    (None, None) => return Err("The issue needs either content or markdown.".into()),
+  };
+  Ok(IssueSource {
+    title,
+    content,
+    preheader,
+  })
+}
 
-  let decoded_credentials = String::from_utf8(decoded_bytes)
-    .context("Decoded credential data is not a valid UTF8 encoded string.")?;
+/// The title and content of an issue, with their merge tags parsed.
+struct IssueTemplate {
+  title: MergeTemplate,
+  html: MergeTemplate,
+  text: MergeTemplate,
+}
 
-  let mut credentials = decoded_credentials.splitn(2, ':');
+/// An issue as a given recipient sees it.
+struct RenderedIssue {
+  title: String,
+  html: String,
+  text: String,
+}
 
-  let username = credentials
-    .next()
-    .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-    .to_string();
+impl IssueTemplate {
+  fn parse(title: &str, content: &Content) -> Result<Self, String> {
+    Ok(Self {
+      title: MergeTemplate::parse(title).map_err(|e| format!("title: {}", e))?,
+      html: MergeTemplate::parse(&content.html).map_err(|e| format!("html: {}", e))?,
+      text: MergeTemplate::parse(&content.text()).map_err(|e| format!("text: {}", e))?,
+    })
+  }
 
-  let password = credentials
-    .next()
-    .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-    .to_string();
+  /// The issue as anyone may read it on the web archive. See `MergeTemplate::render_public`.
+  fn render_public(&self) -> RenderedIssue {
+    RenderedIssue {
+      title: self.title.render_public(Escaping::None),
+      html: self.html.render_public(Escaping::Html),
+      text: self.text.render_public(Escaping::None),
+    }
+  }
 
-  Ok(Credentials { username, password })
+  /// Returns every variable the subscriber is missing, if any.
+  fn render(&self, subscriber: &ConfirmedSubscriber) -> Result<RenderedIssue, Vec<String>> {
+    let recipient = Recipient {
+      name: &subscriber.name,
+      email: subscriber.email.as_ref(),
+      attributes: &subscriber.attributes,
+    };
+    let title = self.title.render(&recipient, Escaping::None);
+    let html = self.html.render(&recipient, Escaping::Html);
+    let text = self.text.render(&recipient, Escaping::None);
+    match (title, html, text) {
+      (Ok(title), Ok(html), Ok(text)) => Ok(RenderedIssue { title, html, text }),
+      (title, html, text) => {
+        let mut missing = title.err().unwrap_or_default();
+        missing.extend(html.err().unwrap_or_default());
+        missing.extend(text.err().unwrap_or_default());
+        Err(missing)
+      }
+    }
+  }
 }
 
 struct ConfirmedSubscriber {
+  id: Uuid,
   email: SubscriberEmail,
+  name: String,
+  attributes: Map<String, Value>,
+  preferences_token: String,
+  frequency: DeliveryFrequency,
+  last_delivered_at: Option<DateTime<Utc>>,
+  tags: Vec<String>,
+  subscribed_at: DateTime<Utc>,
+  tracking_opt_out: bool,
+}
+
+impl ConfirmedSubscriber {
+  /// Everyone is in the segment when there is none.
+  fn is_in(&self, segment: Option<&Segment>, now: DateTime<Utc>) -> bool {
+    segment.is_none_or(|segment| {
+      segment.matches(
+        &SubscriberProfile {
+          tags: &self.tags,
+          attributes: &self.attributes,
+          subscribed_at: self.subscribed_at,
+        },
+        now,
+      )
+    })
+  }
+
+  /// Whether enough time has passed since the last delivery for the subscriber's chosen frequency.
+  fn is_due(&self, now: DateTime<Utc>) -> bool {
+    match self.last_delivered_at {
+      Some(last_delivered_at) => now - last_delivered_at >= self.frequency.min_interval(),
+      None => true,
+    }
+  }
 }
 
+/// Confirmed subscribers of the newsletter, except for those who paused their subscription.
 #[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
 async fn get_confirmed_subscribers(
   pool: &PgPool,
+  newsletter_id: Uuid,
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
   Ok(
     sqlx::query!(
       r#"
-      SELECT email
-      FROM subscriptions
-      WHERE status = 'confirmed'
+      SELECT s.id, s.email, s.name, s.attributes, s.preferences_token, s.frequency,
+        s.last_delivered_at, s.subscribed_at, s.tracking_opt_out,
+        ARRAY(SELECT tag FROM subscriber_tags t WHERE t.subscriber_id = s.id) AS "tags!"
+      FROM subscriptions s
+      JOIN newsletter_subscriptions ns ON ns.subscriber_id = s.id
+      WHERE ns.newsletter_id = $1 AND ns.status = 'confirmed'
+        AND (s.paused_until IS NULL OR s.paused_until <= now())
       "#,
+      newsletter_id,
     )
     .fetch_all(pool)
     .await?
     .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-      Ok(email) => Ok(ConfirmedSubscriber { email }),
-      Err(e) => Err(anyhow::anyhow!(e)),
+    .map(|r| {
+      let email = SubscriberEmail::parse(r.email).map_err(|e| anyhow::anyhow!(e))?;
+      let frequency = DeliveryFrequency::parse(r.frequency).map_err(|e| anyhow::anyhow!(e))?;
+      let attributes = match r.attributes {
+        Value::Object(attributes) => attributes,
+        _ => anyhow::bail!("Stored attributes are not an object."),
+      };
+      Ok(ConfirmedSubscriber {
+        id: r.id,
+        email,
+        name: r.name,
+        attributes,
+        preferences_token: r.preferences_token,
+        frequency,
+        last_delivered_at: r.last_delivered_at,
+        tags: r.tags,
+        subscribed_at: r.subscribed_at,
+        tracking_opt_out: r.tracking_opt_out,
+      })
     })
     .collect(),
   )
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
-async fn validate_credentials(
-  credentials: Credentials,
+#[tracing::instrument(name = "Record delivery", skip(pool))]
+async fn record_delivery(
   pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-  let (user_id, expected_password_hash) = get_stored_credentials(&credentials.username, pool)
-    .await
-    .map_err(PublishError::UnexpectedError)?
-    .map(|(u, p)| (Some(u), p))
-    .unwrap_or((None, "$argon2id$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno".to_string()));
-
-  spawn_blocking_with_tracing(move || {
-    verify_password_hash(expected_password_hash, credentials.password)
-  })
+  issue_id: Uuid,
+  subscriber_id: Uuid,
+  delivered_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    "UPDATE subscriptions SET last_delivered_at = $1 WHERE id = $2",
+    delivered_at,
+    subscriber_id,
+  )
+  .execute(pool)
   .await
-  .context("Failed to spawn blocking task.")
-  .map_err(PublishError::UnexpectedError)??;
-
-  user_id.ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unknown username.")))
-}
-
-#[tracing::instrument(
-  name = "Verify password hash",
-  skip(expected_password_hash, password_candidate)
-)]
-fn verify_password_hash(
-  expected_password_hash: String,
-  password_candidate: String,
-) -> Result<(), PublishError> {
-  let expected_password_hash = PasswordHash::new(&expected_password_hash)
-    .context("Failed to parse hash in PHC string format.")
-    .map_err(PublishError::UnexpectedError)?;
-
-  Argon2::default()
-    .verify_password(password_candidate.as_bytes(), &expected_password_hash)
-    .context("Invalid password.")
-    .map_err(PublishError::AuthError)
-}
-
-#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
-async fn get_stored_credentials(
-  username: &str,
-  pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, String)>, anyhow::Error> {
-  Ok(
-    sqlx::query!(
-      r#"
-      SELECT user_id, password_hash
-      FROM users
-      WHERE username = $1
-      "#,
-      username,
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to perform a query to validate auth credentials.")?
-    .map(|row| (row.user_id, row.password_hash)),
+  .context("Failed to record a delivery.")?;
+  sqlx::query!(
+    r#"
+    INSERT INTO issue_deliveries (issue_id, subscriber_id, delivered_at)
+    VALUES ($1, $2, $3)
+    "#,
+    issue_id,
+    subscriber_id,
+    delivered_at,
   )
+  .execute(pool)
+  .await
+  .context("Failed to record who an issue was delivered to.")?;
+  Ok(())
 }
@@ -4,66 +4,315 @@ use actix_http::StatusCode;
 // since this code is asynchronous, when the transaction goes out
 // of scope and a rollback operation is enqueued, that rollback
 // operation won't execute immediately.
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
+use std::collections::{BTreeMap, HashMap};
 
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde_json::{Map, Value};
 use sqlx::{PgPool, Postgres, Transaction};
 
 use uuid::Uuid;
 
 use crate::{
-  domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+  authentication::{client_ip, insert_retry_after},
+  domain::{NewSubscriber, SubscriberAttributes, SubscriberEmail, SubscriberName},
   email_client::EmailClient,
+  email_templates::{ConfirmationEmail, EmailTemplates},
+  newsletters::{get_newsletter_by_slug, list_newsletters, Newsletter, DEFAULT_NEWSLETTER_SLUG},
+  page_templates::{NewsletterChoice, PageTemplates, SubscribePage, SubscribePendingPage},
+  problem::{accepts_json, is_json, unexpected_problem, Problem},
+  signup_protection::{SignupGuard, SignupProof, SignupRejection},
   startup::ApplicationBaseUrl,
 };
 
+#[derive(Debug, serde::Deserialize)]
+pub struct SubscribePageParameters {
+  /// Slug of the newsletter selected on the form, the default newsletter if missing.
+  list: Option<String>,
+}
+
+/// A subscription submitted from a form.
 #[derive(serde::Deserialize)]
 pub struct SubscribeFormData {
   email: String,
   name: String,
+  /// Slug of the newsletter to subscribe to, the default newsletter if missing.
+  list: Option<String>,
+  #[serde(flatten)]
+  proof: SignupProof,
+  /// Attributes are submitted as `attributes.<name>` fields, e.g. `attributes.city=Montreal`.
+  #[serde(flatten)]
+  other_fields: HashMap<String, String>,
 }
 
-impl TryFrom<SubscribeFormData> for NewSubscriber {
-  type Error = String;
+/// A subscription, as submitted in JSON, or from a form once its attributes are gathered.
+#[derive(serde::Deserialize)]
+pub struct SubscribeData {
+  email: String,
+  name: String,
+  /// Slug of the newsletter to subscribe to, the default newsletter if missing.
+  list: Option<String>,
+  #[serde(default)]
+  attributes: Map<String, Value>,
+  #[serde(flatten)]
+  proof: SignupProof,
+}
 
-  fn try_from(form: SubscribeFormData) -> Result<Self, Self::Error> {
-    let name = SubscriberName::parse(form.name);
-    let email = SubscriberEmail::parse(form.email).map_err(|e| vec![e]);
+impl From<SubscribeFormData> for SubscribeData {
+  fn from(form: SubscribeFormData) -> Self {
+    Self {
+      email: form.email,
+      name: form.name,
+      list: form.list,
+      proof: form.proof,
+      attributes: form
+        .other_fields
+        .into_iter()
+        .filter_map(|(field, value)| {
+          Some((
+            field.strip_prefix("attributes.")?.to_string(),
+            Value::String(value),
+          ))
+        })
+        .collect(),
+    }
+  }
+}
 
-    match (name, email) {
-      (Ok(name), Ok(email)) => Ok(NewSubscriber { name, email }),
-      (Err(es), Ok(_)) => Err(es),
-      (Ok(_), Err(es)) => Err(es),
-      (Err(mut name_es), Err(mut email_es)) => {
-        name_es.append(&mut email_es);
-        Err(name_es)
-      }
+impl SubscribeData {
+  /// The attributes submitted, as filled in on the form.
+  fn attribute_strings(&self) -> BTreeMap<String, String> {
+    self
+      .attributes
+      .iter()
+      .map(|(field, value)| {
+        let value = match value {
+          Value::String(value) => value.clone(),
+          value => value.to_string(),
+        };
+        (field.clone(), value)
+      })
+      .collect()
+  }
+}
+
+/// Everything wrong with a subscription, by field, as shown next to each field of the form.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SubscribeFormErrors {
+  pub name: Vec<String>,
+  pub email: Vec<String>,
+  pub list: Vec<String>,
+  pub attributes: Vec<String>,
+  /// Errors with the form as a whole, like it being taken for a bot's.
+  pub form: Vec<String>,
+}
+
+impl std::fmt::Display for SubscribeFormErrors {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let errors: Vec<&str> = [
+      &self.name,
+      &self.email,
+      &self.list,
+      &self.attributes,
+      &self.form,
+    ]
+    .into_iter()
+    .flatten()
+    .map(String::as_str)
+    .collect();
+    write!(f, "{}", errors.join(", "))
+  }
+}
+
+impl SubscribeFormErrors {
+  /// The fields which have errors, with their errors.
+  fn by_field(&self) -> BTreeMap<&'static str, &[String]> {
+    [
+      ("name", &self.name),
+      ("email", &self.email),
+      ("list", &self.list),
+      ("attributes", &self.attributes),
+      ("form", &self.form),
+    ]
+    .into_iter()
+    .filter(|(_, errors)| !errors.is_empty())
+    .map(|(field, errors)| (field, errors.as_slice()))
+    .collect()
+  }
+}
+
+impl TryFrom<SubscribeData> for NewSubscriber {
+  type Error = SubscribeFormErrors;
+
+  fn try_from(data: SubscribeData) -> Result<Self, Self::Error> {
+    let name = SubscriberName::parse(data.name);
+    let email = SubscriberEmail::parse(data.email).map_err(|e| vec![e]);
+    let attributes = SubscriberAttributes::parse(Value::Object(data.attributes));
+
+    match (name, email, attributes) {
+      (Ok(name), Ok(email), Ok(attributes)) => Ok(NewSubscriber {
+        name,
+        email,
+        attributes,
+      }),
+      (name, email, attributes) => Err(SubscribeFormErrors {
+        name: name.err().unwrap_or_default(),
+        email: email.err().unwrap_or_default(),
+        list: Vec::new(),
+        attributes: attributes.err().unwrap_or_default(),
+        form: Vec::new(),
+      }),
     }
-    .map_err(|es| es.join(", "))
   }
 }
 
-/// Marks a user as a potential subscriber, and sends them a confirmation email.
+/// The subscribe form, with the newsletter given by `list` selected.
+/// API clients accepting JSON, like the subscribe widget, only get what they must submit
+/// the subscription with: a form token and the difficulty of its proof of work.
+#[tracing::instrument(
+  name = "Get subscribe form",
+  skip(request, pool, page_templates, signup_guard)
+)]
+pub async fn subscribe_form(
+  request: HttpRequest,
+  parameters: web::Query<SubscribePageParameters>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+  signup_guard: web::Data<SignupGuard>,
+) -> Result<HttpResponse, SubscribeError> {
+  if accepts_json(&request) {
+    return Ok(HttpResponse::Ok().json(signup_guard.challenge()));
+  }
+  let list = parameters
+    .into_inner()
+    .list
+    .unwrap_or_else(|| DEFAULT_NEWSLETTER_SLUG.to_string());
+  let page = render_subscribe_form(
+    &pool,
+    &page_templates,
+    &signup_guard,
+    &list,
+    "",
+    "",
+    &BTreeMap::new(),
+    &SubscribeFormErrors::default(),
+  )
+  .await?;
+  Ok(html_page(StatusCode::OK, page))
+}
+
+/// Marks a user as a potential subscriber to a newsletter, and sends them a confirmation email.
 /// Only after clicking the link in that email will they be confirmed subscribers.
 /// (Handling the confirmation is done by another endpoint)
+/// Someone who already confirmed their subscription to the newsletter is not sent another email,
+/// but gets the same response, so that it doesn't tell who is subscribed.
+///
+/// The subscription is submitted from a form, or as JSON. Browsers get pages back, with the
+/// form again when it is invalid. API clients, which submit JSON or accept it, get JSON back,
+/// and errors as `application/problem+json`.
+///
+/// Subscriptions are checked for bots before any email is sent, see `SignupGuard`.
+/// Those filling in the honeypot field get the usual response, but are otherwise ignored.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(request, body, pool, email_client, email_templates, page_templates, signup_guard, base_url),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name,
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty,
     )
 )]
-#[allow(clippy::async_yields_async)]
+#[allow(clippy::async_yields_async, clippy::too_many_arguments)]
 pub async fn subscribe(
-  form: web::Form<SubscribeFormData>,
+  request: HttpRequest,
+  body: web::Bytes,
   pool: web::Data<PgPool>,
   email_client: web::Data<EmailClient>,
+  email_templates: web::Data<EmailTemplates>,
+  page_templates: web::Data<PageTemplates>,
+  signup_guard: web::Data<SignupGuard>,
   base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
-  let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+  let mut data = read_subscription(&request, &body)?;
+  let proof = std::mem::take(&mut data.proof);
+  let wants_json = is_json(&request) || accepts_json(&request);
+  tracing::Span::current()
+    .record("subscriber_email", &tracing::field::display(&data.email))
+    .record("subscriber_name", &tracing::field::display(&data.name));
+  let slug = data
+    .list
+    .take()
+    .unwrap_or_else(|| DEFAULT_NEWSLETTER_SLUG.to_string());
+  let (name, email, attributes) = (
+    data.name.clone(),
+    data.email.clone(),
+    data.attribute_strings(),
+  );
+  let (new_subscriber, newsletter) = match validate_subscription(&pool, data, &slug).await {
+    Ok(subscription) => subscription,
+    Err(SubscribeError::ValidationError(errors)) if !wants_json => {
+      tracing::info!(%errors, "Rejected an invalid subscription.");
+      let page = render_subscribe_form(
+        &pool,
+        &page_templates,
+        &signup_guard,
+        &slug,
+        &name,
+        &email,
+        &attributes,
+        &errors,
+      )
+      .await?;
+      return Ok(html_page(StatusCode::BAD_REQUEST, page));
+    }
+    Err(e) => return Err(e),
+  };
+  let response = if wants_json {
+    HttpResponse::Ok().json(serde_json::json!({
+      "email": new_subscriber.email.as_ref(),
+      "list": newsletter.slug,
+    }))
+  } else {
+    let page = page_templates.subscribe_pending(&SubscribePendingPage {
+      name: new_subscriber.name.as_ref(),
+      email: new_subscriber.email.as_ref(),
+      newsletter_name: &newsletter.name,
+    })?;
+    html_page(StatusCode::OK, page)
+  };
+  let ip = client_ip(&request);
+  if proof.fills_honeypot() {
+    tracing::warn!(?ip, "Ignored a subscription filling in the honeypot.");
+    return Ok(response);
+  }
+  if let Err(rejection) = signup_guard.check(&proof, ip, &new_subscriber.email.domain()) {
+    tracing::warn!(%rejection, ?ip, "Rejected a subscription taken for a bot's.");
+    if wants_json {
+      return Err(SubscribeError::Rejected(rejection));
+    }
+    let errors = SubscribeFormErrors {
+      form: vec![rejection.to_string()],
+      ..SubscribeFormErrors::default()
+    };
+    let page = render_subscribe_form(
+      &pool,
+      &page_templates,
+      &signup_guard,
+      &slug,
+      &name,
+      &email,
+      &attributes,
+      &errors,
+    )
+    .await?;
+    let mut response = html_page(rejection.status_code(), page);
+    if let Some(retry_after) = rejection.retry_after() {
+      insert_retry_after(&mut response, retry_after);
+    }
+    return Ok(response);
+  }
+
   let mut transaction = pool
     .begin()
     .await
@@ -71,18 +320,105 @@ pub async fn subscribe(
   let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
     .await
     .context("Failed to insert new subscriber in the database.")?;
+  let status = insert_newsletter_subscription(&mut transaction, newsletter.id, subscriber_id)
+    .await
+    .context("Failed to subscribe a subscriber to a newsletter.")?;
+  if status == "confirmed" {
+    return Ok(response);
+  }
   let token = generate_subcription_token();
-  store_token(&mut transaction, subscriber_id, &token)
+  store_token(&mut transaction, subscriber_id, newsletter.id, &token)
     .await
     .context("Failed to store the confirmation token for a new subscriber.")?;
   transaction
     .commit()
     .await
     .context("Failed to commit SQL transaction to store a new subscriber.")?;
-  send_confirmation_email(&email_client, &new_subscriber, &base_url, &token)
-    .await
-    .context("Failed to send a confirmation email.")?;
-  Ok(HttpResponse::Ok().finish())
+  send_confirmation_email(
+    &email_client,
+    &email_templates,
+    &new_subscriber,
+    &newsletter,
+    &base_url,
+    &token,
+  )
+  .await
+  .context("Failed to send a confirmation email.")?;
+  Ok(response)
+}
+
+/// Reads the subscription in the body, as JSON or from a form depending on its content type.
+fn read_subscription(request: &HttpRequest, body: &[u8]) -> Result<SubscribeData, SubscribeError> {
+  if is_json(request) {
+    serde_json::from_slice(body).map_err(|e| SubscribeError::MalformedRequest(e.to_string()))
+  } else if request.content_type() == "application/x-www-form-urlencoded" {
+    serde_urlencoded::from_bytes::<SubscribeFormData>(body)
+      .map(SubscribeData::from)
+      .map_err(|e| SubscribeError::MalformedRequest(e.to_string()))
+  } else {
+    Err(SubscribeError::UnsupportedMediaType(
+      request.content_type().to_string(),
+    ))
+  }
+}
+
+/// The subscriber and the newsletter they subscribe to, or everything wrong with the subscription.
+async fn validate_subscription(
+  pool: &PgPool,
+  data: SubscribeData,
+  slug: &str,
+) -> Result<(NewSubscriber, Newsletter), SubscribeError> {
+  let newsletter = get_newsletter_by_slug(pool, slug).await?;
+  match (NewSubscriber::try_from(data), newsletter) {
+    (Ok(new_subscriber), Some(newsletter)) => Ok((new_subscriber, newsletter)),
+    (new_subscriber, newsletter) => {
+      let mut errors = new_subscriber.err().unwrap_or_default();
+      if newsletter.is_none() {
+        errors
+          .list
+          .push(format!("There is no newsletter {}.", slug));
+      }
+      Err(SubscribeError::ValidationError(errors))
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn render_subscribe_form(
+  pool: &PgPool,
+  page_templates: &PageTemplates,
+  signup_guard: &SignupGuard,
+  list: &str,
+  name: &str,
+  email: &str,
+  attributes: &BTreeMap<String, String>,
+  errors: &SubscribeFormErrors,
+) -> Result<String, anyhow::Error> {
+  let newsletters = list_newsletters(pool).await?;
+  let choices: Vec<NewsletterChoice> = newsletters
+    .iter()
+    .map(|newsletter| NewsletterChoice {
+      slug: &newsletter.slug,
+      name: &newsletter.name,
+    })
+    .collect();
+  let challenge = signup_guard.challenge();
+  page_templates.subscribe(&SubscribePage {
+    newsletters: &choices,
+    list,
+    name,
+    email,
+    attributes,
+    errors,
+    form_token: &challenge.form_token,
+    proof_of_work_difficulty: challenge.proof_of_work_difficulty,
+  })
+}
+
+pub(crate) fn html_page(status: StatusCode, page: String) -> HttpResponse {
+  HttpResponse::build(status)
+    .content_type("text/html; charset=utf-8")
+    .body(page)
 }
 
 /// Store a token which uniquelly identifies a subscriber.
@@ -95,13 +431,15 @@ pub async fn subscribe(
 pub async fn store_token(
   transaction: &mut Transaction<'_, Postgres>,
   subscriber_id: Uuid,
+  newsletter_id: Uuid,
   subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
   sqlx::query!(
-    r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-    VALUES ($1, $2)"#,
+    r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, newsletter_id)
+    VALUES ($1, $2, $3)"#,
     subscription_token,
     subscriber_id,
+    newsletter_id,
   )
   .execute(transaction)
   .await
@@ -111,8 +449,14 @@ pub async fn store_token(
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
+  #[error("The request body could not be read: {0}")]
+  MalformedRequest(String),
+  #[error("Subscriptions can't be submitted as {0}.")]
+  UnsupportedMediaType(String),
   #[error("{0}")]
-  ValidationError(String),
+  ValidationError(SubscribeFormErrors),
+  #[error(transparent)]
+  Rejected(SignupRejection),
   #[error(transparent)]
   UnexpectedError(#[from] anyhow::Error),
 }
@@ -127,10 +471,56 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
   fn status_code(&self) -> StatusCode {
     match self {
-      SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      SubscribeError::MalformedRequest(_) | SubscribeError::ValidationError(_) => {
+        StatusCode::BAD_REQUEST
+      }
+      SubscribeError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+      SubscribeError::Rejected(rejection) => rejection.status_code(),
       SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
   }
+
+  fn error_response(&self) -> HttpResponse {
+    let status_code = self.status_code();
+    match self {
+      SubscribeError::MalformedRequest(_) => Problem::new(
+        status_code,
+        "malformed_request",
+        "The request body is invalid.",
+      )
+      .detail(self)
+      .response(),
+      SubscribeError::UnsupportedMediaType(_) => Problem::new(
+        status_code,
+        "unsupported_media_type",
+        "Subscriptions are submitted as JSON or from a form.",
+      )
+      .detail(self)
+      .response(),
+      SubscribeError::ValidationError(errors) => Problem::new(
+        status_code,
+        "invalid_subscription",
+        "The subscription is invalid.",
+      )
+      .detail(errors)
+      .extend(&serde_json::json!({ "errors": errors.by_field() }))
+      .response(),
+      SubscribeError::Rejected(rejection) => {
+        let mut response = Problem::new(
+          status_code,
+          rejection.code(),
+          "The subscription was taken for a bot's.",
+        )
+        .detail(rejection)
+        .response();
+        if let Some(retry_after) = rejection.retry_after() {
+          insert_retry_after(&mut response, retry_after);
+        }
+        response
+      }
+      SubscribeError::UnexpectedError(_) => unexpected_problem().response(),
+    }
+  }
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -169,42 +559,44 @@ pub fn error_chain_fmt(
 /// Sends a confirmation email so that a user can confirm they wish to subscribe.
 #[tracing::instrument(
   name = "Send a confirmation email to a new subscriber",
-  skip(email_client, new_subscriber, base_url)
+  skip(email_client, email_templates, new_subscriber, newsletter, base_url)
 )]
 pub async fn send_confirmation_email(
   email_client: &EmailClient,
+  email_templates: &EmailTemplates,
   new_subscriber: &NewSubscriber,
+  newsletter: &Newsletter,
   base_url: &ApplicationBaseUrl,
   subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
   let confirmation_link = format!(
     "{}/subscriptions/confirm?subscription_token={}",
     base_url.as_ref(),
     subscription_token,
   );
 
-  let text_body = format!(
-    "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-    confirmation_link,
-  );
-
-  let html_body = format!(
-    "Welcome to our newsletter!<br />\
-    Click <a href=\"{}\">here</a> to confirm your subscription.",
-    confirmation_link,
-  );
+  let body = email_templates.confirmation(&ConfirmationEmail {
+    name: new_subscriber.name.as_ref(),
+    newsletter_name: &newsletter.name,
+    confirmation_copy: &newsletter.confirmation_copy,
+    confirmation_link: &confirmation_link,
+  })?;
 
   email_client
-    .send_email(
+    .send_email_as(
+      &newsletter.sender_name,
       &new_subscriber.email,
       &format!("Welcome {}!", new_subscriber.name.as_ref()),
-      &html_body,
-      &text_body,
+      &body.html,
+      &body.text,
     )
-    .await
+    .await?;
+  Ok(())
 }
 
 /// Stores a potential subscriber into the database.
+/// Someone who is already known by their email address keeps their details and id,
+/// which is returned either way.
 #[tracing::instrument(
   name = "Saving new subscriber details in the database",
   skip(transaction, new_subscriber)
@@ -214,23 +606,67 @@ pub async fn insert_subscriber(
   transaction: &mut Transaction<'_, Postgres>,
   new_subscriber: &NewSubscriber,
 ) -> Result<Uuid, sqlx::Error> {
-  let subscriber_id = Uuid::new_v4();
   sqlx::query!(
     r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status, preferences_token, attributes)
+    VALUES ($1, $2, $3, $4, 'pending_confirmation', $5, $6)
+    ON CONFLICT (email) DO NOTHING
     "#,
-    subscriber_id,
+    Uuid::new_v4(),
     new_subscriber.email.as_ref(),
     new_subscriber.name.as_ref(),
     Utc::now(),
+    generate_subcription_token(),
+    new_subscriber.attributes.clone().into_value(),
   )
-  .execute(transaction)
+  .execute(&mut *transaction)
+  .await?;
+  let subscriber = sqlx::query!(
+    r#"SELECT id FROM subscriptions WHERE email = $1"#,
+    new_subscriber.email.as_ref(),
+  )
+  .fetch_one(&mut *transaction)
+  .await?;
+  Ok(subscriber.id)
+}
+
+/// Adds a subscriber to a newsletter, pending confirmation unless they were already on it.
+/// Returns the status of their subscription to the newsletter.
+#[tracing::instrument(
+  name = "Saving newsletter subscription in the database",
+  skip(transaction)
+)]
+pub async fn insert_newsletter_subscription(
+  transaction: &mut Transaction<'_, Postgres>,
+  newsletter_id: Uuid,
+  subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_subscriptions (newsletter_id, subscriber_id, status, subscribed_at)
+    VALUES ($1, $2, 'pending_confirmation', $3)
+    ON CONFLICT (newsletter_id, subscriber_id) DO NOTHING
+    "#,
+    newsletter_id,
+    subscriber_id,
+    Utc::now(),
+  )
+  .execute(&mut *transaction)
+  .await?;
+  let subscription = sqlx::query!(
+    r#"
+    SELECT status FROM newsletter_subscriptions
+    WHERE newsletter_id = $1 AND subscriber_id = $2
+    "#,
+    newsletter_id,
+    subscriber_id,
+  )
+  .fetch_one(&mut *transaction)
   .await?;
-  Ok(subscriber_id)
+  Ok(subscription.status)
 }
 
-fn generate_subcription_token() -> String {
+pub(crate) fn generate_subcription_token() -> String {
   let mut rng = thread_rng();
   std::iter::repeat_with(|| rng.sample(Alphanumeric))
     .map(char::from)
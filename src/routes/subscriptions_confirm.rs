@@ -1,60 +1,140 @@
+use actix_http::StatusCode;
 use actix_web::{web, HttpResponse};
+use chrono::Utc;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::{
+  page_templates::{PageTemplates, SubscriptionConfirmedPage},
+  routes::html_page,
+  startup::ApplicationBaseUrl,
+};
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
   #[allow(unused)]
   subscription_token: String,
 }
 
+/// The subscription to a newsletter which a token confirms.
+pub struct PendingSubscription {
+  pub subscriber_id: Uuid,
+  pub newsletter_id: Uuid,
+  pub newsletter_name: String,
+  pub preferences_token: String,
+}
+
 /// Endpoint is used for confirming that a potential subscriber wishes to receive newsletters.
-/// This endpoint is accessed by a user who clicked a confirmation link in an email we sent.
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+/// This endpoint is accessed by a user who clicked a confirmation link in an email we sent,
+/// so it answers with a page telling them whether it worked.
+#[tracing::instrument(
+  name = "Confirm a pending subscriber",
+  skip(parameters, pool, page_templates, base_url)
+)]
 #[allow(clippy::async_yields_async)]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-  match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-    Ok(Some(subscriber_id)) => match confirm_subscriber(&pool, subscriber_id).await {
-      Ok(_) => HttpResponse::Ok(),
-      Err(_) => HttpResponse::InternalServerError(),
+pub async fn confirm(
+  parameters: web::Query<Parameters>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+  let page = match get_subscription_from_token(&pool, &parameters.subscription_token).await {
+    Ok(Some(subscription)) => match confirm_subscriber(&pool, &subscription).await {
+      Ok(_) => {
+        let preferences_link = format!(
+          "{}/subscriptions/preferences?token={}",
+          base_url.get_ref().as_ref(),
+          subscription.preferences_token,
+        );
+        page_templates
+          .subscription_confirmed(&SubscriptionConfirmedPage {
+            newsletter_name: &subscription.newsletter_name,
+            preferences_link: &preferences_link,
+          })
+          .map(|page| (StatusCode::OK, page))
+      }
+      Err(_) => return HttpResponse::InternalServerError().finish(),
     },
-    Ok(None) => HttpResponse::Unauthorized(),
-    Err(_) => HttpResponse::InternalServerError(),
+    Ok(None) => page_templates
+      .invalid_link()
+      .map(|page| (StatusCode::UNAUTHORIZED, page)),
+    Err(_) => return HttpResponse::InternalServerError().finish(),
+  };
+  match page {
+    Ok((status, page)) => html_page(status, page),
+    Err(e) => {
+      tracing::error!(error = ?e, "Failed to render the confirmation page");
+      HttpResponse::InternalServerError().finish()
+    }
   }
-  .finish()
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-  match sqlx::query!(
-    r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-    subscriber_id,
-  )
-  .execute(pool)
-  .await
-  {
-    Ok(_) => Ok(()),
-    Err(e) => {
-      tracing::error!("Failed to execute query: {:?}", e);
-      Err(e)
-    }
+/// Confirms both the subscriber and their subscription to the newsletter the token was sent for.
+/// The tokens for that subscription are used up, so that an old confirmation email can't
+/// subscribe someone again once they unsubscribed.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscription, pool))]
+pub async fn confirm_subscriber(
+  pool: &PgPool,
+  subscription: &PendingSubscription,
+) -> Result<(), sqlx::Error> {
+  let result = async {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+      r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+      subscription.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+      r#"
+      UPDATE newsletter_subscriptions
+      SET status = 'confirmed',
+        confirmed_at = CASE WHEN status = 'confirmed' THEN confirmed_at ELSE $3 END
+      WHERE newsletter_id = $1 AND subscriber_id = $2
+      "#,
+      subscription.newsletter_id,
+      subscription.subscriber_id,
+      Utc::now(),
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+      "DELETE FROM subscription_tokens WHERE newsletter_id = $1 AND subscriber_id = $2",
+      subscription.newsletter_id,
+      subscription.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await
+  }
+  .await;
+  if let Err(e) = &result {
+    tracing::error!("Failed to execute query: {:?}", e);
   }
+  result
 }
 
-/// Token is used to identify which user wishes to confirm their subscription.
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
+/// Token is used to identify which user wishes to confirm their subscription, and to what.
+#[tracing::instrument(name = "Get subscription from token", skip(subscription_token, pool))]
+pub async fn get_subscription_from_token(
   pool: &PgPool,
   subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-  match sqlx::query!(
-    r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+) -> Result<Option<PendingSubscription>, sqlx::Error> {
+  match sqlx::query_as!(
+    PendingSubscription,
+    r#"
+    SELECT t.subscriber_id, t.newsletter_id, n.name AS newsletter_name, s.preferences_token
+    FROM subscription_tokens t
+    JOIN newsletters n ON n.id = t.newsletter_id
+    JOIN subscriptions s ON s.id = t.subscriber_id
+    WHERE t.subscription_token = $1
+    "#,
     subscription_token,
   )
   .fetch_optional(pool)
   .await
   {
-    Ok(maybe_v) => Ok(maybe_v.map(|r| r.subscriber_id)),
+    Ok(maybe_v) => Ok(maybe_v),
     Err(e) => {
       tracing::error!(error = %e, "failed to execute query");
       Err(e)
@@ -0,0 +1,365 @@
+use actix_http::StatusCode;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+  domain::SubscriberEmail,
+  email_client::EmailClient,
+  email_templates::{EmailChangeConfirmation, EmailChangeNotice, EmailTemplates},
+  page_templates::{EmailChangePage, PageTemplates},
+  routes::{
+    get_preferences, html_page, or_invalid_link_page, preferences_page, record_subscription_change,
+    render_preferences_page, subscriptions::generate_subcription_token, PreferencesError,
+    PreferencesFormErrors,
+  },
+  startup::ApplicationBaseUrl,
+};
+
+#[derive(Deserialize)]
+pub struct EmailChangeFormData {
+  token: String,
+  email: String,
+}
+
+#[derive(Deserialize)]
+pub struct EmailChangeParameters {
+  confirmation_token: String,
+}
+
+/// Starts changing a subscriber's address, from their preferences page.
+/// Nothing changes until the new address is confirmed through the link we send to it.
+#[tracing::instrument(
+  name = "Request an email address change",
+  skip(form, pool, email_client, email_templates, page_templates, base_url),
+  fields(new_email = %form.email)
+)]
+pub async fn request_email_change(
+  form: web::Form<EmailChangeFormData>,
+  pool: web::Data<PgPool>,
+  email_client: web::Data<EmailClient>,
+  email_templates: web::Data<EmailTemplates>,
+  page_templates: web::Data<PageTemplates>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, PreferencesError> {
+  let result = store_email_change_request(
+    &form,
+    &pool,
+    &email_client,
+    &email_templates,
+    &page_templates,
+    &base_url,
+  )
+  .await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn store_email_change_request(
+  form: &EmailChangeFormData,
+  pool: &PgPool,
+  email_client: &EmailClient,
+  email_templates: &EmailTemplates,
+  page_templates: &PageTemplates,
+  base_url: &ApplicationBaseUrl,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let current = get_preferences(&mut transaction, &form.token).await?;
+
+  let new_email = match SubscriberEmail::parse(form.email.clone()) {
+    Ok(new_email) if current.email == new_email.as_ref() => Err((
+      StatusCode::BAD_REQUEST,
+      "That is already your address.".to_string(),
+    )),
+    Ok(new_email) if is_email_taken(&mut transaction, &new_email).await? => {
+      Err((StatusCode::CONFLICT, EMAIL_TAKEN.to_string()))
+    }
+    Ok(new_email) => Ok(new_email),
+    Err(e) => Err((StatusCode::BAD_REQUEST, e)),
+  };
+  let new_email = match new_email {
+    Ok(new_email) => new_email,
+    Err((status, error)) => {
+      let errors = PreferencesFormErrors {
+        email: vec![error],
+        ..PreferencesFormErrors::default()
+      };
+      let mut page = preferences_page(&form.token, &current, None, &errors);
+      page.email = &form.email;
+      return render_preferences_page(page_templates, status, &page);
+    }
+  };
+
+  // Only the latest request can be confirmed.
+  sqlx::query!(
+    "DELETE FROM email_change_requests WHERE subscriber_id = $1",
+    current.subscriber_id,
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to discard previous email change requests.")?;
+  let confirmation_token = generate_subcription_token();
+  sqlx::query!(
+    r#"
+    INSERT INTO email_change_requests (confirmation_token, subscriber_id, new_email, requested_at)
+    VALUES ($1, $2, $3, $4)
+    "#,
+    confirmation_token,
+    current.subscriber_id,
+    new_email.as_ref(),
+    Utc::now(),
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to store an email change request.")?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to store an email change request.")?;
+
+  send_email_change_confirmation(
+    email_client,
+    email_templates,
+    &new_email,
+    &current.name,
+    base_url,
+    &confirmation_token,
+  )
+  .await
+  .context("Failed to send an email change confirmation.")?;
+
+  let message = format!(
+    "We sent a link to {} to confirm the change.",
+    new_email.as_ref()
+  );
+  render_preferences_page(
+    page_templates,
+    StatusCode::OK,
+    &preferences_page(
+      &form.token,
+      &current,
+      Some(&message),
+      &PreferencesFormErrors::default(),
+    ),
+  )
+}
+
+/// Swaps in the new address, once its owner followed the link we sent them.
+/// The previous address is told about the change, in case it wasn't wanted.
+#[tracing::instrument(
+  name = "Confirm an email address change",
+  skip(parameters, pool, email_client, email_templates, page_templates)
+)]
+pub async fn confirm_email_change(
+  parameters: web::Query<EmailChangeParameters>,
+  pool: web::Data<PgPool>,
+  email_client: web::Data<EmailClient>,
+  email_templates: web::Data<EmailTemplates>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+  let result = change_email(
+    &parameters.confirmation_token,
+    &pool,
+    &email_client,
+    &email_templates,
+    &page_templates,
+  )
+  .await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn change_email(
+  confirmation_token: &str,
+  pool: &PgPool,
+  email_client: &EmailClient,
+  email_templates: &EmailTemplates,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let request = sqlx::query!(
+    r#"
+    SELECT r.subscriber_id, r.new_email, r.requested_at, s.email AS old_email
+    FROM email_change_requests r
+    JOIN subscriptions s ON s.id = r.subscriber_id
+    WHERE r.confirmation_token = $1
+    FOR UPDATE OF s
+    "#,
+    confirmation_token,
+  )
+  .fetch_optional(&mut transaction)
+  .await
+  .context("Failed to fetch an email change request.")?
+  .ok_or(PreferencesError::UnknownToken)?;
+
+  if Utc::now() - request.requested_at > Duration::hours(EMAIL_CHANGE_VALIDITY_HOURS) {
+    sqlx::query!(
+      "DELETE FROM email_change_requests WHERE subscriber_id = $1",
+      request.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to discard an expired email change request.")?;
+    transaction
+      .commit()
+      .await
+      .context("Failed to commit SQL transaction to discard an expired email change request.")?;
+    return Err(PreferencesError::ExpiredToken);
+  }
+
+  let updated = sqlx::query!(
+    "UPDATE subscriptions SET email = $1 WHERE id = $2",
+    request.new_email,
+    request.subscriber_id,
+  )
+  .execute(&mut transaction)
+  .await;
+  match updated {
+    Ok(_) => {}
+    // Someone subscribed with the new address since the change was requested.
+    Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+      let page = page_templates.email_change_taken(&EmailChangePage {
+        new_email: &request.new_email,
+      })?;
+      return Ok(html_page(StatusCode::CONFLICT, page));
+    }
+    Err(e) => {
+      return Err(
+        anyhow::Error::new(e)
+          .context("Failed to change an email address.")
+          .into(),
+      )
+    }
+  }
+  sqlx::query!(
+    "DELETE FROM email_change_requests WHERE subscriber_id = $1",
+    request.subscriber_id,
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to discard email change requests.")?;
+  record_subscription_change(
+    &mut transaction,
+    request.subscriber_id,
+    "email",
+    serde_json::json!({ "from": request.old_email, "to": request.new_email }),
+  )
+  .await?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to change an email address.")?;
+
+  // The change is done, so failing to notify the previous address shouldn't undo it.
+  let notice = match SubscriberEmail::parse(request.old_email) {
+    Ok(old_email) => send_email_change_notice(
+      email_client,
+      email_templates,
+      &old_email,
+      &request.new_email,
+    )
+    .await
+    .context("Failed to send an email change notice."),
+    Err(e) => Err(anyhow::anyhow!(e).context("The previous address is invalid.")),
+  };
+  if let Err(e) = notice {
+    tracing::error!(
+      error.cause_chain = ?e,
+      "Failed to notify the previous address of an email change."
+    );
+  }
+
+  let page = page_templates.email_change_confirmed(&EmailChangePage {
+    new_email: &request.new_email,
+  })?;
+  Ok(html_page(StatusCode::OK, page))
+}
+
+/// How long the link sent to the new address can be followed.
+const EMAIL_CHANGE_VALIDITY_HOURS: i64 = 24;
+
+/// Postgres' error code for a violated UNIQUE constraint.
+const UNIQUE_VIOLATION: &str = "23505";
+
+const EMAIL_TAKEN: &str = "That address is already subscribed.";
+
+async fn is_email_taken(
+  transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+  email: &SubscriberEmail,
+) -> Result<bool, anyhow::Error> {
+  Ok(
+    sqlx::query!(
+      "SELECT id FROM subscriptions WHERE email = $1",
+      email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to look up a subscriber by email.")?
+    .is_some(),
+  )
+}
+
+/// Sends a confirmation email so that the owner of the new address can confirm the change.
+#[tracing::instrument(
+  name = "Send an email change confirmation",
+  skip(email_client, email_templates, name, base_url, confirmation_token)
+)]
+pub async fn send_email_change_confirmation(
+  email_client: &EmailClient,
+  email_templates: &EmailTemplates,
+  new_email: &SubscriberEmail,
+  name: &str,
+  base_url: &ApplicationBaseUrl,
+  confirmation_token: &str,
+) -> Result<(), anyhow::Error> {
+  let confirmation_link = format!(
+    "{}/subscriptions/email/confirm?confirmation_token={}",
+    base_url.as_ref(),
+    confirmation_token,
+  );
+
+  let body = email_templates.email_change_confirmation(&EmailChangeConfirmation {
+    name,
+    confirmation_link: &confirmation_link,
+  })?;
+
+  email_client
+    .send_email(
+      new_email,
+      &format!("Confirm your new address, {}", name),
+      &body.html,
+      &body.text,
+    )
+    .await?;
+  Ok(())
+}
+
+/// Lets the previous address know that newsletters now go somewhere else.
+#[tracing::instrument(
+  name = "Send an email change notice",
+  skip(email_client, email_templates)
+)]
+pub async fn send_email_change_notice(
+  email_client: &EmailClient,
+  email_templates: &EmailTemplates,
+  old_email: &SubscriberEmail,
+  new_email: &str,
+) -> Result<(), anyhow::Error> {
+  let body = email_templates.email_change_notice(&EmailChangeNotice { new_email })?;
+
+  email_client
+    .send_email(
+      old_email,
+      "Your subscription address was changed",
+      &body.html,
+      &body.text,
+    )
+    .await?;
+  Ok(())
+}
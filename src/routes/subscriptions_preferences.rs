@@ -0,0 +1,679 @@
+use std::collections::HashSet;
+
+use actix_http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+  domain::{DeliveryFrequency, SubscriberName},
+  page_templates::{FrequencyChoice, ListChoice, PageTemplates, PreferencesPage},
+  routes::{error_chain_fmt, html_page},
+};
+
+/// The longest a subscriber may pause their subscription for.
+const MAX_PAUSE_WEEKS: i64 = 52;
+
+#[derive(Deserialize)]
+pub struct PreferencesParameters {
+  token: String,
+}
+
+#[derive(Deserialize)]
+pub struct PauseFormData {
+  token: String,
+  /// Pausing for 0 weeks resumes the subscription.
+  weeks: i64,
+}
+
+#[derive(Deserialize)]
+pub struct TrackingFormData {
+  token: String,
+  /// Whether the subscriber lets us know when they open an issue or follow its links.
+  allow_tracking: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UnsubscribeFormData {
+  token: String,
+}
+
+/// The main preferences form. Each checked list is submitted as its own `list` field,
+/// which is why the form is read as a sequence of pairs rather than a struct.
+pub struct PreferencesFormData {
+  token: String,
+  name: String,
+  frequency: String,
+  lists: Vec<String>,
+}
+
+impl PreferencesFormData {
+  fn from_pairs(pairs: Vec<(String, String)>) -> Result<Self, PreferencesError> {
+    let mut token = None;
+    let mut name = None;
+    let mut frequency = None;
+    let mut lists = Vec::new();
+    for (key, value) in pairs {
+      match key.as_str() {
+        "token" => token = Some(value),
+        "name" => name = Some(value),
+        "frequency" => frequency = Some(value),
+        "list" => lists.push(value),
+        _ => {}
+      }
+    }
+    let missing = |field: &str| PreferencesError::ValidationError(format!("{} is missing", field));
+    Ok(Self {
+      token: token.ok_or_else(|| missing("token"))?,
+      name: name.ok_or_else(|| missing("name"))?,
+      frequency: frequency.ok_or_else(|| missing("frequency"))?,
+      lists,
+    })
+  }
+}
+
+/// Everything wrong with a change of preferences, by form, as shown next to each form of the page.
+#[derive(Debug, Default, Serialize)]
+pub struct PreferencesFormErrors {
+  pub name: Vec<String>,
+  pub frequency: Vec<String>,
+  pub lists: Vec<String>,
+  pub pause: Vec<String>,
+  pub email: Vec<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+  #[error("The preferences link is not valid.")]
+  UnknownToken,
+  #[error("The link expired. Please request the change again.")]
+  ExpiredToken,
+  #[error("{0}")]
+  ValidationError(String),
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl ResponseError for PreferencesError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      PreferencesError::UnknownToken => StatusCode::UNAUTHORIZED,
+      PreferencesError::ExpiredToken => StatusCode::GONE,
+      PreferencesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  /// Invalid values are shown on the page, and invalid links get their own page,
+  /// so only a form that doesn't come from the page gets here.
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      PreferencesError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+      _ => HttpResponse::build(self.status_code())
+        .content_type("text/plain; charset=utf-8")
+        .body(self.to_string()),
+    }
+  }
+}
+
+/// Answers with the invalid link page when the token is unknown or expired,
+/// as it is for someone following the link of an old email.
+pub(crate) fn or_invalid_link_page(
+  result: Result<HttpResponse, PreferencesError>,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  match result {
+    Err(e @ (PreferencesError::UnknownToken | PreferencesError::ExpiredToken)) => {
+      Ok(html_page(e.status_code(), page_templates.invalid_link()?))
+    }
+    result => result,
+  }
+}
+
+/// A subscriber's current preferences, as shown on their preferences page.
+pub(crate) struct SubscriberPreferences {
+  pub subscriber_id: Uuid,
+  pub email: String,
+  pub name: String,
+  frequency: DeliveryFrequency,
+  paused_until: Option<DateTime<Utc>>,
+  tracking_opt_out: bool,
+  lists: Vec<ListMembership>,
+}
+
+struct ListMembership {
+  newsletter_id: Uuid,
+  slug: String,
+  name: String,
+  /// Subscriptions pending confirmation don't count.
+  subscribed: bool,
+}
+
+/// Shows the preferences page of the subscriber the token (from the link in every issue) belongs to.
+#[tracing::instrument(
+  name = "Show subscriber preferences",
+  skip(parameters, pool, page_templates)
+)]
+pub async fn preferences(
+  parameters: web::Query<PreferencesParameters>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+  let result = show_preferences(&parameters.token, &pool, &page_templates).await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn show_preferences(
+  token: &str,
+  pool: &PgPool,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let preferences = get_preferences(&mut transaction, token).await?;
+  render_preferences_page(
+    page_templates,
+    StatusCode::OK,
+    &preferences_page(token, &preferences, None, &PreferencesFormErrors::default()),
+  )
+}
+
+/// Updates the subscriber's name, delivery frequency and the lists they receive.
+#[tracing::instrument(
+  name = "Update subscriber preferences",
+  skip(form, pool, page_templates)
+)]
+pub async fn update_preferences(
+  form: web::Form<Vec<(String, String)>>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+  let form = PreferencesFormData::from_pairs(form.into_inner())?;
+  let result = save_preferences(form, &pool, &page_templates).await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn save_preferences(
+  form: PreferencesFormData,
+  pool: &PgPool,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let current = get_preferences(&mut transaction, &form.token).await?;
+
+  let mut errors = PreferencesFormErrors::default();
+  let name = SubscriberName::parse(form.name.clone())
+    .map_err(|es| errors.name.extend(es))
+    .ok();
+  let frequency = DeliveryFrequency::parse(form.frequency)
+    .map_err(|e| errors.frequency.push(e))
+    .ok();
+  let wanted_lists: HashSet<String> = form.lists.into_iter().collect();
+  errors.lists.extend(
+    wanted_lists
+      .iter()
+      .filter(|slug| !current.lists.iter().any(|list| &list.slug == *slug))
+      .map(|unknown| format!("There is no newsletter {}.", unknown)),
+  );
+  let (name, frequency) = match (name, frequency) {
+    (Some(name), Some(frequency)) if errors.lists.is_empty() => (name, frequency),
+    _ => {
+      let mut page = preferences_page(&form.token, &current, None, &errors);
+      page.name = &form.name;
+      return render_preferences_page(page_templates, StatusCode::BAD_REQUEST, &page);
+    }
+  };
+
+  if current.name != name.as_ref() {
+    sqlx::query!(
+      "UPDATE subscriptions SET name = $1 WHERE id = $2",
+      name.as_ref(),
+      current.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update a subscriber's name.")?;
+    record_subscription_change(
+      &mut transaction,
+      current.subscriber_id,
+      "name",
+      serde_json::json!({ "from": current.name, "to": name.as_ref() }),
+    )
+    .await?;
+  }
+
+  if current.frequency != frequency {
+    sqlx::query!(
+      "UPDATE subscriptions SET frequency = $1 WHERE id = $2",
+      frequency.as_str(),
+      current.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update a subscriber's delivery frequency.")?;
+    record_subscription_change(
+      &mut transaction,
+      current.subscriber_id,
+      "frequency",
+      serde_json::json!({ "from": current.frequency.as_str(), "to": frequency.as_str() }),
+    )
+    .await?;
+  }
+
+  let mut subscribed = Vec::new();
+  let mut unsubscribed = Vec::new();
+  for list in &current.lists {
+    let wanted = wanted_lists.contains(&list.slug);
+    if wanted && !list.subscribed {
+      // The subscriber proved they own the address by following the link we emailed them,
+      // so there is nothing left to confirm.
+      set_list_status(
+        &mut transaction,
+        list.newsletter_id,
+        current.subscriber_id,
+        "confirmed",
+      )
+      .await?;
+      subscribed.push(list.slug.as_str());
+    } else if !wanted && list.subscribed {
+      set_list_status(
+        &mut transaction,
+        list.newsletter_id,
+        current.subscriber_id,
+        "unsubscribed",
+      )
+      .await?;
+      unsubscribed.push(list.slug.as_str());
+    }
+  }
+  if !subscribed.is_empty() || !unsubscribed.is_empty() {
+    record_subscription_change(
+      &mut transaction,
+      current.subscriber_id,
+      "lists",
+      serde_json::json!({ "subscribed": subscribed, "unsubscribed": unsubscribed }),
+    )
+    .await?;
+  }
+
+  let updated = get_preferences(&mut transaction, &form.token).await?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to update subscriber preferences.")?;
+  render_preferences_page(
+    page_templates,
+    StatusCode::OK,
+    &preferences_page(
+      &form.token,
+      &updated,
+      Some("Your preferences have been saved."),
+      &PreferencesFormErrors::default(),
+    ),
+  )
+}
+
+/// Stops deliveries for the given number of weeks, or resumes them right away for 0 weeks.
+#[tracing::instrument(
+  name = "Pause subscription",
+  skip(form, pool, page_templates),
+  fields(weeks = form.weeks)
+)]
+pub async fn pause_subscription(
+  form: web::Form<PauseFormData>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+  let result = pause(&form, &pool, &page_templates).await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn pause(
+  form: &PauseFormData,
+  pool: &PgPool,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let current = get_preferences(&mut transaction, &form.token).await?;
+  if !(0..=MAX_PAUSE_WEEKS).contains(&form.weeks) {
+    let mut errors = PreferencesFormErrors::default();
+    errors.pause.push(format!(
+      "A subscription can be paused for at most {} weeks.",
+      MAX_PAUSE_WEEKS
+    ));
+    return render_preferences_page(
+      page_templates,
+      StatusCode::BAD_REQUEST,
+      &preferences_page(&form.token, &current, None, &errors),
+    );
+  }
+  let paused_until = (form.weeks > 0).then(|| Utc::now() + Duration::weeks(form.weeks));
+
+  sqlx::query!(
+    "UPDATE subscriptions SET paused_until = $1 WHERE id = $2",
+    paused_until,
+    current.subscriber_id,
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to pause a subscription.")?;
+  record_subscription_change(
+    &mut transaction,
+    current.subscriber_id,
+    "pause",
+    serde_json::json!({ "from": current.paused_until, "to": paused_until }),
+  )
+  .await?;
+
+  let updated = get_preferences(&mut transaction, &form.token).await?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to pause a subscription.")?;
+  let message = match paused_until {
+    Some(_) => "Your subscription has been paused.",
+    None => "Your subscription has been resumed.",
+  };
+  render_preferences_page(
+    page_templates,
+    StatusCode::OK,
+    &preferences_page(
+      &form.token,
+      &updated,
+      Some(message),
+      &PreferencesFormErrors::default(),
+    ),
+  )
+}
+
+/// Opts out of open and click tracking, or back in.
+#[tracing::instrument(
+  name = "Update tracking",
+  skip(form, pool, page_templates),
+  fields(allow_tracking = form.allow_tracking)
+)]
+pub async fn update_tracking(
+  form: web::Form<TrackingFormData>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+  let result = save_tracking(&form, &pool, &page_templates).await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn save_tracking(
+  form: &TrackingFormData,
+  pool: &PgPool,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let current = get_preferences(&mut transaction, &form.token).await?;
+  let tracking_opt_out = !form.allow_tracking;
+  if current.tracking_opt_out != tracking_opt_out {
+    sqlx::query!(
+      "UPDATE subscriptions SET tracking_opt_out = $1 WHERE id = $2",
+      tracking_opt_out,
+      current.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update a subscriber's tracking.")?;
+    record_subscription_change(
+      &mut transaction,
+      current.subscriber_id,
+      "tracking",
+      serde_json::json!({ "from": !current.tracking_opt_out, "to": form.allow_tracking }),
+    )
+    .await?;
+  }
+
+  let updated = get_preferences(&mut transaction, &form.token).await?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to update tracking.")?;
+  let message = if form.allow_tracking {
+    "We will know when you open our emails again."
+  } else {
+    "We will no longer know when you open our emails, or which links you follow."
+  };
+  render_preferences_page(
+    page_templates,
+    StatusCode::OK,
+    &preferences_page(
+      &form.token,
+      &updated,
+      Some(message),
+      &PreferencesFormErrors::default(),
+    ),
+  )
+}
+
+/// Unsubscribes from every list at once.
+/// The preferences page keeps working, so that the subscriber can change their mind.
+#[tracing::instrument(name = "Unsubscribe", skip(form, pool, page_templates))]
+pub async fn unsubscribe(
+  form: web::Form<UnsubscribeFormData>,
+  pool: web::Data<PgPool>,
+  page_templates: web::Data<PageTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+  let result = unsubscribe_from_everything(&form.token, &pool, &page_templates).await;
+  or_invalid_link_page(result, &page_templates)
+}
+
+async fn unsubscribe_from_everything(
+  token: &str,
+  pool: &PgPool,
+  page_templates: &PageTemplates,
+) -> Result<HttpResponse, PreferencesError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")?;
+  let current = get_preferences(&mut transaction, token).await?;
+  let mut unsubscribed = Vec::new();
+  for list in current.lists.iter().filter(|list| list.subscribed) {
+    set_list_status(
+      &mut transaction,
+      list.newsletter_id,
+      current.subscriber_id,
+      "unsubscribed",
+    )
+    .await?;
+    unsubscribed.push(list.slug.as_str());
+  }
+  record_subscription_change(
+    &mut transaction,
+    current.subscriber_id,
+    "unsubscribe",
+    serde_json::json!({ "unsubscribed": unsubscribed }),
+  )
+  .await?;
+
+  let updated = get_preferences(&mut transaction, token).await?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit SQL transaction to unsubscribe.")?;
+  render_preferences_page(
+    page_templates,
+    StatusCode::OK,
+    &preferences_page(
+      token,
+      &updated,
+      Some("You have been unsubscribed from every newsletter."),
+      &PreferencesFormErrors::default(),
+    ),
+  )
+}
+
+#[tracing::instrument(name = "Get subscriber preferences", skip(transaction, token))]
+pub(crate) async fn get_preferences(
+  transaction: &mut Transaction<'_, Postgres>,
+  token: &str,
+) -> Result<SubscriberPreferences, PreferencesError> {
+  let subscriber = sqlx::query!(
+    r#"
+    SELECT id, email, name, frequency, paused_until, tracking_opt_out
+    FROM subscriptions
+    WHERE preferences_token = $1
+    "#,
+    token,
+  )
+  .fetch_optional(&mut *transaction)
+  .await
+  .context("Failed to fetch a subscriber by their preferences token.")?
+  .ok_or(PreferencesError::UnknownToken)?;
+
+  let lists = sqlx::query!(
+    r#"
+    SELECT n.id, n.slug, n.name, ns.status AS "status?"
+    FROM newsletters n
+    LEFT JOIN newsletter_subscriptions ns
+      ON ns.newsletter_id = n.id AND ns.subscriber_id = $1
+    ORDER BY n.created_at
+    "#,
+    subscriber.id,
+  )
+  .fetch_all(&mut *transaction)
+  .await
+  .context("Failed to fetch a subscriber's newsletters.")?
+  .into_iter()
+  .map(|r| ListMembership {
+    newsletter_id: r.id,
+    slug: r.slug,
+    name: r.name,
+    subscribed: r.status.as_deref() == Some("confirmed"),
+  })
+  .collect();
+
+  Ok(SubscriberPreferences {
+    subscriber_id: subscriber.id,
+    email: subscriber.email,
+    name: subscriber.name,
+    frequency: DeliveryFrequency::parse(subscriber.frequency)
+      .map_err(|e| anyhow::anyhow!(e).context("A stored delivery frequency is invalid."))?,
+    paused_until: subscriber.paused_until,
+    tracking_opt_out: subscriber.tracking_opt_out,
+    lists,
+  })
+}
+
+async fn set_list_status(
+  transaction: &mut Transaction<'_, Postgres>,
+  newsletter_id: Uuid,
+  subscriber_id: Uuid,
+  status: &str,
+) -> Result<(), anyhow::Error> {
+  let now = Utc::now();
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_subscriptions
+      (newsletter_id, subscriber_id, status, subscribed_at, confirmed_at)
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (newsletter_id, subscriber_id) DO UPDATE
+      SET status = EXCLUDED.status,
+        confirmed_at = COALESCE(EXCLUDED.confirmed_at, newsletter_subscriptions.confirmed_at)
+    "#,
+    newsletter_id,
+    subscriber_id,
+    status,
+    now,
+    (status == "confirmed").then(|| now),
+  )
+  .execute(transaction)
+  .await
+  .context("Failed to update a newsletter subscription.")?;
+  Ok(())
+}
+
+/// Records a change a subscriber made to their own subscription.
+pub async fn record_subscription_change(
+  transaction: &mut Transaction<'_, Postgres>,
+  subscriber_id: Uuid,
+  change: &str,
+  details: serde_json::Value,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO subscription_changes (subscriber_id, changed_at, change, details)
+    VALUES ($1, $2, $3, $4)
+    "#,
+    subscriber_id,
+    Utc::now(),
+    change,
+    details,
+  )
+  .execute(transaction)
+  .await
+  .context("Failed to record a subscription change.")?;
+  Ok(())
+}
+
+/// The variables of the preferences page showing the stored preferences,
+/// which callers replace with what was submitted when it is invalid.
+pub(crate) fn preferences_page<'a>(
+  token: &'a str,
+  preferences: &'a SubscriberPreferences,
+  message: Option<&'a str>,
+  errors: &'a PreferencesFormErrors,
+) -> PreferencesPage<'a> {
+  PreferencesPage {
+    token,
+    name: &preferences.name,
+    email: &preferences.email,
+    frequencies: DeliveryFrequency::ALL
+      .iter()
+      .map(|frequency| FrequencyChoice {
+        value: frequency.as_str(),
+        description: frequency.description(),
+        selected: *frequency == preferences.frequency,
+      })
+      .collect(),
+    lists: preferences
+      .lists
+      .iter()
+      .map(|list| ListChoice {
+        slug: &list.slug,
+        name: &list.name,
+        subscribed: list.subscribed,
+      })
+      .collect(),
+    paused_until: preferences
+      .paused_until
+      .filter(|until| *until > Utc::now())
+      .map(|until| until.format("%B %-d, %Y").to_string()),
+    tracking_opt_out: preferences.tracking_opt_out,
+    max_pause_weeks: MAX_PAUSE_WEEKS,
+    message,
+    errors,
+  }
+}
+
+pub(crate) fn render_preferences_page(
+  page_templates: &PageTemplates,
+  status: StatusCode,
+  page: &PreferencesPage,
+) -> Result<HttpResponse, PreferencesError> {
+  Ok(html_page(status, page_templates.preferences(page)?))
+}
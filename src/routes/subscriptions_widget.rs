@@ -0,0 +1,37 @@
+use actix_http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{cors::CorsPolicy, problem::Problem};
+
+/// The script of the subscribe widget. See `static/widget.js` for how to embed it.
+const WIDGET_SCRIPT: &str = include_str!("../../static/widget.js");
+
+/// Serves the subscribe widget, which other sites embed with a script tag.
+/// It only works on the sites whose origin is allowed by the CORS policy.
+pub async fn subscribe_widget() -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type("text/javascript; charset=utf-8")
+    .insert_header((header::CACHE_CONTROL, "public, max-age=3600"))
+    .body(WIDGET_SCRIPT)
+}
+
+/// Answers the preflight request browsers send before posting JSON to `/subscriptions`
+/// from another site, telling them what the allowed origins may send.
+pub async fn subscriptions_preflight(
+  request: HttpRequest,
+  policy: web::Data<CorsPolicy>,
+) -> HttpResponse {
+  if policy.allowed_origin(request.headers()).is_none() {
+    return Problem::new(
+      StatusCode::FORBIDDEN,
+      "origin_not_allowed",
+      "This site may not subscribe people.",
+    )
+    .response();
+  }
+  HttpResponse::NoContent()
+    .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST"))
+    .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, "Accept, Content-Type"))
+    .insert_header((header::ACCESS_CONTROL_MAX_AGE, "86400"))
+    .finish()
+}
@@ -0,0 +1,174 @@
+use actix_http::{header, StatusCode};
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  html,
+  routes::error_chain_fmt,
+  tracking::{TrackingKey, TRACKING_PIXEL},
+};
+
+#[derive(Deserialize)]
+pub struct OpenParameters {
+  issue: Uuid,
+  subscriber: Uuid,
+  signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct ClickParameters {
+  issue: Uuid,
+  subscriber: Uuid,
+  url: String,
+  signature: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+  #[error("The tracking link is not valid.")]
+  InvalidSignature,
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl ResponseError for TrackingError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      TrackingError::InvalidSignature => StatusCode::NOT_FOUND,
+      TrackingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+}
+
+/// Serves the pixel of an issue, recording that the subscriber opened it.
+/// Subscribers who opted out since the issue was sent aren't recorded, nor are opens of
+/// issues of newsletters which stopped tracking them.
+#[tracing::instrument(name = "Track open", skip(parameters, pool, tracking_key))]
+pub async fn track_open(
+  parameters: web::Query<OpenParameters>,
+  pool: web::Data<PgPool>,
+  tracking_key: web::Data<TrackingKey>,
+) -> Result<HttpResponse, TrackingError> {
+  if !tracking_key.verify_open(
+    parameters.issue,
+    parameters.subscriber,
+    &parameters.signature,
+  ) {
+    return Err(TrackingError::InvalidSignature);
+  }
+  record_open(&pool, parameters.issue, parameters.subscriber).await?;
+  Ok(
+    HttpResponse::Ok()
+      .content_type("image/gif")
+      // Every open should reach us, rather than a cache.
+      .insert_header((header::CACHE_CONTROL, "no-store"))
+      .body(TRACKING_PIXEL.to_vec()),
+  )
+}
+
+/// Records that the subscriber followed a link of an issue, then redirects them to it.
+/// Only the links we signed are redirected to, so that we can't be used to disguise
+/// links to anywhere else.
+#[tracing::instrument(name = "Track click", skip(parameters, pool, tracking_key))]
+pub async fn track_click(
+  parameters: web::Query<ClickParameters>,
+  pool: web::Data<PgPool>,
+  tracking_key: web::Data<TrackingKey>,
+) -> Result<HttpResponse, TrackingError> {
+  let is_web_link = matches!(
+    html::url_scheme(&parameters.url).as_deref(),
+    Some("http" | "https")
+  );
+  if !is_web_link
+    || !tracking_key.verify_click(
+      parameters.issue,
+      parameters.subscriber,
+      &parameters.url,
+      &parameters.signature,
+    )
+  {
+    return Err(TrackingError::InvalidSignature);
+  }
+  record_click(
+    &pool,
+    parameters.issue,
+    parameters.subscriber,
+    &parameters.url,
+  )
+  .await?;
+  Ok(
+    HttpResponse::Found()
+      .insert_header((header::LOCATION, parameters.url.as_str()))
+      .insert_header((header::CACHE_CONTROL, "no-store"))
+      .finish(),
+  )
+}
+
+#[tracing::instrument(name = "Record open", skip(pool))]
+async fn record_open(
+  pool: &PgPool,
+  issue_id: Uuid,
+  subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO issue_opens (issue_id, subscriber_id, first_opened_at, last_opened_at, open_count)
+    SELECT i.id, s.id, $3, $3, 1
+    FROM newsletter_issues i
+    JOIN newsletters n ON n.id = i.newsletter_id
+    CROSS JOIN subscriptions s
+    WHERE i.id = $1 AND s.id = $2 AND n.track_opens AND NOT s.tracking_opt_out
+    ON CONFLICT (issue_id, subscriber_id) DO UPDATE
+      SET last_opened_at = EXCLUDED.last_opened_at,
+        open_count = issue_opens.open_count + 1
+    "#,
+    issue_id,
+    subscriber_id,
+    Utc::now(),
+  )
+  .execute(pool)
+  .await
+  .context("Failed to record an open.")?;
+  Ok(())
+}
+
+/// Like opens, clicks of subscribers who opted out since the issue was sent aren't recorded.
+#[tracing::instrument(name = "Record click", skip(pool))]
+async fn record_click(
+  pool: &PgPool,
+  issue_id: Uuid,
+  subscriber_id: Uuid,
+  url: &str,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO link_clicks
+      (issue_id, subscriber_id, url, first_clicked_at, last_clicked_at, click_count)
+    SELECT i.id, s.id, $3, $4, $4, 1
+    FROM newsletter_issues i
+    CROSS JOIN subscriptions s
+    WHERE i.id = $1 AND s.id = $2 AND NOT s.tracking_opt_out
+    ON CONFLICT (issue_id, subscriber_id, url) DO UPDATE
+      SET last_clicked_at = EXCLUDED.last_clicked_at,
+        click_count = link_clicks.click_count + 1
+    "#,
+    issue_id,
+    subscriber_id,
+    url,
+    Utc::now(),
+  )
+  .execute(pool)
+  .await
+  .context("Failed to record a click.")?;
+  Ok(())
+}
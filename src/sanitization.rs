@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, HashSet};
+
+use crate::{configuration::SanitizationSettings, html};
+
+/// Cleans the html of submitted issues down to an allowlist of tags, attributes and URL schemes,
+/// so that scripts, forms or tracking iframes never reach subscribers.
+#[derive(Debug)]
+pub struct HtmlSanitizer {
+  tags: HashSet<String>,
+  attributes: HashSet<String>,
+  url_schemes: HashSet<String>,
+  /// Whether html which needs cleaning is rejected, rather than cleaned.
+  pub strict: bool,
+}
+
+/// Sanitized html, and a description of everything that was removed from it, e.g. `<script> element`.
+#[derive(Debug)]
+pub struct SanitizedHtml {
+  pub html: String,
+  pub stripped: Vec<String>,
+}
+
+/// Attributes which hold a URL, whose scheme is checked.
+const URL_ATTRIBUTES: [&str; 3] = ["href", "src", "background"];
+
+/// Tags which are removed along with their content, unless they are allowed.
+const CLEAN_CONTENT_TAGS: [&str; 2] = ["script", "style"];
+
+impl HtmlSanitizer {
+  pub fn new(settings: &SanitizationSettings) -> Self {
+    let lowercase = |values: &[String]| values.iter().map(|v| v.to_ascii_lowercase()).collect();
+    Self {
+      tags: lowercase(&settings.allowed_tags),
+      attributes: lowercase(&settings.allowed_attributes),
+      url_schemes: lowercase(&settings.allowed_url_schemes),
+      strict: settings.strict,
+    }
+  }
+
+  pub fn sanitize(&self, html: &str) -> SanitizedHtml {
+    let clean_content_tags = CLEAN_CONTENT_TAGS
+      .iter()
+      .copied()
+      .filter(|tag| !self.tags.contains(*tag))
+      .collect();
+    let cleaned = ammonia::Builder::empty()
+      .tags(self.tags.iter().map(String::as_str).collect())
+      .clean_content_tags(clean_content_tags)
+      .tag_attributes(Default::default())
+      .generic_attributes(self.attributes.iter().map(String::as_str).collect())
+      .url_schemes(self.url_schemes.iter().map(String::as_str).collect())
+      // Relative URLs are resolved against the base URL when the issue is sent.
+      .url_relative(ammonia::UrlRelative::PassThrough)
+      .link_rel(None)
+      .strip_comments(true)
+      .clean(html)
+      .to_string();
+    SanitizedHtml {
+      html: cleaned,
+      stripped: self.disallowed(html::tags(html).filter(|tag| !tag.closing)),
+    }
+  }
+
+  /// Describes every disallowed tag, attribute and URL scheme among the tags.
+  fn disallowed<'a>(&self, tags: impl Iterator<Item = html::Tag<'a>>) -> Vec<String> {
+    let mut stripped = BTreeSet::new();
+    for tag in tags {
+      if !self.tags.contains(&tag.name) {
+        stripped.insert(format!("<{}> element", tag.name));
+        continue;
+      }
+      for (name, value) in tag.attributes {
+        if !self.attributes.contains(&name) {
+          stripped.insert(format!("{} attribute", name));
+        } else if URL_ATTRIBUTES.contains(&name.as_str()) {
+          if let Some(scheme) = html::url_scheme(value) {
+            if !self.url_schemes.contains(&scheme) {
+              stripped.insert(format!("{}: URL", scheme));
+            }
+          }
+        }
+      }
+    }
+    stripped.into_iter().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::HtmlSanitizer;
+  use crate::configuration::SanitizationSettings;
+
+  fn sanitizer() -> HtmlSanitizer {
+    let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+    HtmlSanitizer::new(&SanitizationSettings {
+      strict: false,
+      allowed_tags: strings(&["p", "a", "img", "style", "strong"]),
+      allowed_attributes: strings(&["href", "src", "alt", "style"]),
+      allowed_url_schemes: strings(&["https", "mailto"]),
+    })
+  }
+
+  #[test]
+  fn allowed_html_is_unchanged() {
+    let html = r#"<style>p { color: red; }</style><p>Hi <strong>{{ name }}</strong>, <a href="https://example.com/x">read</a> <img src="/logo.png" alt="logo"></p>"#;
+    let sanitized = sanitizer().sanitize(html);
+    assert_eq!(sanitized.html, html);
+    assert!(sanitized.stripped.is_empty());
+  }
+
+  #[test]
+  fn disallowed_html_is_stripped_and_reported() {
+    let sanitized = sanitizer().sanitize(
+      r#"<p onclick="steal()">Hi</p><script>alert(1)</script>
+      <iframe src="https://tracker.example.com"></iframe>
+      <form action="https://example.com"><input name="password"></form>
+      <a href="javascript:alert(1)">click</a><!-- a comment -->"#,
+    );
+    for removed in [
+      "onclick",
+      "<script>",
+      "alert(1)",
+      "<iframe",
+      "<form",
+      "<input",
+      "javascript:",
+      "comment",
+    ] {
+      assert!(!sanitized.html.contains(removed), "{}", removed);
+    }
+    assert_eq!(
+      sanitized.stripped,
+      vec![
+        "<form> element",
+        "<iframe> element",
+        "<input> element",
+        "<script> element",
+        "javascript: URL",
+        "onclick attribute",
+      ]
+    );
+  }
+}
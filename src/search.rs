@@ -0,0 +1,114 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::html;
+
+/// Where a match starts and ends in the snippets Postgres highlights, as private use characters
+/// so that the snippets can be escaped before being marked. They're removed from the text of
+/// the issues first, so that an issue can't mark its own snippets.
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+/// An issue which matches a search.
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+  pub id: Uuid,
+  pub slug: String,
+  pub title: String,
+  /// Name of the newsletter the issue was published to.
+  pub newsletter: String,
+  pub public: bool,
+  pub published_at: DateTime<Utc>,
+  /// Higher for better matches, e.g. when the terms are in the title.
+  pub rank: f32,
+  /// Excerpts of the text around the matches, as html with the matching words in `<mark>`.
+  pub snippet: String,
+}
+
+/// Which issues are searched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchScope {
+  /// What the web archive shows anyone.
+  Public,
+  /// Every issue, private ones included, for admins.
+  All,
+}
+
+/// Issues matching the query, best first.
+/// The query is written like in a web search engine: words, `"quoted phrases"`, `or`,
+/// and `-` to exclude a word. See `websearch_to_tsquery`.
+#[tracing::instrument(name = "Search issues", skip(pool))]
+pub async fn search_issues(
+  pool: &PgPool,
+  query: &str,
+  scope: SearchScope,
+  offset: i64,
+  limit: i64,
+) -> Result<Vec<SearchResult>, anyhow::Error> {
+  let headline_options = format!(
+    "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=30, MinWords=10, FragmentDelimiter=\" … \"",
+    MATCH_START, MATCH_END
+  );
+  let rows = sqlx::query!(
+    r#"
+    SELECT i.id, i.slug, i.title, n.name AS newsletter, i.public, i.published_at,
+      ts_rank_cd(i.search_vector, q.query) AS "rank!",
+      ts_headline('english', translate(i.text_content, $6, ''), q.query, $2) AS "snippet!"
+    FROM newsletter_issues i
+    JOIN newsletters n ON n.id = i.newsletter_id
+    CROSS JOIN websearch_to_tsquery('english', $1) AS q(query)
+    WHERE i.search_vector @@ q.query AND (i.public OR $3)
+    ORDER BY 7 DESC, i.published_at DESC, i.id
+    OFFSET $4 LIMIT $5
+    "#,
+    query,
+    headline_options,
+    scope == SearchScope::All,
+    offset,
+    limit,
+    format!("{}{}", MATCH_START, MATCH_END),
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to search the issues.")?;
+
+  Ok(
+    rows
+      .into_iter()
+      .map(|r| SearchResult {
+        id: r.id,
+        slug: r.slug,
+        title: r.title,
+        newsletter: r.newsletter,
+        public: r.public,
+        published_at: r.published_at,
+        rank: r.rank,
+        snippet: highlight(&r.snippet),
+      })
+      .collect(),
+  )
+}
+
+/// Escapes the snippet, then marks what Postgres highlighted in it.
+fn highlight(snippet: &str) -> String {
+  html::escape(snippet.trim())
+    .replace(MATCH_START, "<mark>")
+    .replace(MATCH_END, "</mark>")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{highlight, MATCH_END, MATCH_START};
+
+  #[test]
+  fn snippets_are_escaped_before_matches_are_marked() {
+    let snippet = format!(" <b>{}async{} Rust</b> & more", MATCH_START, MATCH_END);
+    assert_eq!(
+      highlight(&snippet),
+      "&lt;b&gt;<mark>async</mark> Rust&lt;/b&gt; &amp; more"
+    );
+  }
+}
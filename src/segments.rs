@@ -0,0 +1,267 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::domain::SubscriberTag;
+
+/// The longest period `within_days` can cover, about a century, which is more than any
+/// subscriber has been around. Dates can't go back arbitrarily far.
+const MAX_WITHIN_DAYS: i64 = 36_500;
+
+/// Describes which subscribers an issue is sent to, e.g. everyone tagged `beta`
+/// or who subscribed in the last 30 days:
+///
+/// ```json
+/// { "any": [{ "tag": "beta" }, { "subscribed_at": { "within_days": 30 } }] }
+/// ```
+///
+/// Segments only ever narrow down the confirmed subscribers of a newsletter, which is why
+/// there is no condition on the status of a subscription.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Segment {
+  /// Matches when every segment matches, which is everyone when there are none.
+  All(Vec<Segment>),
+  /// Matches when at least one segment matches.
+  Any(Vec<Segment>),
+  Not(Box<Segment>),
+  Tag(String),
+  HasAttribute(String),
+  Attribute(AttributeCondition),
+  SubscribedAt(DateRange),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AttributeCondition {
+  pub name: String,
+  pub equals: Value,
+}
+
+/// Every bound is optional, and bounds are combined.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DateRange {
+  /// Inclusive.
+  pub after: Option<DateTime<Utc>>,
+  /// Exclusive.
+  pub before: Option<DateTime<Utc>>,
+  pub within_days: Option<i64>,
+}
+
+/// What segments are matched against.
+pub struct SubscriberProfile<'a> {
+  pub tags: &'a [String],
+  pub attributes: &'a Map<String, Value>,
+  pub subscribed_at: DateTime<Utc>,
+}
+
+impl Segment {
+  /// Checks the parts of the segment which deserializing can't, reporting every problem at once.
+  pub fn validate(&self) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    self.collect_errors(&mut errors);
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(errors)
+    }
+  }
+
+  fn collect_errors(&self, errors: &mut Vec<String>) {
+    match self {
+      Segment::All(segments) | Segment::Any(segments) => {
+        for segment in segments {
+          segment.collect_errors(errors);
+        }
+      }
+      Segment::Not(segment) => segment.collect_errors(errors),
+      Segment::Tag(tag) => {
+        if let Err(e) = SubscriberTag::parse(tag.clone()) {
+          errors.push(e);
+        }
+      }
+      Segment::HasAttribute(_) => {}
+      Segment::Attribute(condition) => {
+        if !matches!(
+          condition.equals,
+          Value::String(_) | Value::Number(_) | Value::Bool(_)
+        ) {
+          errors.push(format!(
+            "attribute {} can only be compared to a string, a number or a boolean",
+            condition.name
+          ));
+        }
+      }
+      Segment::SubscribedAt(range) => {
+        if range.after.is_none() && range.before.is_none() && range.within_days.is_none() {
+          errors
+            .push("subscribed_at needs at least one of after, before or within_days".to_string());
+        }
+        if range.within_days.is_some_and(|days| days < 0) {
+          errors.push("within_days cannot be negative".to_string());
+        }
+        if range.within_days.is_some_and(|days| days > MAX_WITHIN_DAYS) {
+          errors.push(format!(
+            "within_days cannot be more than {}",
+            MAX_WITHIN_DAYS
+          ));
+        }
+      }
+    }
+  }
+
+  pub fn matches(&self, subscriber: &SubscriberProfile, now: DateTime<Utc>) -> bool {
+    match self {
+      Segment::All(segments) => segments.iter().all(|s| s.matches(subscriber, now)),
+      Segment::Any(segments) => segments.iter().any(|s| s.matches(subscriber, now)),
+      Segment::Not(segment) => !segment.matches(subscriber, now),
+      Segment::Tag(tag) => subscriber.tags.contains(tag),
+      Segment::HasAttribute(name) => subscriber
+        .attributes
+        .get(name)
+        .is_some_and(|value| !value.is_null()),
+      Segment::Attribute(condition) => {
+        subscriber.attributes.get(&condition.name) == Some(&condition.equals)
+      }
+      Segment::SubscribedAt(range) => {
+        range
+          .after
+          .is_none_or(|after| subscriber.subscribed_at >= after)
+          && range
+            .before
+            .is_none_or(|before| subscriber.subscribed_at < before)
+          && range
+            .within_days
+            .is_none_or(|days| subscriber.subscribed_at >= now - Duration::days(days))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Segment, SubscriberProfile};
+  use chrono::{Duration, Utc};
+  use claim::{assert_err, assert_ok};
+  use serde_json::{json, Map, Value};
+
+  fn segment(value: Value) -> Segment {
+    serde_json::from_value(value).unwrap()
+  }
+
+  fn subscriber_matches(
+    segment: &Segment,
+    tags: &[&str],
+    attributes: Value,
+    days_ago: i64,
+  ) -> bool {
+    let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+    let attributes: Map<String, Value> = attributes.as_object().unwrap().clone();
+    let now = Utc::now();
+    segment.matches(
+      &SubscriberProfile {
+        tags: &tags,
+        attributes: &attributes,
+        subscribed_at: now - Duration::days(days_ago),
+      },
+      now,
+    )
+  }
+
+  #[test]
+  fn an_empty_all_matches_everyone() {
+    assert!(subscriber_matches(
+      &segment(json!({ "all": [] })),
+      &[],
+      json!({}),
+      100
+    ));
+  }
+
+  #[test]
+  fn tags_must_be_present() {
+    let beta = segment(json!({ "tag": "beta" }));
+    assert!(subscriber_matches(&beta, &["beta"], json!({}), 0));
+    assert!(!subscriber_matches(&beta, &["alpha"], json!({}), 0));
+  }
+
+  #[test]
+  fn recent_subscribers_are_matched_by_within_days() {
+    let recent = segment(json!({ "subscribed_at": { "within_days": 30 } }));
+    assert!(subscriber_matches(&recent, &[], json!({}), 29));
+    assert!(!subscriber_matches(&recent, &[], json!({}), 31));
+  }
+
+  #[test]
+  fn beta_or_recent_subscribers_are_matched() {
+    let beta_or_recent = segment(json!({
+      "any": [{ "tag": "beta" }, { "subscribed_at": { "within_days": 30 } }]
+    }));
+    assert!(subscriber_matches(
+      &beta_or_recent,
+      &["beta"],
+      json!({}),
+      100
+    ));
+    assert!(subscriber_matches(&beta_or_recent, &[], json!({}), 1));
+    assert!(!subscriber_matches(&beta_or_recent, &[], json!({}), 100));
+  }
+
+  #[test]
+  fn attributes_are_compared_by_value() {
+    let pro = segment(json!({ "attribute": { "name": "plan", "equals": "pro" } }));
+    assert!(subscriber_matches(&pro, &[], json!({ "plan": "pro" }), 0));
+    assert!(!subscriber_matches(&pro, &[], json!({ "plan": "free" }), 0));
+    assert!(!subscriber_matches(&pro, &[], json!({}), 0));
+
+    let has_city = segment(json!({ "has_attribute": "city" }));
+    assert!(subscriber_matches(
+      &has_city,
+      &[],
+      json!({ "city": "Montreal" }),
+      0
+    ));
+  }
+
+  #[test]
+  fn segments_can_be_negated_and_combined() {
+    let not_beta_pros = segment(json!({
+      "all": [
+        { "not": { "tag": "beta" } },
+        { "attribute": { "name": "plan", "equals": "pro" } }
+      ]
+    }));
+    assert!(subscriber_matches(
+      &not_beta_pros,
+      &[],
+      json!({ "plan": "pro" }),
+      0
+    ));
+    assert!(!subscriber_matches(
+      &not_beta_pros,
+      &["beta"],
+      json!({ "plan": "pro" }),
+      0
+    ));
+  }
+
+  #[test]
+  fn invalid_segments_are_rejected() {
+    assert_err!(segment(json!({ "tag": "Beta" })).validate());
+    assert_err!(segment(json!({ "subscribed_at": {} })).validate());
+    assert_err!(segment(json!({ "subscribed_at": { "within_days": -1 } })).validate());
+    assert_err!(segment(json!({ "subscribed_at": { "within_days": 36_501 } })).validate());
+    assert_err!(segment(json!({ "subscribed_at": { "within_days": 1_000_000_000 } })).validate());
+    assert_err!(segment(json!({ "attribute": { "name": "plan", "equals": ["pro"] } })).validate());
+    assert!(serde_json::from_value::<Segment>(json!({ "tags": "beta" })).is_err());
+    assert!(serde_json::from_value::<Segment>(json!({ "status": "confirmed" })).is_err());
+  }
+
+  #[test]
+  fn valid_segments_are_accepted() {
+    assert_ok!(segment(json!({
+      "any": [{ "tag": "beta" }, { "subscribed_at": { "within_days": 30 } }]
+    }))
+    .validate());
+    assert_ok!(segment(json!({ "subscribed_at": { "within_days": 36_500 } })).validate());
+  }
+}
@@ -0,0 +1,485 @@
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  hash::Hash,
+  net::IpAddr,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use actix_http::StatusCode;
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+use crate::configuration::SignupProtectionSettings;
+
+/// Past this many tracked keys, expired entries are pruned so that
+/// a bot cycling through addresses cannot grow the maps without bound.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Window of the per IP and per domain limits.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// What a subscription is submitted with, besides the subscriber, to show it comes from a person.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct SignupProof {
+  /// The signed token the form was served with.
+  pub form_token: Option<String>,
+  /// The answer to the proof of work challenge of the form token.
+  pub proof_of_work: Option<String>,
+  /// The honeypot: a field hidden from people, which only bots fill in.
+  pub website: Option<String>,
+}
+
+impl SignupProof {
+  pub fn fills_honeypot(&self) -> bool {
+    self
+      .website
+      .as_deref()
+      .is_some_and(|w| !w.trim().is_empty())
+  }
+}
+
+/// What a form is served with, and must be submitted with.
+#[derive(Debug, serde::Serialize)]
+pub struct SignupChallenge {
+  pub form_token: String,
+  /// Leading zero bits of the SHA-256 hash of `{form_token}:{proof_of_work}`, 0 if there is no challenge.
+  pub proof_of_work_difficulty: u8,
+}
+
+/// Why a subscription was taken for a bot's. The messages are shown on the form.
+#[derive(thiserror::Error, Debug)]
+pub enum SignupRejection {
+  #[error("The form is invalid. Please reload the page and submit it again.")]
+  InvalidFormToken,
+  #[error("The form expired. Please submit it again.")]
+  ExpiredFormToken,
+  #[error("The form was submitted too quickly. Please submit it again.")]
+  SubmittedTooFast,
+  #[error(
+    "Your browser could not prove the form was not submitted by a bot. Please submit it again."
+  )]
+  InvalidProofOfWork,
+  #[error("Too many subscriptions were submitted. Please try again later.")]
+  TooManySignups(Duration),
+}
+
+impl SignupRejection {
+  pub fn code(&self) -> &'static str {
+    match self {
+      SignupRejection::InvalidFormToken => "invalid_form_token",
+      SignupRejection::ExpiredFormToken => "expired_form_token",
+      SignupRejection::SubmittedTooFast => "submitted_too_fast",
+      SignupRejection::InvalidProofOfWork => "invalid_proof_of_work",
+      SignupRejection::TooManySignups(_) => "too_many_subscriptions",
+    }
+  }
+
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      SignupRejection::TooManySignups(_) => StatusCode::TOO_MANY_REQUESTS,
+      _ => StatusCode::BAD_REQUEST,
+    }
+  }
+
+  /// How long the client must wait before subscribing again, when rate limited.
+  pub fn retry_after(&self) -> Option<Duration> {
+    match self {
+      SignupRejection::TooManySignups(retry_after) => Some(*retry_after),
+      _ => None,
+    }
+  }
+}
+
+/// Keeps bots from having us send confirmation emails to random addresses, without a
+/// third-party CAPTCHA. Forms are served with a signed token saying when they were served,
+/// and can only be submitted once, after a few seconds and before they expire, optionally
+/// with a proof of work over the token. Subscriptions are also limited per IP and per
+/// email domain, except for the webmail domains everyone shares. State is kept in memory,
+/// so each instance enforces the limits on its own.
+pub struct SignupGuard {
+  key: Vec<u8>,
+  enabled: bool,
+  min_submit_time: Duration,
+  max_form_age: Duration,
+  proof_of_work_difficulty: u8,
+  /// Signatures of the tokens already submitted, with when they expire in milliseconds.
+  used_tokens: Mutex<HashMap<String, i64>>,
+  ips: RateLimit<IpAddr>,
+  domains: RateLimit<String>,
+  /// Lowercase domains exempt from the per domain limit.
+  unlimited_domains: HashSet<String>,
+}
+
+impl std::fmt::Debug for SignupGuard {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("SignupGuard(..)")
+  }
+}
+
+impl SignupGuard {
+  pub fn new(settings: &SignupProtectionSettings, secret: &str) -> Self {
+    Self {
+      key: secret.as_bytes().to_vec(),
+      enabled: settings.enabled,
+      min_submit_time: settings.min_submit_time,
+      max_form_age: settings.max_form_age,
+      proof_of_work_difficulty: settings.proof_of_work_difficulty,
+      used_tokens: Mutex::new(HashMap::new()),
+      ips: RateLimit::new(settings.ip_signups_per_hour),
+      domains: RateLimit::new(settings.domain_signups_per_hour),
+      unlimited_domains: settings
+        .unlimited_domains
+        .iter()
+        .map(|domain| domain.to_lowercase())
+        .collect(),
+    }
+  }
+
+  /// A fresh token for a form being served.
+  pub fn challenge(&self) -> SignupChallenge {
+    SignupChallenge {
+      form_token: self.issue_token(Utc::now().timestamp_millis()),
+      proof_of_work_difficulty: if self.enabled {
+        self.proof_of_work_difficulty
+      } else {
+        0
+      },
+    }
+  }
+
+  /// Accepts a subscription to an address of `email_domain`, counting it against the limits,
+  /// or tells why it looks like a bot's.
+  pub fn check(
+    &self,
+    proof: &SignupProof,
+    ip: Option<IpAddr>,
+    email_domain: &str,
+  ) -> Result<(), SignupRejection> {
+    if !self.enabled {
+      return Ok(());
+    }
+    let form_token = proof
+      .form_token
+      .as_deref()
+      .ok_or(SignupRejection::InvalidFormToken)?;
+    let (issued_at, signature) = self
+      .verify_token(form_token)
+      .ok_or(SignupRejection::InvalidFormToken)?;
+    let now = Utc::now().timestamp_millis();
+    let age = Duration::from_millis(now.saturating_sub(issued_at).max(0) as u64);
+    if age < self.min_submit_time {
+      return Err(SignupRejection::SubmittedTooFast);
+    }
+    if age > self.max_form_age {
+      return Err(SignupRejection::ExpiredFormToken);
+    }
+    if self.proof_of_work_difficulty > 0 {
+      let answer = proof.proof_of_work.as_deref().unwrap_or_default();
+      if !is_proof_of_work(form_token, answer, self.proof_of_work_difficulty) {
+        return Err(SignupRejection::InvalidProofOfWork);
+      }
+    }
+
+    let mut used_tokens = self.used_tokens.lock().unwrap();
+    if used_tokens.contains_key(signature) {
+      return Err(SignupRejection::ExpiredFormToken);
+    }
+    let instant = Instant::now();
+    // Bots could otherwise use up the limit of a domain like gmail.com, turning everyone away.
+    let domain_limited = !self.unlimited_domains.contains(email_domain);
+    let domain_wait = if domain_limited {
+      self.domains.wait(email_domain, instant)
+    } else {
+      None
+    };
+    let wait = ip
+      .and_then(|ip| self.ips.wait(&ip, instant))
+      .max(domain_wait);
+    if let Some(wait) = wait {
+      return Err(SignupRejection::TooManySignups(wait));
+    }
+
+    if used_tokens.len() > PRUNE_THRESHOLD {
+      used_tokens.retain(|_, expires_at| *expires_at > now);
+    }
+    let expires_at = issued_at.saturating_add(self.max_form_age.as_millis() as i64);
+    used_tokens.insert(signature.to_string(), expires_at);
+    if let Some(ip) = ip {
+      self.ips.record(ip, instant);
+    }
+    if domain_limited {
+      self.domains.record(email_domain.to_string(), instant);
+    }
+    Ok(())
+  }
+
+  /// `{issued_at}.{nonce}.{signature}`, where `issued_at` is in milliseconds since the epoch.
+  /// The nonce makes every token, and so every proof of work challenge, different.
+  fn issue_token(&self, issued_at: i64) -> String {
+    let nonce: String = thread_rng()
+      .sample_iter(Alphanumeric)
+      .map(char::from)
+      .take(16)
+      .collect();
+    let payload = format!("{}.{}", issued_at, nonce);
+    let signature = base64::encode_config(
+      self.mac(&payload).finalize().into_bytes(),
+      base64::URL_SAFE_NO_PAD,
+    );
+    format!("{}.{}", payload, signature)
+  }
+
+  /// When the token was issued, and its signature, if it was signed by us.
+  fn verify_token<'a>(&self, token: &'a str) -> Option<(i64, &'a str)> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let (issued_at, _nonce) = payload.split_once('.')?;
+    let decoded = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+    // Compares in constant time, so that a signature can't be found byte by byte.
+    self.mac(payload).verify(&decoded).ok()?;
+    Some((issued_at.parse().ok()?, signature))
+  }
+
+  fn mac(&self, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+      Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+    mac.update(format!("signup:{}", payload).as_bytes());
+    mac
+  }
+}
+
+/// Whether the SHA-256 hash of `{form_token}:{answer}` starts with `difficulty` zero bits.
+fn is_proof_of_work(form_token: &str, answer: &str, difficulty: u8) -> bool {
+  let hash = Sha256::digest(format!("{}:{}", form_token, answer).as_bytes());
+  leading_zero_bits(&hash) >= u32::from(difficulty)
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+  let mut bits = 0;
+  for byte in bytes {
+    bits += byte.leading_zeros();
+    if *byte != 0 {
+      break;
+    }
+  }
+  bits
+}
+
+/// Counts events per key over a sliding window of an hour. A limit of 0 turns it off.
+struct RateLimit<K> {
+  events: Mutex<HashMap<K, VecDeque<Instant>>>,
+  limit: u32,
+}
+
+impl<K: Eq + Hash> RateLimit<K> {
+  fn new(limit: u32) -> Self {
+    Self {
+      events: Mutex::new(HashMap::new()),
+      limit,
+    }
+  }
+
+  /// How long until the key is under its limit again, if it has reached it.
+  fn wait<Q>(&self, key: &Q, now: Instant) -> Option<Duration>
+  where
+    K: std::borrow::Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+  {
+    if self.limit == 0 {
+      return None;
+    }
+    let events = self.events.lock().unwrap();
+    let recent: Vec<&Instant> = events
+      .get(key)?
+      .iter()
+      .filter(|at| now.saturating_duration_since(**at) < RATE_LIMIT_WINDOW)
+      .collect();
+    if recent.len() < self.limit as usize {
+      return None;
+    }
+    // Once the oldest events counted against the limit leave the window, there is room again.
+    let oldest = recent[recent.len() - self.limit as usize..].first()?;
+    Some(RATE_LIMIT_WINDOW.saturating_sub(now.saturating_duration_since(**oldest)))
+  }
+
+  fn record(&self, key: K, now: Instant) {
+    let mut events = self.events.lock().unwrap();
+    if events.len() > PRUNE_THRESHOLD {
+      events.retain(|_, at| {
+        at.back()
+          .is_some_and(|last| now.saturating_duration_since(*last) < RATE_LIMIT_WINDOW)
+      });
+    }
+    let at = events.entry(key).or_default();
+    while at
+      .front()
+      .is_some_and(|first| now.saturating_duration_since(*first) >= RATE_LIMIT_WINDOW)
+    {
+      at.pop_front();
+    }
+    at.push_back(now);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    is_proof_of_work, leading_zero_bits, RateLimit, SignupGuard, SignupProof, SignupRejection,
+  };
+  use crate::configuration::SignupProtectionSettings;
+  use chrono::Utc;
+  use claim::{assert_none, assert_ok, assert_some};
+  use std::time::{Duration, Instant};
+
+  fn guard(proof_of_work_difficulty: u8) -> SignupGuard {
+    SignupGuard::new(
+      &SignupProtectionSettings {
+        enabled: true,
+        min_submit_time: Duration::from_secs(3),
+        max_form_age: Duration::from_secs(3600),
+        ip_signups_per_hour: 10,
+        domain_signups_per_hour: 10,
+        unlimited_domains: vec!["Gmail.com".to_string()],
+        proof_of_work_difficulty,
+      },
+      "secret",
+    )
+  }
+
+  fn proof(form_token: String) -> SignupProof {
+    SignupProof {
+      form_token: Some(form_token),
+      ..SignupProof::default()
+    }
+  }
+
+  fn token_issued_ago(guard: &SignupGuard, ago: Duration) -> String {
+    guard.issue_token(Utc::now().timestamp_millis() - ago.as_millis() as i64)
+  }
+
+  #[test]
+  fn forms_are_accepted_once_between_the_minimum_time_and_their_expiry() {
+    let guard = guard(0);
+    let check = |token: String| guard.check(&proof(token), None, "example.com");
+
+    assert_ok!(check(token_issued_ago(&guard, Duration::from_secs(5))));
+    assert!(matches!(
+      check(guard.challenge().form_token),
+      Err(SignupRejection::SubmittedTooFast)
+    ));
+    assert!(matches!(
+      check(token_issued_ago(&guard, Duration::from_secs(7200))),
+      Err(SignupRejection::ExpiredFormToken)
+    ));
+
+    let token = token_issued_ago(&guard, Duration::from_secs(5));
+    assert_ok!(check(token.clone()));
+    assert!(matches!(
+      check(token),
+      Err(SignupRejection::ExpiredFormToken)
+    ));
+  }
+
+  #[test]
+  fn forged_tokens_are_rejected() {
+    let guard = guard(0);
+    let token = token_issued_ago(&guard, Duration::from_secs(5));
+    let (_, nonce_and_signature) = token.split_once('.').unwrap();
+    let backdated = format!(
+      "{}.{}",
+      Utc::now().timestamp_millis() - 60_000,
+      nonce_and_signature
+    );
+    let other_key = SignupGuard::new(
+      &SignupProtectionSettings {
+        enabled: true,
+        min_submit_time: Duration::ZERO,
+        max_form_age: Duration::from_secs(3600),
+        ip_signups_per_hour: 10,
+        domain_signups_per_hour: 10,
+        unlimited_domains: vec!["Gmail.com".to_string()],
+        proof_of_work_difficulty: 0,
+      },
+      "other secret",
+    );
+
+    for token in [
+      backdated,
+      token_issued_ago(&other_key, Duration::from_secs(5)),
+      "garbage".to_string(),
+    ] {
+      assert!(matches!(
+        guard.check(&proof(token), None, "example.com"),
+        Err(SignupRejection::InvalidFormToken)
+      ));
+    }
+    assert!(matches!(
+      guard.check(&SignupProof::default(), None, "example.com"),
+      Err(SignupRejection::InvalidFormToken)
+    ));
+  }
+
+  #[test]
+  fn the_proof_of_work_must_solve_the_challenge_of_the_token() {
+    let guard = guard(8);
+    let token = token_issued_ago(&guard, Duration::from_secs(5));
+    let answer = (0..)
+      .map(|n: u32| n.to_string())
+      .find(|answer| is_proof_of_work(&token, answer, 8))
+      .unwrap();
+
+    let mut submitted = proof(token_issued_ago(&guard, Duration::from_secs(5)));
+    submitted.proof_of_work = Some(answer.clone());
+    assert!(matches!(
+      guard.check(&submitted, None, "example.com"),
+      Err(SignupRejection::InvalidProofOfWork)
+    ));
+    submitted.form_token = Some(token);
+    assert_ok!(guard.check(&submitted, None, "example.com"));
+  }
+
+  #[test]
+  fn unlimited_domains_are_not_rate_limited() {
+    let guard = guard(0);
+    let check = |domain: &str| {
+      guard.check(
+        &proof(token_issued_ago(&guard, Duration::from_secs(5))),
+        None,
+        domain,
+      )
+    };
+
+    for _ in 0..10 {
+      assert_ok!(check("example.com"));
+      assert_ok!(check("gmail.com"));
+    }
+    assert!(matches!(
+      check("example.com"),
+      Err(SignupRejection::TooManySignups(_))
+    ));
+    assert_ok!(check("gmail.com"));
+  }
+
+  #[test]
+  fn leading_zero_bits_are_counted_across_bytes() {
+    assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x1f, 0xff]), 19);
+    assert_eq!(leading_zero_bits(&[0x80, 0x00]), 0);
+    assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+  }
+
+  #[test]
+  fn rate_limits_free_up_as_events_leave_the_window() {
+    let limit = RateLimit::new(2);
+    let start = Instant::now();
+    limit.record("key", start);
+    limit.record("key", start + Duration::from_secs(60));
+    assert_none!(limit.wait(&"other key", start));
+
+    let wait = limit.wait(&"key", start + Duration::from_secs(120));
+    assert_eq!(wait, Some(Duration::from_secs(3480)));
+    assert_some!(limit.wait(&"key", start + Duration::from_secs(3599)));
+    assert_none!(limit.wait(&"key", start + Duration::from_secs(3600)));
+  }
+}
@@ -1,6 +1,7 @@
 use actix_web::dev::Server;
 use actix_web::{
-  web::{get, post, Data},
+  http::Method,
+  web::{delete, get, method, post, put, resource, Data, JsonConfig},
   App, HttpServer,
 };
 
@@ -8,9 +9,17 @@ use sqlx::PgPool;
 use std::net::{SocketAddr, TcpListener};
 use tracing_actix_web::TracingLogger;
 
+use crate::authentication::{LoginThrottle, PasswordHashing, TrustedProxies};
 use crate::configuration::Settings;
+use crate::cors::{allow_origins, CorsPolicy};
 use crate::email_client::EmailClient;
+use crate::email_templates::EmailTemplates;
+use crate::page_templates::PageTemplates;
+use crate::problem::json_error_handler;
 use crate::routes::{self, publish_newsletter};
+use crate::sanitization::HtmlSanitizer;
+use crate::signup_protection::SignupGuard;
+use crate::tracking::TrackingKey;
 
 #[derive(Debug, Clone)]
 pub struct ApplicationBaseUrl(String);
@@ -25,7 +34,16 @@ pub struct ServerBuilder {
   listener: TcpListener,
   db_pool: PgPool,
   email_client: EmailClient,
+  email_templates: EmailTemplates,
+  page_templates: PageTemplates,
+  sanitizer: HtmlSanitizer,
+  cors_policy: CorsPolicy,
   base_url: ApplicationBaseUrl,
+  tracking_key: TrackingKey,
+  login_throttle: LoginThrottle,
+  trusted_proxies: TrustedProxies,
+  signup_guard: SignupGuard,
+  password_hashing: PasswordHashing,
 }
 
 impl ServerBuilder {
@@ -35,6 +53,26 @@ impl ServerBuilder {
     let email_client = EmailClient::try_from(configuration.email_client)
       .expect("failed to parse EmailClientSettings");
 
+    let email_templates = EmailTemplates::new(
+      configuration
+        .application
+        .templates_directory
+        .as_deref()
+        .map(std::path::Path::new),
+      &configuration.application.base_url,
+    )
+    .expect("failed to load the email templates");
+    let page_templates = PageTemplates::new(
+      configuration
+        .application
+        .templates_directory
+        .as_deref()
+        .map(std::path::Path::new),
+    )
+    .expect("failed to load the page templates");
+    let sanitizer = HtmlSanitizer::new(&configuration.sanitization);
+    let cors_policy = CorsPolicy::new(&configuration.application.allowed_origins);
+
     let address = format!(
       "{}:{}",
       configuration.application.host, configuration.application.port
@@ -42,11 +80,30 @@ impl ServerBuilder {
     let listener = TcpListener::bind(address)?;
 
     let base_url = ApplicationBaseUrl(configuration.application.base_url);
+    let tracking_key = TrackingKey::new(&configuration.application.hmac_secret);
+    let login_throttle = LoginThrottle::new(&configuration.authentication.login_throttle);
+    let trusted_proxies = TrustedProxies::parse(&configuration.application.trusted_proxies)
+      .expect("failed to parse the trusted proxies");
+    let signup_guard = SignupGuard::new(
+      &configuration.signup_protection,
+      &configuration.application.hmac_secret,
+    );
+    let password_hashing = PasswordHashing::new(&configuration.authentication.password_hashing)
+      .expect("failed to parse PasswordHashingSettings");
     Ok(Self {
       listener,
       db_pool,
       email_client,
+      email_templates,
+      page_templates,
+      sanitizer,
+      cors_policy,
       base_url,
+      tracking_key,
+      login_throttle,
+      trusted_proxies,
+      signup_guard,
+      password_hashing,
     })
   }
 
@@ -55,23 +112,133 @@ impl ServerBuilder {
       listener,
       db_pool,
       email_client,
+      email_templates,
+      page_templates,
+      sanitizer,
+      cors_policy,
       base_url,
+      tracking_key,
+      login_throttle,
+      trusted_proxies,
+      signup_guard,
+      password_hashing,
     } = self;
     let db_pool = Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let email_templates = Data::new(email_templates);
+    let page_templates = Data::new(page_templates);
+    let sanitizer = Data::new(sanitizer);
+    let cors_policy = Data::new(cors_policy);
     let base_url = Data::new(base_url);
+    let tracking_key = Data::new(tracking_key);
+    let login_throttle = Data::new(login_throttle);
+    let trusted_proxies = Data::new(trusted_proxies);
+    let signup_guard = Data::new(signup_guard);
+    let password_hashing = Data::new(password_hashing);
 
     Ok(
       HttpServer::new(move || {
         App::new()
           .wrap(TracingLogger::default())
           .route("/health_check", get().to(routes::health))
+          .route("/admin/audit_log", get().to(routes::admin::read_audit_log))
+          .route(
+            "/admin/newsletters",
+            get().to(routes::admin::get_newsletters),
+          )
+          .route(
+            "/admin/newsletters",
+            post().to(routes::admin::create_newsletter),
+          )
+          .route("/admin/growth", get().to(routes::admin::get_growth))
+          .route("/admin/issues", get().to(routes::admin::get_issues))
+          .route(
+            "/admin/issues/search",
+            get().to(routes::admin::search_issues),
+          )
+          .route(
+            "/admin/issues/{issue_id}/report",
+            get().to(routes::admin::get_issue_report),
+          )
+          .route(
+            "/admin/issues/{issue_id}/visibility",
+            put().to(routes::admin::update_issue_visibility),
+          )
+          .route(
+            "/admin/newsletters/{slug}/tracking",
+            put().to(routes::admin::update_newsletter_tracking),
+          )
+          .route(
+            "/admin/subscribers/{subscriber_id}/attributes",
+            put().to(routes::admin::update_subscriber_attributes),
+          )
+          .route(
+            "/admin/subscribers/{subscriber_id}/tags/{tag}",
+            put().to(routes::admin::tag_subscriber),
+          )
+          .route(
+            "/admin/subscribers/{subscriber_id}/tags/{tag}",
+            delete().to(routes::admin::untag_subscriber),
+          )
+          .route("/admin/totp/enroll", post().to(routes::admin::enroll_totp))
+          .route(
+            "/admin/totp/confirm",
+            post().to(routes::admin::confirm_totp),
+          )
+          .route("/archive", get().to(routes::archive))
+          .route("/archive/{slug}", get().to(routes::archived_issue))
+          .route("/feeds/{list}/{file_name}", get().to(routes::feed))
           .route("/newsletters", post().to(publish_newsletter))
-          .route("/subscriptions", post().to(routes::subscribe))
+          .route("/newsletters/lint", post().to(routes::lint_newsletter))
+          .service(
+            resource("/subscriptions")
+              .wrap_fn(allow_origins)
+              .route(get().to(routes::subscribe_form))
+              .route(post().to(routes::subscribe))
+              .route(method(Method::OPTIONS).to(routes::subscriptions_preflight)),
+          )
           .route("/subscriptions/confirm", get().to(routes::confirm))
+          .route("/subscriptions/preferences", get().to(routes::preferences))
+          .route(
+            "/subscriptions/preferences",
+            post().to(routes::update_preferences),
+          )
+          .route(
+            "/subscriptions/preferences/pause",
+            post().to(routes::pause_subscription),
+          )
+          .route(
+            "/subscriptions/preferences/email",
+            post().to(routes::request_email_change),
+          )
+          .route(
+            "/subscriptions/email/confirm",
+            get().to(routes::confirm_email_change),
+          )
+          .route(
+            "/subscriptions/preferences/tracking",
+            post().to(routes::update_tracking),
+          )
+          .route("/subscriptions/unsubscribe", post().to(routes::unsubscribe))
+          .route(
+            "/subscriptions/widget.js",
+            get().to(routes::subscribe_widget),
+          )
+          .route("/tracking/open", get().to(routes::track_open))
+          .route("/tracking/click", get().to(routes::track_click))
+          .app_data(JsonConfig::default().error_handler(json_error_handler))
           .app_data(db_pool.clone())
           .app_data(email_client.clone())
+          .app_data(email_templates.clone())
+          .app_data(page_templates.clone())
+          .app_data(sanitizer.clone())
+          .app_data(cors_policy.clone())
           .app_data(base_url.clone())
+          .app_data(tracking_key.clone())
+          .app_data(login_throttle.clone())
+          .app_data(trusted_proxies.clone())
+          .app_data(signup_guard.clone())
+          .app_data(password_hashing.clone())
       })
       .listen(listener)?
       .run(),
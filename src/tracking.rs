@@ -0,0 +1,177 @@
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::Url;
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Signs the tracking links put in issues, so that nobody can record an open or a click on
+/// behalf of someone else, guess the links of other subscribers, or have us redirect anywhere.
+/// Also signs the archive links of issues, so that their recipients can read private ones.
+#[derive(Clone)]
+pub struct TrackingKey(Vec<u8>);
+
+impl std::fmt::Debug for TrackingKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("TrackingKey(..)")
+  }
+}
+
+/// A transparent 1x1 GIF.
+pub const TRACKING_PIXEL: [u8; 43] = [
+  0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+  0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+  0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+impl TrackingKey {
+  pub fn new(secret: &str) -> Self {
+    Self(secret.as_bytes().to_vec())
+  }
+
+  /// The URL of the pixel which records that the subscriber opened the issue.
+  pub fn open_pixel_url(&self, base_url: &str, issue_id: Uuid, subscriber_id: Uuid) -> String {
+    format!(
+      "{}/tracking/open?issue={}&subscriber={}&signature={}",
+      base_url,
+      issue_id,
+      subscriber_id,
+      self.sign(&open_message(issue_id, subscriber_id)),
+    )
+  }
+
+  pub fn verify_open(&self, issue_id: Uuid, subscriber_id: Uuid, signature: &str) -> bool {
+    self.verify(&open_message(issue_id, subscriber_id), signature)
+  }
+
+  /// The URL which records that the subscriber followed the link, then redirects to it.
+  pub fn click_url(
+    &self,
+    base_url: &str,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: &str,
+  ) -> String {
+    let mut click_url = Url::parse(&format!("{}/tracking/click", base_url))
+      .expect("The base URL was validated when the email templates were loaded");
+    click_url
+      .query_pairs_mut()
+      .append_pair("issue", &issue_id.to_string())
+      .append_pair("subscriber", &subscriber_id.to_string())
+      .append_pair("url", url)
+      .append_pair(
+        "signature",
+        &self.sign(&click_message(issue_id, subscriber_id, url)),
+      );
+    click_url.into()
+  }
+
+  pub fn verify_click(
+    &self,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: &str,
+    signature: &str,
+  ) -> bool {
+    self.verify(&click_message(issue_id, subscriber_id, url), signature)
+  }
+
+  /// The URL of the issue on the web archive, which works even if the issue is private.
+  pub fn archive_url(&self, base_url: &str, issue_id: Uuid, slug: &str) -> String {
+    format!(
+      "{}/archive/{}?key={}",
+      base_url,
+      slug,
+      self.sign(&archive_message(issue_id)),
+    )
+  }
+
+  pub fn verify_archive(&self, issue_id: Uuid, key: &str) -> bool {
+    self.verify(&archive_message(issue_id), key)
+  }
+
+  fn mac(&self, message: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac
+  }
+
+  fn sign(&self, message: &str) -> String {
+    base64::encode_config(
+      self.mac(message).finalize().into_bytes(),
+      base64::URL_SAFE_NO_PAD,
+    )
+  }
+
+  /// Compares in constant time, so that a signature can't be found byte by byte.
+  fn verify(&self, message: &str, signature: &str) -> bool {
+    match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+      Ok(signature) => self.mac(message).verify(&signature).is_ok(),
+      Err(_) => false,
+    }
+  }
+}
+
+/// What is signed for an open, prefixed so that it can't pass for the signature of another link.
+fn open_message(issue_id: Uuid, subscriber_id: Uuid) -> String {
+  format!("open:{}:{}", issue_id, subscriber_id)
+}
+
+fn click_message(issue_id: Uuid, subscriber_id: Uuid, url: &str) -> String {
+  format!("click:{}:{}:{}", issue_id, subscriber_id, url)
+}
+
+fn archive_message(issue_id: Uuid) -> String {
+  format!("archive:{}", issue_id)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::TrackingKey;
+  use reqwest::Url;
+  use std::collections::HashMap;
+  use uuid::Uuid;
+
+  #[test]
+  fn signed_opens_are_verified() {
+    let key = TrackingKey::new("secret");
+    let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+    let url = key.open_pixel_url("https://news.example.com", issue, subscriber);
+    let signature = url.rsplit("signature=").next().unwrap();
+
+    assert!(key.verify_open(issue, subscriber, signature));
+    assert!(!key.verify_open(issue, Uuid::new_v4(), signature));
+    assert!(!key.verify_open(issue, subscriber, "forged"));
+    assert!(!TrackingKey::new("other secret").verify_open(issue, subscriber, signature));
+  }
+
+  #[test]
+  fn signed_clicks_are_verified() {
+    let key = TrackingKey::new("secret");
+    let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+    let url = "https://example.com/post?a=1&b=2";
+    let click_url =
+      Url::parse(&key.click_url("https://news.example.com", issue, subscriber, url)).unwrap();
+    let query: HashMap<_, _> = click_url.query_pairs().into_owned().collect();
+    assert_eq!(query["url"], url);
+    let signature = &query["signature"];
+
+    assert!(key.verify_click(issue, subscriber, url, signature));
+    assert!(!key.verify_click(issue, subscriber, "https://evil.example.com", signature));
+    // The signature of an open doesn't pass for that of a click, nor the other way around.
+    let open_url = key.open_pixel_url("https://news.example.com", issue, subscriber);
+    let open_signature = open_url.rsplit("signature=").next().unwrap();
+    assert!(!key.verify_click(issue, subscriber, url, open_signature));
+    assert!(!key.verify_open(issue, subscriber, signature));
+  }
+
+  #[test]
+  fn signed_archive_links_are_verified() {
+    let key = TrackingKey::new("secret");
+    let issue = Uuid::new_v4();
+    let url = key.archive_url("https://news.example.com", issue, "first-issue");
+    assert!(url.starts_with("https://news.example.com/archive/first-issue?key="));
+    let signature = url.rsplit("key=").next().unwrap();
+
+    assert!(key.verify_archive(issue, signature));
+    assert!(!key.verify_archive(Uuid::new_v4(), signature));
+  }
+}
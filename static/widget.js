@@ -0,0 +1,133 @@
+// Subscribe widget, embedded on other sites with
+//   <script src="https://<our base URL>/subscriptions/widget.js" data-list="<newsletter slug>"></script>
+// It inserts a subscribe form after the script tag, which posts to /subscriptions as JSON
+// and shows the errors of each field from the problem it gets back.
+// Like the subscribe page, it is submitted with a form token, and the answer to its proof of
+// work challenge if there is one, which it fetches from /subscriptions when it is shown.
+(function () {
+  "use strict";
+
+  var script = document.currentScript;
+  var endpoint = new URL("/subscriptions", script.src).href;
+  var list = script.getAttribute("data-list");
+
+  var form = document.createElement("form");
+  form.className = "newsletter-widget";
+  form.noValidate = true;
+  form.innerHTML =
+    '<label>Name <input type="text" name="name" required></label>' +
+    '<ul class="newsletter-widget-errors" data-field="name"></ul>' +
+    '<label>Email <input type="email" name="email" required></label>' +
+    '<ul class="newsletter-widget-errors" data-field="email"></ul>' +
+    '<div hidden aria-hidden="true"><label>Leave this field empty <input type="text" name="website" tabindex="-1" autocomplete="off"></label></div>' +
+    '<ul class="newsletter-widget-errors" data-field=""></ul>' +
+    '<button type="submit">Subscribe</button>';
+  script.parentNode.insertBefore(form, script.nextSibling);
+  var button = form.querySelector("button");
+  var challenge = null;
+
+  function fetchChallenge() {
+    challenge = fetch(endpoint, { headers: { "Accept": "application/json" } }).then(function (response) {
+      return response.json();
+    });
+  }
+
+  function leadingZeroBits(bytes) {
+    var bits = 0;
+    for (var i = 0; i < bytes.length; i++) {
+      if (bytes[i] !== 0) {
+        return bits + Math.clz32(bytes[i]) - 24;
+      }
+      bits += 8;
+    }
+    return bits;
+  }
+
+  // Finds an answer whose SHA-256 hash with the form token starts with enough zero bits.
+  function solve(token, difficulty, answer) {
+    if (difficulty === 0) {
+      return Promise.resolve("");
+    }
+    return crypto.subtle.digest("SHA-256", new TextEncoder().encode(token + ":" + answer)).then(function (hash) {
+      return leadingZeroBits(new Uint8Array(hash)) >= difficulty ? String(answer) : solve(token, difficulty, answer + 1);
+    });
+  }
+
+  function errorList(field) {
+    return form.querySelector('.newsletter-widget-errors[data-field="' + field + '"]') ||
+      form.querySelector('.newsletter-widget-errors[data-field=""]');
+  }
+
+  function showError(field, message) {
+    var item = document.createElement("li");
+    item.textContent = message;
+    errorList(field).appendChild(item);
+  }
+
+  function clearErrors() {
+    var lists = form.querySelectorAll(".newsletter-widget-errors");
+    for (var i = 0; i < lists.length; i++) {
+      lists[i].textContent = "";
+    }
+  }
+
+  fetchChallenge();
+
+  form.addEventListener("submit", function (event) {
+    event.preventDefault();
+    clearErrors();
+    var subscription = {
+      name: form.elements.name.value,
+      email: form.elements.email.value,
+      website: form.elements.website.value
+    };
+    if (list) {
+      subscription.list = list;
+    }
+    button.disabled = true;
+
+    challenge
+      .then(function (challenge) {
+        subscription.form_token = challenge.form_token;
+        return solve(challenge.form_token, challenge.proof_of_work_difficulty, 0);
+      })
+      .then(function (answer) {
+        subscription.proof_of_work = answer;
+        return fetch(endpoint, {
+          method: "POST",
+          headers: { "Content-Type": "application/json", "Accept": "application/json" },
+          body: JSON.stringify(subscription)
+        });
+      })
+      .then(function (response) {
+        return response.json().then(function (body) {
+          if (response.ok) {
+            var done = document.createElement("p");
+            done.textContent = "Check your inbox: we sent a link to " + body.email + " to confirm your subscription.";
+            form.replaceChildren(done);
+            return;
+          }
+          if (body.code === "expired_form_token" || body.code === "invalid_form_token") {
+            fetchChallenge();
+          }
+          var errors = body.errors || {};
+          var fields = Object.keys(errors);
+          if (fields.length === 0) {
+            showError("", body.detail || body.title);
+          }
+          fields.forEach(function (field) {
+            errors[field].forEach(function (message) {
+              showError(field, message);
+            });
+          });
+        });
+      })
+      .catch(function () {
+        fetchChallenge();
+        showError("", "Your subscription could not be sent. Please try again.");
+      })
+      .then(function () {
+        button.disabled = false;
+      });
+  });
+})();
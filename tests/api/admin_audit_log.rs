@@ -0,0 +1,182 @@
+use uuid::Uuid;
+
+use crate::helpers::{problem_body, spawn_app};
+
+fn newsletter_body() -> serde_json::Value {
+  serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  })
+}
+
+#[actix_rt::test]
+async fn publishing_a_newsletter_is_recorded_in_the_audit_log() {
+  let app = spawn_app().await;
+
+  app
+    .post_newsletters(newsletter_body())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let saved = sqlx::query!(
+    "SELECT actor_user_id, target, request_id, client_ip FROM audit_log WHERE action = 'publish_newsletter'",
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .expect("failed to fetch the audit log entry");
+
+  assert_eq!(saved.actor_user_id, Some(app.test_user.user_id));
+  assert_eq!(saved.target.as_deref(), Some("Newsletter title"));
+  assert!(saved.request_id.is_some());
+  assert_eq!(saved.client_ip.as_deref(), Some("127.0.0.1"));
+}
+
+#[actix_rt::test]
+async fn failed_logins_are_recorded_in_the_audit_log() {
+  let app = spawn_app().await;
+
+  reqwest::Client::new()
+    .post(format!("{}/newsletters", &app.address))
+    .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+    .json(&newsletter_body())
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+  let saved = sqlx::query!("SELECT actor_user_id, action, target FROM audit_log",)
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to fetch the audit log entry");
+
+  assert_eq!(saved.action, "login_failed");
+  assert_eq!(saved.actor_user_id, None);
+  assert_eq!(
+    saved.target.as_deref(),
+    Some(app.test_user.username.as_str())
+  );
+}
+
+#[actix_rt::test]
+async fn audit_log_entries_cannot_be_modified() {
+  let app = spawn_app().await;
+  app
+    .post_newsletters(newsletter_body())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let update = sqlx::query!("UPDATE audit_log SET target = 'something else'",)
+    .execute(&app.db_pool)
+    .await;
+  let delete = sqlx::query!("DELETE FROM audit_log",)
+    .execute(&app.db_pool)
+    .await;
+
+  assert!(update.is_err());
+  assert!(delete.is_err());
+}
+
+#[actix_rt::test]
+async fn audit_log_can_be_filtered_by_action_and_actor() {
+  let app = spawn_app().await;
+  app
+    .post_newsletters(newsletter_body())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let resp = app
+    .get_audit_log(&format!(
+      "action=publish_newsletter&actor={}",
+      app.test_user.user_id
+    ))
+    .await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let body: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(body["total"], 1);
+  assert_eq!(body["entries"][0]["action"], "publish_newsletter");
+  assert_eq!(body["entries"][0]["target"], "Newsletter title");
+
+  let resp = app
+    .get_audit_log(&format!("actor={}", Uuid::new_v4()))
+    .await;
+  let body: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(body["total"], 0);
+}
+
+#[actix_rt::test]
+async fn audit_log_can_be_filtered_by_date_range() {
+  let app = spawn_app().await;
+  app
+    .post_newsletters(newsletter_body())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let resp = app
+    .get_audit_log("action=publish_newsletter&from=2000-01-01T00:00:00Z&to=2001-01-01T00:00:00Z")
+    .await;
+  let body: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(body["total"], 0);
+
+  let resp = app
+    .get_audit_log("action=publish_newsletter&from=2000-01-01T00:00:00Z")
+    .await;
+  let body: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(body["total"], 1);
+}
+
+#[actix_rt::test]
+async fn audit_log_is_paginated() {
+  let app = spawn_app().await;
+  for _ in 0..3 {
+    app
+      .post_newsletters(newsletter_body())
+      .await
+      .error_for_status()
+      .unwrap();
+  }
+
+  let resp = app
+    .get_audit_log("action=publish_newsletter&per_page=2&page=2")
+    .await;
+  let body: serde_json::Value = resp.json().await.unwrap();
+
+  assert_eq!(body["total"], 3);
+  assert_eq!(body["page"], 2);
+  assert_eq!(body["entries"].as_array().unwrap().len(), 1);
+}
+
+#[actix_rt::test]
+async fn audit_log_rejects_invalid_pagination() {
+  let app = spawn_app().await;
+
+  for query in ["page=0", "per_page=0", "per_page=1000"] {
+    let resp = app.get_audit_log(query).await;
+    assert_eq!(
+      resp.status(),
+      reqwest::StatusCode::BAD_REQUEST,
+      "expected a 400 for {}",
+      query
+    );
+    assert_eq!(problem_body(resp).await["code"], "invalid_request");
+  }
+}
+
+#[actix_rt::test]
+async fn audit_log_requires_authentication() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::Client::new()
+    .get(format!("{}/admin/audit_log", &app.address))
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,185 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::Utc;
+use newsletter::authentication::{time_step, TotpSecret};
+
+use crate::helpers::{problem_body, spawn_app};
+
+fn newsletter_body() -> serde_json::Value {
+  serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  })
+}
+
+#[actix_rt::test]
+async fn enrollment_returns_a_provisioning_uri_for_the_secret() {
+  let app = spawn_app().await;
+
+  let resp = app.post_totp_enroll().await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let body: serde_json::Value = resp.json().await.unwrap();
+  let secret = body["secret"].as_str().unwrap();
+  let uri = body["provisioning_uri"].as_str().unwrap();
+
+  assert!(TotpSecret::parse(secret).is_ok());
+  assert!(uri.starts_with("otpauth://totp/"));
+  assert!(uri.contains(&format!("secret={}", secret)));
+}
+
+#[actix_rt::test]
+async fn enrollment_requires_authentication() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::Client::new()
+    .post(format!("{}/admin/totp/enroll", &app.address))
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+  assert_eq!(r#"Basic realm="admin""#, resp.headers()["WWW-Authenticate"]);
+}
+
+#[actix_rt::test]
+async fn confirming_with_a_valid_code_returns_recovery_codes() {
+  let app = spawn_app().await;
+
+  let (_, recovery_codes) = app.enroll_test_user_in_totp().await;
+
+  assert_eq!(recovery_codes.len(), 10);
+}
+
+#[actix_rt::test]
+async fn confirming_with_an_invalid_code_is_rejected() {
+  let app = spawn_app().await;
+  app.post_totp_enroll().await.error_for_status().unwrap();
+
+  let resp = app.post_totp_confirm("not-a-code").await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  assert_eq!(problem_body(resp).await["code"], "invalid_request");
+}
+
+#[actix_rt::test]
+async fn confirming_without_a_pending_enrollment_is_rejected() {
+  let app = spawn_app().await;
+
+  let resp = app.post_totp_confirm("123456").await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  assert_eq!(problem_body(resp).await["code"], "invalid_request");
+}
+
+#[actix_rt::test]
+async fn unconfirmed_enrollments_do_not_require_a_code() {
+  let app = spawn_app().await;
+  app.post_totp_enroll().await.error_for_status().unwrap();
+
+  let resp = app.post_newsletters(newsletter_body()).await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn enrolled_users_must_provide_a_valid_code() {
+  let app = spawn_app().await;
+  let (secret, _) = app.enroll_test_user_in_totp().await;
+
+  let resp = app.post_newsletters(newsletter_body()).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+  let resp = app
+    .post_newsletters_with_code(newsletter_body(), "000000")
+    .await;
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+  // The current step's code was used to confirm the enrollment, so we use the next one.
+  let code = secret.code_at(time_step(Utc::now()) + 1);
+  let resp = app
+    .post_newsletters_with_code(newsletter_body(), &code)
+    .await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn one_time_codes_cannot_be_replayed() {
+  let app = spawn_app().await;
+  let (secret, _) = app.enroll_test_user_in_totp().await;
+  let code = secret.code_at(time_step(Utc::now()) + 1);
+
+  let first = app
+    .post_newsletters_with_code(newsletter_body(), &code)
+    .await;
+  let second = app
+    .post_newsletters_with_code(newsletter_body(), &code)
+    .await;
+
+  assert_eq!(first.status(), reqwest::StatusCode::OK);
+  assert_eq!(second.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn recovery_codes_can_only_be_used_once() {
+  let app = spawn_app().await;
+  let (_, recovery_codes) = app.enroll_test_user_in_totp().await;
+
+  let first = app
+    .post_newsletters_with_code(newsletter_body(), &recovery_codes[0])
+    .await;
+  let second = app
+    .post_newsletters_with_code(newsletter_body(), &recovery_codes[0])
+    .await;
+
+  assert_eq!(first.status(), reqwest::StatusCode::OK);
+  assert_eq!(second.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn outdated_password_hashes_are_only_upgraded_once_the_code_is_verified() {
+  let app = spawn_app().await;
+  let (secret, _) = app.enroll_test_user_in_totp().await;
+  let salt = SaltString::generate(&mut rand::thread_rng());
+  let weak_hash = Argon2::new(
+    Algorithm::Argon2id,
+    Version::V0x13,
+    Params::new(4096, 1, 1, None).unwrap(),
+  )
+  .hash_password(app.test_user.password.as_bytes(), &salt)
+  .unwrap()
+  .to_string();
+  sqlx::query!(
+    "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+    weak_hash,
+    app.test_user.user_id,
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+  let stored_hash = || async {
+    sqlx::query!(
+      "SELECT password_hash FROM users WHERE user_id = $1",
+      app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+  };
+
+  let resp = app
+    .post_newsletters_with_code(newsletter_body(), "000000")
+    .await;
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+  assert_eq!(stored_hash().await, weak_hash);
+
+  let code = secret.code_at(time_step(Utc::now()) + 1);
+  let resp = app
+    .post_newsletters_with_code(newsletter_body(), &code)
+    .await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  assert_ne!(stored_hash().await, weak_hash);
+}
@@ -0,0 +1,246 @@
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{problem_body, spawn_app, TestApp};
+
+/// Subscribes and confirms, returning the subscriber's preferences token.
+async fn create_confirmed_subscriber(app: &TestApp) -> String {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .named("Create subscriber")
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  sqlx::query!("SELECT preferences_token FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .preferences_token
+}
+
+/// Publishes an issue with a link, returning its id and the html sent for it.
+async fn publish_issue(app: &TestApp) -> (String, String) {
+  let _mock_guard = Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  let report: serde_json::Value = app
+    .post_newsletters(serde_json::json!({
+      "title": "Newsletter title",
+      "content": { "html": r#"<p>Read <a href="https://example.com/post">this</a></p>"# },
+    }))
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  (
+    report["issue_id"].as_str().unwrap().to_string(),
+    body["HtmlBody"].as_str().unwrap().to_string(),
+  )
+}
+
+/// Follows every tracking link of the html which starts with the prefix.
+async fn follow(app: &TestApp, html: &str, prefix: &str) {
+  let prefix = format!("http://127.0.0.1{}", prefix);
+  let client = reqwest::Client::builder()
+    .redirect(reqwest::redirect::Policy::none())
+    .build()
+    .unwrap();
+  for (start, _) in html.match_indices(&prefix) {
+    let end = start + html[start..].find('"').unwrap();
+    let mut url = reqwest::Url::parse(&html[start..end].replace("&amp;", "&")).unwrap();
+    url.set_port(Some(app.port)).unwrap();
+    client.get(url).send().await.unwrap();
+  }
+}
+
+async fn get_admin(app: &TestApp, endpoint: &str) -> reqwest::Response {
+  reqwest::Client::new()
+    .get(format!("{}{}", &app.address, endpoint))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .unwrap()
+}
+
+#[actix_rt::test]
+async fn issues_report_their_open_and_click_rates() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let (issue_id, html) = publish_issue(&app).await;
+  follow(&app, &html, "/tracking/open?").await;
+  follow(&app, &html, "/tracking/open?").await;
+  follow(&app, &html, "/tracking/click?").await;
+
+  let resp = get_admin(&app, "/admin/issues").await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let issues: Vec<serde_json::Value> = resp.json().await.unwrap();
+  assert_eq!(issues.len(), 1);
+  let issue = &issues[0];
+  assert_eq!(issue["id"], issue_id.as_str());
+  assert_eq!(issue["list"], "default");
+  assert_eq!(issue["delivered"], 1);
+  assert_eq!(issue["unique_opens"], 1);
+  assert_eq!(issue["total_opens"], 2);
+  assert_eq!(issue["open_rate"], 1.0);
+  assert_eq!(issue["unique_clicks"], 1);
+  assert_eq!(issue["click_through_rate"], 1.0);
+  assert_eq!(issue["unsubscribes"], 0);
+
+  let issues: Vec<serde_json::Value> = get_admin(&app, "/admin/issues?list=other")
+    .await
+    .json()
+    .await
+    .unwrap();
+  assert!(issues.is_empty());
+}
+
+#[actix_rt::test]
+async fn unsubscribes_are_attributed_to_the_last_issue_received() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  let (first_issue, _) = publish_issue(&app).await;
+  let (last_issue, _) = publish_issue(&app).await;
+  reqwest::Client::new()
+    .post(format!("{}/subscriptions/unsubscribe", &app.address))
+    .form(&[("token", token.as_str())])
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  for (issue_id, unsubscribes) in [(first_issue, 0), (last_issue, 1)] {
+    let report: serde_json::Value = get_admin(&app, &format!("/admin/issues/{}/report", issue_id))
+      .await
+      .json()
+      .await
+      .unwrap();
+    assert_eq!(report["unsubscribes"], unsubscribes);
+  }
+}
+
+#[actix_rt::test]
+async fn growth_is_reported_for_every_day() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  let resp = get_admin(&app, "/admin/growth").await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let days: Vec<serde_json::Value> = resp.json().await.unwrap();
+  assert_eq!(days.len(), 30);
+  let today = &days[29];
+  assert_eq!(today["date"], chrono::Utc::today().naive_utc().to_string());
+  assert_eq!(today["subscribes"], 1);
+  assert_eq!(today["confirmations"], 1);
+  assert_eq!(today["unsubscribes"], 0);
+  assert_eq!(today["net"], 1);
+  assert!(days[..29].iter().all(|day| day["subscribes"] == 0));
+}
+
+#[actix_rt::test]
+async fn invalid_growth_periods_are_rejected() {
+  let app = spawn_app().await;
+
+  for (query, detail) in [
+    ("from=2022-02-01&to=2022-01-01", "from cannot be after to"),
+    (
+      "from=2020-01-01&to=2022-01-01",
+      "Growth can be reported over at most 366 days.",
+    ),
+  ] {
+    let resp = get_admin(&app, &format!("/admin/growth?{}", query)).await;
+    assert_eq!(resp.status().as_u16(), 400, "{}", query);
+    let problem = problem_body(resp).await;
+    assert_eq!(problem["code"], "invalid_request");
+    assert_eq!(problem["detail"], detail);
+  }
+
+  let resp = get_admin(&app, "/admin/growth?from=yesterday").await;
+  assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[actix_rt::test]
+async fn reports_can_be_exported_as_csv() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let (issue_id, html) = publish_issue(&app).await;
+  follow(&app, &html, "/tracking/click?").await;
+
+  let resp = get_admin(
+    &app,
+    &format!("/admin/issues/{}/report?format=csv", issue_id),
+  )
+  .await;
+  assert_eq!(resp.status().as_u16(), 200);
+  assert_eq!(resp.headers()["Content-Type"], "text/csv; charset=utf-8");
+  assert_eq!(
+    resp.headers()["Content-Disposition"],
+    format!("attachment; filename=\"issue-{}.csv\"", issue_id).as_str()
+  );
+  let csv = resp.text().await.unwrap();
+  let mut lines = csv.lines();
+  assert_eq!(
+    lines.next().unwrap(),
+    "id,list,title,published_at,delivered,unique_opens,total_opens,open_rate,\
+     unique_clicks,total_clicks,click_through_rate,unsubscribes,\
+     url,link_unique_clicks,link_clicks"
+  );
+  let row = lines.next().unwrap();
+  assert!(row.starts_with(&format!("{},default,Newsletter title,", issue_id)));
+  assert!(row.ends_with(",1,1,1.0,0,https://example.com/post,1,1"));
+  assert_eq!(lines.next(), None);
+
+  let csv = get_admin(&app, "/admin/issues?format=csv")
+    .await
+    .text()
+    .await
+    .unwrap();
+  assert!(csv.starts_with("id,list,title,published_at,delivered,unique_opens,"));
+  assert!(csv.contains(&issue_id));
+}
+
+#[actix_rt::test]
+async fn reports_require_authentication() {
+  let app = spawn_app().await;
+
+  for endpoint in ["/admin/issues", "/admin/growth"] {
+    let resp = reqwest::get(format!("{}{}", &app.address, endpoint))
+      .await
+      .unwrap();
+    assert_eq!(resp.status().as_u16(), 401, "{}", endpoint);
+  }
+}
@@ -0,0 +1,229 @@
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .named("Create subscriber")
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+}
+
+/// Publishes an issue, returning the publish report.
+async fn publish_issue(app: &TestApp, body: serde_json::Value) -> serde_json::Value {
+  let _mock_guard = Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_newsletters(body)
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+fn issue(title: &str) -> serde_json::Value {
+  serde_json::json!({
+    "title": title,
+    "content": { "html": r#"<p>Hello {{ name | default: "reader" }}, welcome to the first issue.</p>"# },
+    "preheader": "What's new this week",
+  })
+}
+
+/// The archive link of the last email sent, pointed at the test app.
+async fn archive_link(app: &TestApp) -> reqwest::Url {
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  let text = body["TextBody"].as_str().unwrap();
+  let link = text
+    .lines()
+    .find_map(|line| line.strip_prefix("Read it in your browser: "))
+    .unwrap();
+  let mut url = reqwest::Url::parse(link).unwrap();
+  url.set_port(Some(app.port)).unwrap();
+  url
+}
+
+async fn get_archive(app: &TestApp, endpoint: &str) -> reqwest::Response {
+  reqwest::get(format!("{}{}", &app.address, endpoint))
+    .await
+    .unwrap()
+}
+
+async fn put_visibility(app: &TestApp, issue_id: &str, public: bool) -> reqwest::Response {
+  reqwest::Client::new()
+    .put(format!(
+      "{}/admin/issues/{}/visibility",
+      &app.address, issue_id
+    ))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .json(&serde_json::json!({ "public": public }))
+    .send()
+    .await
+    .unwrap()
+}
+
+#[actix_rt::test]
+async fn published_issues_are_listed_on_the_archive() {
+  let app = spawn_app().await;
+  let report = publish_issue(&app, issue("Our First Issue!")).await;
+  assert_eq!(report["slug"], "our-first-issue");
+
+  let resp = get_archive(&app, "/archive").await;
+  assert_eq!(resp.status().as_u16(), 200);
+  assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+  let page = resp.text().await.unwrap();
+  assert!(page.contains(r#"<a href="/archive/our-first-issue">Our First Issue!</a>"#));
+}
+
+#[actix_rt::test]
+async fn archived_issues_are_shown_with_open_graph_tags() {
+  let app = spawn_app().await;
+  publish_issue(&app, issue("Our First Issue!")).await;
+
+  let resp = get_archive(&app, "/archive/our-first-issue").await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let page = resp.text().await.unwrap();
+  // Merge tags are filled in for nobody in particular.
+  assert!(page.contains("<p>Hello reader, welcome to the first issue.</p>"));
+  for tag in [
+    r#"<meta property="og:type" content="article">"#,
+    r#"<meta property="og:title" content="Our First Issue!">"#,
+    r#"<meta property="og:description" content="What&#39;s new this week">"#,
+    r#"<meta property="og:url" content="http://127.0.0.1/archive/our-first-issue">"#,
+    r#"<meta property="og:site_name" content="Newsletter">"#,
+    r#"<link rel="alternate" type="application/rss+xml" title="Newsletter" href="http://127.0.0.1/feeds/default/rss.xml">"#,
+  ] {
+    assert!(page.contains(tag), "{}", tag);
+  }
+  assert!(!page.contains("noindex"));
+}
+
+#[actix_rt::test]
+async fn issues_with_the_same_title_get_numbered_slugs() {
+  let app = spawn_app().await;
+
+  let mut slugs = Vec::new();
+  for _ in 0..3 {
+    slugs.push(publish_issue(&app, issue("Weekly news")).await["slug"].clone());
+  }
+  assert_eq!(slugs, ["weekly-news", "weekly-news-2", "weekly-news-3"]);
+}
+
+#[actix_rt::test]
+async fn every_email_links_to_its_archive_page() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  publish_issue(&app, issue("Our First Issue!")).await;
+
+  let link = archive_link(&app).await;
+  assert_eq!(link.path(), "/archive/our-first-issue");
+  let resp = reqwest::get(link).await.unwrap();
+  assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn private_issues_are_only_shown_to_their_recipients() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let mut body = issue("Members only");
+  body["public"] = serde_json::json!(false);
+  publish_issue(&app, body).await;
+
+  let page = get_archive(&app, "/archive").await.text().await.unwrap();
+  assert!(!page.contains("Members only"));
+  let resp = get_archive(&app, "/archive/members-only").await;
+  assert_eq!(resp.status().as_u16(), 404);
+  let resp = get_archive(&app, "/archive/members-only?key=forged").await;
+  assert_eq!(resp.status().as_u16(), 404);
+
+  let resp = reqwest::get(archive_link(&app).await).await.unwrap();
+  assert_eq!(resp.status().as_u16(), 200);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains(r#"<meta name="robots" content="noindex">"#));
+}
+
+#[actix_rt::test]
+async fn issues_can_be_made_public_or_private() {
+  let app = spawn_app().await;
+  let report = publish_issue(&app, issue("Our First Issue!")).await;
+  let issue_id = report["issue_id"].as_str().unwrap();
+
+  let resp = put_visibility(&app, issue_id, false).await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let resp = get_archive(&app, "/archive/our-first-issue").await;
+  assert_eq!(resp.status().as_u16(), 404);
+
+  put_visibility(&app, issue_id, true).await;
+  let resp = get_archive(&app, "/archive/our-first-issue").await;
+  assert_eq!(resp.status().as_u16(), 200);
+
+  let recorded = sqlx::query!(
+    "SELECT COUNT(*) AS \"count!\" FROM audit_log WHERE action = 'update_issue_visibility'"
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(recorded.count, 2);
+
+  let resp = put_visibility(&app, &uuid::Uuid::new_v4().to_string(), true).await;
+  assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[actix_rt::test]
+async fn the_archive_is_paginated() {
+  let app = spawn_app().await;
+  for n in 1..=21 {
+    publish_issue(&app, issue(&format!("Issue {}", n))).await;
+  }
+
+  let first_page = get_archive(&app, "/archive").await.text().await.unwrap();
+  assert!(first_page.contains("/archive/issue-21"));
+  assert!(!first_page.contains("/archive/issue-1\""));
+  assert!(first_page.contains(r#"<a href="/archive?page=2" rel="next">"#));
+
+  let second_page = get_archive(&app, "/archive?page=2")
+    .await
+    .text()
+    .await
+    .unwrap();
+  assert!(second_page.contains("/archive/issue-1\""));
+  assert!(!second_page.contains("rel=\"next\""));
+
+  for page in ["0", "3"] {
+    let resp = get_archive(&app, &format!("/archive?page={}", page)).await;
+    assert_eq!(resp.status().as_u16(), 404, "{}", page);
+  }
+}
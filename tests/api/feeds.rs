@@ -0,0 +1,144 @@
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Publishes an issue, returning the publish report.
+async fn publish_issue(app: &TestApp, title: &str, public: bool) -> serde_json::Value {
+  let _mock_guard = Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_newsletters(serde_json::json!({
+      "title": title,
+      "content": { "html": "<p>Newsletter body as HTML</p>" },
+      "public": public,
+    }))
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+async fn get_feed(app: &TestApp, file_name: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+  let mut request =
+    reqwest::Client::new().get(format!("{}/feeds/default/{}", &app.address, file_name));
+  for (name, value) in headers {
+    request = request.header(*name, *value);
+  }
+  request.send().await.unwrap()
+}
+
+#[actix_rt::test]
+async fn public_issues_are_published_in_every_feed_format() {
+  let app = spawn_app().await;
+  let report = publish_issue(&app, "First issue", true).await;
+  publish_issue(&app, "Private issue", false).await;
+  let guid = format!("urn:uuid:{}", report["issue_id"].as_str().unwrap());
+
+  for (file_name, content_type) in [
+    ("rss.xml", "application/rss+xml; charset=utf-8"),
+    ("atom.xml", "application/atom+xml; charset=utf-8"),
+    ("feed.json", "application/feed+json; charset=utf-8"),
+  ] {
+    let resp = get_feed(&app, file_name, &[]).await;
+    assert_eq!(resp.status().as_u16(), 200, "{}", file_name);
+    assert_eq!(resp.headers()["Content-Type"], content_type);
+    let feed = resp.text().await.unwrap();
+    assert!(feed.contains(&guid), "{}", file_name);
+    assert!(
+      feed.contains("http://127.0.0.1/archive/first-issue"),
+      "{}",
+      file_name
+    );
+    assert!(!feed.contains("Private issue"), "{}", file_name);
+  }
+}
+
+#[actix_rt::test]
+async fn unknown_feeds_are_not_found() {
+  let app = spawn_app().await;
+
+  for path in ["/feeds/default/feed.txt", "/feeds/unknown/rss.xml"] {
+    let resp = reqwest::get(format!("{}{}", &app.address, path))
+      .await
+      .unwrap();
+    assert_eq!(resp.status().as_u16(), 404, "{}", path);
+  }
+}
+
+#[actix_rt::test]
+async fn unchanged_feeds_are_not_sent_again() {
+  let app = spawn_app().await;
+  publish_issue(&app, "First issue", true).await;
+
+  let resp = get_feed(&app, "atom.xml", &[]).await;
+  let etag = resp.headers()["ETag"].to_str().unwrap().to_string();
+  let last_modified = resp.headers()["Last-Modified"]
+    .to_str()
+    .unwrap()
+    .to_string();
+  assert!(last_modified.ends_with(" GMT"));
+
+  for headers in [
+    [("If-None-Match", etag.as_str())],
+    [("If-Modified-Since", last_modified.as_str())],
+  ] {
+    let resp = get_feed(&app, "atom.xml", &headers).await;
+    assert_eq!(resp.status().as_u16(), 304, "{:?}", headers);
+    assert_eq!(resp.headers()["ETag"], etag.as_str());
+    assert!(resp.text().await.unwrap().is_empty());
+  }
+  // Each format has its own tag.
+  let resp = get_feed(&app, "rss.xml", &[("If-None-Match", etag.as_str())]).await;
+  assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn feeds_change_when_an_issue_is_published_or_made_private() {
+  let app = spawn_app().await;
+  let report = publish_issue(&app, "First issue", true).await;
+  let etag = |resp: &reqwest::Response| resp.headers()["ETag"].to_str().unwrap().to_string();
+  let first = etag(&get_feed(&app, "rss.xml", &[]).await);
+
+  publish_issue(&app, "Second issue", true).await;
+  let resp = get_feed(&app, "rss.xml", &[("If-None-Match", first.as_str())]).await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let second = etag(&resp);
+  assert_ne!(first, second);
+
+  reqwest::Client::new()
+    .put(format!(
+      "{}/admin/issues/{}/visibility",
+      &app.address,
+      report["issue_id"].as_str().unwrap()
+    ))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .json(&serde_json::json!({ "public": false }))
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  let resp = get_feed(&app, "rss.xml", &[("If-None-Match", second.as_str())]).await;
+  assert_eq!(resp.status().as_u16(), 200);
+  assert!(!resp.text().await.unwrap().contains("First issue"));
+}
+
+#[actix_rt::test]
+async fn archive_pages_link_to_the_feeds() {
+  let app = spawn_app().await;
+  publish_issue(&app, "First issue", true).await;
+
+  let page = reqwest::get(format!("{}/archive/first-issue", &app.address))
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+  assert!(page.contains(
+    r#"<link rel="alternate" type="application/atom+xml" title="Newsletter" href="http://127.0.0.1/feeds/default/atom.xml">"#
+  ));
+}
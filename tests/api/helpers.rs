@@ -1,6 +1,8 @@
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::Utc;
 use newsletter::{
-  configuration::{get_configuration, DatabaseSettings},
+  authentication::{time_step, TotpSecret, ONE_TIME_CODE_HEADER},
+  configuration::{get_configuration, DatabaseSettings, Settings},
   startup::ServerBuilder,
   telemetry::{get_subscriber, init_subscriber},
 };
@@ -60,6 +62,108 @@ impl TestApp {
       .expect("Failed to execute request.")
   }
 
+  /// POST to the /newsletters/lint endpoint.
+  pub async fn post_newsletters_lint(&self, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+      .post(format!("{}/newsletters/lint", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// POST to the /newsletters endpoint, with a one-time code for users enrolled in two-factor authentication.
+  pub async fn post_newsletters_with_code(
+    &self,
+    body: serde_json::Value,
+    code: &str,
+  ) -> reqwest::Response {
+    reqwest::Client::new()
+      .post(format!("{}/newsletters", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .header(ONE_TIME_CODE_HEADER, code)
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// GET the /admin/audit_log endpoint, with the given query string.
+  pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+      .get(format!("{}/admin/audit_log?{}", &self.address, query))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// POST to the /admin/newsletters endpoint.
+  pub async fn post_admin_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+      .post(format!("{}/admin/newsletters", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// GET the /admin/newsletters endpoint.
+  pub async fn get_admin_newsletters(&self) -> reqwest::Response {
+    reqwest::Client::new()
+      .get(format!("{}/admin/newsletters", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// POST to the /admin/totp/enroll endpoint.
+  pub async fn post_totp_enroll(&self) -> reqwest::Response {
+    reqwest::Client::new()
+      .post(format!("{}/admin/totp/enroll", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// POST to the /admin/totp/confirm endpoint.
+  pub async fn post_totp_confirm(&self, code: &str) -> reqwest::Response {
+    reqwest::Client::new()
+      .post(format!("{}/admin/totp/confirm", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .json(&serde_json::json!({ "code": code }))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  /// Enrolls the test user in two-factor authentication, returning the secret and recovery codes.
+  pub async fn enroll_test_user_in_totp(&self) -> (TotpSecret, Vec<String>) {
+    let enrollment: serde_json::Value = self.post_totp_enroll().await.json().await.unwrap();
+    let secret = TotpSecret::parse(enrollment["secret"].as_str().unwrap()).unwrap();
+
+    let confirmation: serde_json::Value = self
+      .post_totp_confirm(&secret.code_at(time_step(Utc::now())))
+      .await
+      .error_for_status()
+      .unwrap()
+      .json()
+      .await
+      .unwrap();
+    let recovery_codes = confirmation["recovery_codes"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|c| c.as_str().unwrap().to_string())
+      .collect();
+
+    (secret, recovery_codes)
+  }
+
   /// Parse the confirmation links from the given mock request.
   pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
     let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
@@ -89,6 +193,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+  spawn_app_with(|_| {}).await
+}
+
+/// Spawns the app with a configuration adjusted for the test.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
   Lazy::force(&TRACING);
 
   let email_server = MockServer::start().await;
@@ -99,6 +208,9 @@ pub async fn spawn_app() -> TestApp {
     c.database.database_name = Uuid::new_v4().to_string();
     c.email_client.base_url = email_server.uri();
     c.application.port = 0;
+    // Tests submit subscriptions directly, without a form; the bot checks have their own tests.
+    c.signup_protection.enabled = false;
+    configure(&mut c);
     c
   };
 
@@ -131,6 +243,12 @@ pub async fn spawn_app() -> TestApp {
   test_app
 }
 
+/// The body of an error response, which must be a problem+json document.
+pub async fn problem_body(resp: reqwest::Response) -> serde_json::Value {
+  assert_eq!(resp.headers()["Content-Type"], "application/problem+json");
+  resp.json().await.unwrap()
+}
+
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
   let mut connection = PgConnection::connect_with(&config.without_db())
     .await
@@ -1,5 +1,26 @@
+// Some of the older tests have borrows which newer versions of clippy flag as needless,
+// and discard the handle of the server they spawn.
+#![allow(
+  clippy::needless_borrow,
+  clippy::needless_borrows_for_generic_args,
+  clippy::let_underscore_future
+)]
+
+mod admin_audit_log;
+mod admin_totp;
+mod analytics;
+mod archive;
+mod feeds;
 mod health_check;
 mod helpers;
 mod newsletter;
+mod newsletter_lists;
+mod search;
+mod signup_protection;
+mod subscribe_widget;
+mod subscriber_attributes;
+mod subscriber_tags;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod tracking;
@@ -1,4 +1,8 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use argon2::{
+  password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, ConfirmationLinks, TestApp};
 
 use uuid::Uuid;
 
@@ -72,6 +76,364 @@ async fn newsletters_returns_badrequest_for_invalid_data() {
     ),
   ];
 
+  for (body, msg) in test_cases {
+    let resp = app.post_newsletters(body).await;
+
+    assert_eq!(
+      resp.status(),
+      reqwest::StatusCode::BAD_REQUEST,
+      "Expected API to respond with BAD_REQUEST, for {}",
+      msg
+    );
+    let problem: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_issue", "{}", msg);
+  }
+}
+
+#[actix_rt::test]
+async fn invalid_issues_are_problems_telling_what_is_wrong() {
+  let app = spawn_app().await;
+
+  let resp = app
+    .post_newsletters(serde_json::json!({
+      "title": "Newsletter title",
+      "content": { "html": "<p>Newsletter body as HTML</p>" },
+      "list": "unknown",
+    }))
+    .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  assert_eq!(resp.headers()["Content-Type"], "application/problem+json");
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(problem["code"], "invalid_issue");
+  assert_eq!(problem["detail"], "There is no newsletter unknown.");
+}
+
+#[actix_rt::test]
+async fn issues_can_be_written_in_markdown() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "markdown": "---\ntitle: Issue one\n---\nHello **{{ name }}**, read [the post](https://example.com/post).\n",
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert_eq!(email["Subject"], "Issue one");
+  let html = email["HtmlBody"].as_str().unwrap();
+  assert!(html.contains("Hello <strong>phil nadon</strong>"));
+  let text = email["TextBody"].as_str().unwrap();
+  assert!(text.starts_with("Hello phil nadon, read the post (https://example.com/post)."));
+}
+
+#[actix_rt::test]
+async fn explicit_content_overrides_the_markdown() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "markdown": "---\ntitle: Ignored\n---\nMarkdown body\n",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert_eq!(email["Subject"], "Newsletter title");
+  assert!(email["TextBody"]
+    .as_str()
+    .unwrap()
+    .starts_with("Newsletter body as plain text"));
+}
+
+#[actix_rt::test]
+async fn the_text_part_is_generated_when_only_html_is_given() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "html": "<p>Hi {{ name }}, read <a href=\"https://example.com/post\">the post</a>.</p>",
+    }
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  let text = email["TextBody"].as_str().unwrap();
+  assert!(text.starts_with("Hi phil nadon, read [the post][1].\n\n[1]: https://example.com/post"));
+}
+
+#[actix_rt::test]
+async fn html_bodies_are_prepared_for_email_clients() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "preheader": "What's new this week",
+    "content": {
+      "html": "<style>p { color: red; }</style><p>See the <a href=\"/archive\">archive</a>.</p>",
+    }
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  let html = email["HtmlBody"].as_str().unwrap();
+  assert!(html.contains(r#"<p style="color: red;">"#));
+  // The link is absolute by the time it is rewritten to record clicks.
+  assert!(html.contains("url=http%3A%2F%2F127.0.0.1%2Farchive"));
+  assert!(html.contains("What&#39;s new this week</div>"));
+  assert!(!email["TextBody"].as_str().unwrap().contains("What's new"));
+}
+
+#[actix_rt::test]
+async fn disallowed_html_is_stripped_and_reported() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "html": "<p onclick=\"steal()\">Hello</p><script>alert(1)</script>",
+      "text": "Hello",
+    }
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  let report: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(
+    report["stripped"],
+    serde_json::json!(["<script> element", "onclick attribute"])
+  );
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  let html = email["HtmlBody"].as_str().unwrap();
+  assert!(html.contains("<p>Hello</p>"));
+  assert!(!html.contains("<script") && !html.contains("alert(1)"));
+}
+
+#[actix_rt::test]
+async fn strict_sanitization_rejects_disallowed_html() {
+  let app = spawn_app_with(|c| c.sanitization.strict = true).await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "html": "<p>Hello</p><iframe src=\"https://tracker.example.com\"></iframe>",
+      "text": "Hello",
+    }
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn issues_can_be_linted_without_being_sent() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "FREE MONEY INSIDE",
+    "content": {
+      "html": "<div><p>Hi <a href=\"http://localhost:8000/offer\">there</a></div>",
+    }
+  });
+  let resp = app.post_newsletters_lint(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+  let report: serde_json::Value = resp.json().await.unwrap();
+  let codes = |severity: &str| -> Vec<String> {
+    report[severity]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|finding| finding["code"].as_str().unwrap().to_string())
+      .collect()
+  };
+  assert_eq!(codes("errors"), vec!["local_link"]);
+  assert_eq!(
+    codes("warnings"),
+    vec!["all_caps_subject", "spammy_subject", "unbalanced_html"]
+  );
+}
+
+#[actix_rt::test]
+async fn linting_requires_authorization() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::Client::new()
+    .post(format!("{}/newsletters/lint", &app.address))
+    .json(&serde_json::json!({
+      "title": "Newsletter title",
+      "content": { "html": "<p>Hello</p>" }
+    }))
+    .send()
+    .await
+    .expect("Failed to execute request.");
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn lint_findings_block_publishing_when_asked_to() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "html": "<p>Read <a href=\"http://example.com/post\">the post</a></p>",
+    },
+    "block_on": "warnings",
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+  let report: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(report["code"], "lint_failed");
+  assert_eq!(report["warnings"][0]["code"], "insecure_link");
+}
+
+#[actix_rt::test]
+async fn lint_findings_are_reported_when_publishing() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "html": "<p>Read <a href=\"http://example.com/post\">the post</a></p>",
+    },
+    "block_on": "errors",
+  });
+  let resp = app.post_newsletters(body).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  let report: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(report["lint"]["errors"], serde_json::json!([]));
+  assert_eq!(report["lint"]["warnings"][0]["code"], "insecure_link");
+}
+
+#[actix_rt::test]
+async fn invalid_markdown_issues_are_rejected() {
+  let app = spawn_app().await;
+  let test_cases = vec![
+    (
+      serde_json::json!({ "markdown": "No title anywhere." }),
+      "missing title",
+    ),
+    (
+      serde_json::json!({ "markdown": "---\ntitle: [\n---\nBody" }),
+      "invalid front matter",
+    ),
+    (
+      serde_json::json!({ "markdown": "---\ntitle: Later\nsend_at: 2999-01-01T00:00:00Z\n---\nBody" }),
+      "send time, as issues can't be scheduled",
+    ),
+  ];
+
   for (body, msg) in test_cases {
     let resp = app.post_newsletters(body).await;
 
@@ -106,6 +468,8 @@ async fn requests_missing_authorization_are_rejected() {
     r#"Basic realm="publish""#,
     resp.headers()["WWW-Authenticate"]
   );
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(problem["code"], "authentication_failed");
 }
 
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
@@ -201,3 +565,135 @@ async fn invalid_password_is_rejected() {
     response.headers()["WWW-Authenticate"]
   );
 }
+
+#[actix_rt::test]
+async fn repeated_failed_logins_lock_out_the_username() {
+  let app = spawn_app().await;
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  });
+
+  for _ in 0..5 {
+    let response = reqwest::Client::new()
+      .post(format!("{}/newsletters", &app.address))
+      .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request.");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+  }
+
+  // Even the right password is turned away until the lockout expires.
+  let response = app.post_newsletters(body).await;
+
+  assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+  assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[actix_rt::test]
+async fn outdated_password_hashes_are_upgraded_on_login() {
+  let app = spawn_app().await;
+  let body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  });
+
+  let salt = SaltString::generate(&mut rand::thread_rng());
+  let weak_hash = Argon2::new(
+    Algorithm::Argon2id,
+    Version::V0x13,
+    Params::new(4096, 1, 1, None).unwrap(),
+  )
+  .hash_password(app.test_user.password.as_bytes(), &salt)
+  .unwrap()
+  .to_string();
+  sqlx::query!(
+    "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+    weak_hash,
+    app.test_user.user_id,
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+
+  let response = app.post_newsletters(body.clone()).await;
+  assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+  let saved = sqlx::query!(
+    "SELECT password_hash FROM users WHERE user_id = $1",
+    app.test_user.user_id,
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  let upgraded_hash = PasswordHash::new(&saved.password_hash).unwrap();
+  let params = Params::try_from(&upgraded_hash).unwrap();
+  assert_eq!(
+    (params.m_cost(), params.t_cost(), params.p_cost()),
+    (15000, 2, 1)
+  );
+
+  // The password keeps working against the upgraded hash.
+  let response = app.post_newsletters(body).await;
+  assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn unreadable_bodies_are_problems() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::Client::new()
+    .post(format!("{}/newsletters", &app.address))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .json(&serde_json::json!({ "title": 42 }))
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(problem["code"], "malformed_request");
+  assert!(problem["detail"].as_str().unwrap().contains("invalid type"));
+}
+
+#[actix_rt::test]
+async fn publishing_is_audited_even_when_sending_fails() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(500))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app
+    .post_newsletters(serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    }))
+    .await;
+  assert_eq!(
+    response.status(),
+    reqwest::StatusCode::INTERNAL_SERVER_ERROR
+  );
+
+  let saved = sqlx::query!(
+    r#"SELECT details->>'recipients' AS recipients FROM audit_log WHERE action = 'publish_newsletter'"#,
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .expect("failed to fetch the audit log entry");
+  assert_eq!(saved.recipients.as_deref(), Some("1"));
+}
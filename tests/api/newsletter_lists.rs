@@ -0,0 +1,266 @@
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{problem_body, spawn_app, TestApp};
+
+fn rust_weekly() -> serde_json::Value {
+  serde_json::json!({
+    "slug": "rust-weekly",
+    "name": "Rust Weekly",
+    "sender_name": "Rust Weekly",
+    "confirmation_copy": "Welcome to Rust Weekly!",
+  })
+}
+
+fn issue_of(list: &str) -> serde_json::Value {
+  serde_json::json!({
+    "title": "Newsletter title",
+    "list": list,
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  })
+}
+
+/// Subscribes to the given list and confirms, returning the confirmation email which was sent.
+async fn subscribe_and_confirm(app: &TestApp, list: &str) -> serde_json::Value {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .named("Create subscriber")
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions(format!(
+      "name=phil%20nadon&email=phil%40nadon.io&list={}",
+      list
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[actix_rt::test]
+async fn created_newsletters_are_listed() {
+  let app = spawn_app().await;
+
+  let resp = app.post_admin_newsletters(rust_weekly()).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+
+  let newsletters: serde_json::Value = app
+    .get_admin_newsletters()
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+  let slugs: Vec<&str> = newsletters
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|n| n["slug"].as_str().unwrap())
+    .collect();
+  assert_eq!(slugs, vec!["default", "rust-weekly"]);
+}
+
+#[actix_rt::test]
+async fn creating_a_newsletter_is_recorded_in_the_audit_log() {
+  let app = spawn_app().await;
+
+  app
+    .post_admin_newsletters(rust_weekly())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let saved =
+    sqlx::query!("SELECT actor_user_id, target FROM audit_log WHERE action = 'create_newsletter'",)
+      .fetch_one(&app.db_pool)
+      .await
+      .expect("failed to fetch the audit log entry");
+  assert_eq!(saved.actor_user_id, Some(app.test_user.user_id));
+  assert_eq!(saved.target.as_deref(), Some("rust-weekly"));
+}
+
+#[actix_rt::test]
+async fn creating_an_invalid_or_duplicate_newsletter_is_rejected() {
+  let app = spawn_app().await;
+  app
+    .post_admin_newsletters(rust_weekly())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let test_cases = vec![
+    (rust_weekly(), "duplicate slug"),
+    (
+      serde_json::json!({
+        "slug": "Rust Weekly",
+        "name": "Rust Weekly",
+        "sender_name": "Rust Weekly",
+        "confirmation_copy": "Welcome!",
+      }),
+      "invalid slug",
+    ),
+    (
+      serde_json::json!({
+        "slug": "another-list",
+        "name": "Another list",
+        "sender_name": "<script>",
+        "confirmation_copy": "Welcome!",
+      }),
+      "invalid sender name",
+    ),
+  ];
+
+  for (body, msg) in test_cases {
+    let resp = app.post_admin_newsletters(body).await;
+    assert_eq!(
+      resp.status(),
+      reqwest::StatusCode::BAD_REQUEST,
+      "expected api to fail with a 400, with {}",
+      msg,
+    );
+    assert_eq!(
+      problem_body(resp).await["code"],
+      "invalid_request",
+      "{}",
+      msg
+    );
+  }
+}
+
+#[actix_rt::test]
+async fn subscribing_to_an_unknown_newsletter_is_rejected() {
+  let app = spawn_app().await;
+
+  let resp = app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io&list=nope".into())
+    .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn confirmation_email_uses_the_newsletters_copy_and_sender_name() {
+  let app = spawn_app().await;
+  app
+    .post_admin_newsletters(rust_weekly())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let email = subscribe_and_confirm(&app, "rust-weekly").await;
+
+  assert!(email["From"]
+    .as_str()
+    .unwrap()
+    .starts_with("\"Rust Weekly\" <"));
+  assert!(email["TextBody"]
+    .as_str()
+    .unwrap()
+    .starts_with("Welcome to Rust Weekly!"));
+}
+
+#[actix_rt::test]
+async fn issues_are_only_delivered_to_subscribers_of_that_newsletter() {
+  let app = spawn_app().await;
+  app
+    .post_admin_newsletters(rust_weekly())
+    .await
+    .error_for_status()
+    .unwrap();
+  subscribe_and_confirm(&app, "rust-weekly").await;
+
+  {
+    let _mock_guard = Mock::given(any())
+      .respond_with(ResponseTemplate::new(200))
+      .expect(0)
+      .mount_as_scoped(&app.email_server)
+      .await;
+    let resp = app.post_newsletters(issue_of("default")).await;
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  }
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let resp = app.post_newsletters(issue_of("rust-weekly")).await;
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn publishing_to_an_unknown_newsletter_is_rejected() {
+  let app = spawn_app().await;
+
+  let resp = app.post_newsletters(issue_of("nope")).await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn one_subscriber_can_subscribe_to_several_newsletters() {
+  let app = spawn_app().await;
+  app
+    .post_admin_newsletters(rust_weekly())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  subscribe_and_confirm(&app, "default").await;
+  subscribe_and_confirm(&app, "rust-weekly").await;
+
+  let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(subscribers.len(), 1);
+  let memberships = sqlx::query!(
+    "SELECT status FROM newsletter_subscriptions WHERE subscriber_id = $1",
+    subscribers[0].id,
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(memberships.len(), 2);
+  assert!(memberships.iter().all(|m| m.status == "confirmed"));
+}
+
+#[actix_rt::test]
+async fn confirmed_subscribers_are_not_sent_another_confirmation_email() {
+  let app = spawn_app().await;
+  subscribe_and_confirm(&app, "default").await;
+
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+  let resp = app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+}
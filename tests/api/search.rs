@@ -0,0 +1,160 @@
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{problem_body, spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str, html: &str, public: bool) {
+  let _mock_guard = Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_newsletters(serde_json::json!({
+      "title": title,
+      "content": { "html": html },
+      "public": public,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+async fn publish_issues(app: &TestApp) {
+  publish_issue(
+    app,
+    "Async Rust in practice",
+    "<p>Futures, executors and why async functions are lazy.</p>",
+    true,
+  )
+  .await;
+  publish_issue(
+    app,
+    "Gardening tips",
+    "<p>Water your tomatoes; an async watering schedule helps.</p>",
+    true,
+  )
+  .await;
+  publish_issue(
+    app,
+    "Async internals",
+    "<p>Only for members: how the async runtime polls.</p>",
+    false,
+  )
+  .await;
+}
+
+async fn admin_search(app: &TestApp, query: &str) -> reqwest::Response {
+  reqwest::Client::new()
+    .get(format!("{}/admin/issues/search", &app.address))
+    .query(&[("q", query)])
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .unwrap()
+}
+
+#[actix_rt::test]
+async fn readers_find_public_issues_with_highlighted_snippets() {
+  let app = spawn_app().await;
+  publish_issues(&app).await;
+
+  let resp = reqwest::Client::new()
+    .get(format!("{}/archive", &app.address))
+    .query(&[("q", "async rust")])
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status().as_u16(), 200);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains(r#"<a href="/archive/async-rust-in-practice">"#));
+  assert!(page.contains("<mark>async</mark>"));
+  assert!(!page.contains("Gardening tips"));
+  assert!(!page.contains("Async internals"));
+  assert!(page.contains(r#"name="q" value="async rust""#));
+}
+
+#[actix_rt::test]
+async fn results_are_ranked() {
+  let app = spawn_app().await;
+  publish_issues(&app).await;
+
+  let resp = admin_search(&app, "async").await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let body: serde_json::Value = resp.json().await.unwrap();
+  let titles: Vec<&str> = body["results"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|result| result["title"].as_str().unwrap())
+    .collect();
+  // Matches in the title rank above those in the text only.
+  assert_eq!(titles.len(), 3);
+  assert_eq!(titles[2], "Gardening tips");
+}
+
+#[actix_rt::test]
+async fn admins_also_find_private_issues() {
+  let app = spawn_app().await;
+  publish_issues(&app).await;
+
+  let body: serde_json::Value = admin_search(&app, "runtime polls")
+    .await
+    .json()
+    .await
+    .unwrap();
+  let result = &body["results"][0];
+  assert_eq!(result["title"], "Async internals");
+  assert_eq!(result["public"], false);
+  assert!(result["snippet"]
+    .as_str()
+    .unwrap()
+    .contains("<mark>runtime</mark> <mark>polls</mark>"));
+}
+
+#[actix_rt::test]
+async fn searches_with_no_match_say_so() {
+  let app = spawn_app().await;
+  publish_issues(&app).await;
+
+  let page = reqwest::Client::new()
+    .get(format!("{}/archive", &app.address))
+    .query(&[("q", "kubernetes")])
+    .send()
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+  assert!(page.contains("No issue matches your search."));
+}
+
+#[actix_rt::test]
+async fn admin_searches_require_a_query_and_authentication() {
+  let app = spawn_app().await;
+
+  let resp = admin_search(&app, "  ").await;
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(problem_body(resp).await["detail"], "q cannot be empty");
+  let resp = reqwest::get(format!("{}/admin/issues/search?q=async", &app.address))
+    .await
+    .unwrap();
+  assert_eq!(resp.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn issues_cannot_mark_their_own_snippets() {
+  let app = spawn_app().await;
+  publish_issue(
+    &app,
+    "Async Rust in practice",
+    "<p>Futures are lazy, \u{E000}unlike\u{E001} promises.</p>",
+    true,
+  )
+  .await;
+
+  let resp = admin_search(&app, "lazy").await;
+  assert_eq!(resp.status().as_u16(), 200);
+  let results: serde_json::Value = resp.json().await.unwrap();
+  let snippet = results["results"][0]["snippet"].as_str().unwrap();
+  assert!(snippet.contains("<mark>lazy</mark>, unlike promises"));
+  assert_eq!(snippet.matches("<mark>").count(), 1);
+}
@@ -0,0 +1,270 @@
+use std::time::Duration;
+
+use newsletter::configuration::SignupProtectionSettings;
+use sha2::{Digest, Sha256};
+use wiremock::{
+  matchers::{method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+/// Spawns the app with the bot checks on, which tests otherwise turn off.
+async fn spawn_protected_app(configure: impl FnOnce(&mut SignupProtectionSettings)) -> TestApp {
+  let app = spawn_app_with(|c| {
+    c.signup_protection.enabled = true;
+    c.signup_protection.min_submit_time = Duration::ZERO;
+    configure(&mut c.signup_protection);
+  })
+  .await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  app
+}
+
+/// What the subscribe widget fetches before submitting a subscription.
+async fn get_challenge(app: &TestApp) -> (String, u8) {
+  let challenge: serde_json::Value = reqwest::Client::new()
+    .get(format!("{}/subscriptions", &app.address))
+    .header("Accept", "application/json")
+    .send()
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+  (
+    challenge["form_token"].as_str().unwrap().to_string(),
+    challenge["proof_of_work_difficulty"].as_u64().unwrap() as u8,
+  )
+}
+
+async fn post_json(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+  reqwest::Client::new()
+    .post(format!("{}/subscriptions", &app.address))
+    .json(&body)
+    .send()
+    .await
+    .unwrap()
+}
+
+async fn subscribe_with_token(app: &TestApp, email: &str, form_token: &str) -> reqwest::Response {
+  post_json(
+    app,
+    serde_json::json!({ "name": "Phil", "email": email, "form_token": form_token }),
+  )
+  .await
+}
+
+async fn problem_code(resp: reqwest::Response) -> String {
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  problem["code"].as_str().unwrap().to_string()
+}
+
+async fn count_subscribers(app: &TestApp) -> i64 {
+  sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count
+}
+
+#[actix_rt::test]
+async fn subscriptions_filling_in_the_honeypot_are_ignored() {
+  let app = spawn_app().await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let resp = app
+    .post_subscriptions(
+      "name=Phil&email=phil%40nadon.io&website=https%3A%2F%2Fspam.example.org".into(),
+    )
+    .await;
+
+  assert_eq!(resp.status().as_u16(), 200);
+  assert!(resp.text().await.unwrap().contains("phil@nadon.io"));
+  assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[actix_rt::test]
+async fn the_subscribe_form_is_served_with_a_token_and_a_honeypot() {
+  let app = spawn_protected_app(|_| {}).await;
+
+  let page = reqwest::get(format!("{}/subscriptions", &app.address))
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+
+  assert!(page.contains(r#"<input type="hidden" name="form_token" value=""#));
+  assert!(page.contains(r#"name="website""#));
+}
+
+#[actix_rt::test]
+async fn subscriptions_without_a_valid_form_token_are_rejected() {
+  let app = spawn_protected_app(|_| {}).await;
+
+  let resp = post_json(
+    &app,
+    serde_json::json!({ "name": "Phil", "email": "phil@nadon.io" }),
+  )
+  .await;
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(problem_code(resp).await, "invalid_form_token");
+
+  let resp = subscribe_with_token(&app, "phil@nadon.io", "1700000000000.nonce.forged").await;
+  assert_eq!(problem_code(resp).await, "invalid_form_token");
+
+  let resp = app
+    .post_subscriptions("name=Phil&email=phil%40nadon.io".into())
+    .await;
+  assert_eq!(resp.status().as_u16(), 400);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("The form is invalid. Please reload the page and submit it again."));
+  assert!(page.contains(r#"value="phil@nadon.io""#));
+  assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[actix_rt::test]
+async fn forms_submitted_too_quickly_are_rejected() {
+  let app = spawn_protected_app(|s| s.min_submit_time = Duration::from_secs(1)).await;
+
+  let (form_token, _) = get_challenge(&app).await;
+  let resp = subscribe_with_token(&app, "phil@nadon.io", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(problem_code(resp).await, "submitted_too_fast");
+
+  tokio::time::sleep(Duration::from_secs(1)).await;
+  let resp = subscribe_with_token(&app, "phil@nadon.io", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 200);
+  assert_eq!(count_subscribers(&app).await, 1);
+}
+
+#[actix_rt::test]
+async fn form_tokens_are_accepted_only_once() {
+  let app = spawn_protected_app(|_| {}).await;
+
+  let (form_token, _) = get_challenge(&app).await;
+  let resp = subscribe_with_token(&app, "phil@nadon.io", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 200);
+
+  let resp = subscribe_with_token(&app, "ursula@example.com", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(problem_code(resp).await, "expired_form_token");
+}
+
+#[actix_rt::test]
+async fn subscriptions_from_a_single_ip_are_rate_limited() {
+  let app = spawn_protected_app(|s| s.ip_signups_per_hour = 2).await;
+
+  for email in ["phil@nadon.io", "ursula@example.com"] {
+    let (form_token, _) = get_challenge(&app).await;
+    let resp = subscribe_with_token(&app, email, &form_token).await;
+    assert_eq!(resp.status().as_u16(), 200);
+  }
+
+  let (form_token, _) = get_challenge(&app).await;
+  let resp = subscribe_with_token(&app, "octavia@example.org", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 429);
+  let retry_after: u64 = resp.headers()["Retry-After"]
+    .to_str()
+    .unwrap()
+    .parse()
+    .unwrap();
+  assert!(retry_after > 3500 && retry_after <= 3600);
+  assert_eq!(problem_code(resp).await, "too_many_subscriptions");
+  assert_eq!(count_subscribers(&app).await, 2);
+}
+
+#[actix_rt::test]
+async fn forwarding_headers_from_untrusted_peers_do_not_bypass_the_ip_limit() {
+  let app = spawn_protected_app(|s| s.ip_signups_per_hour = 1).await;
+
+  let mut statuses = Vec::new();
+  for (email, forwarded_for) in [
+    ("phil@nadon.io", "203.0.113.1"),
+    ("ursula@example.com", "203.0.113.2"),
+  ] {
+    let (form_token, _) = get_challenge(&app).await;
+    let resp = reqwest::Client::new()
+      .post(format!("{}/subscriptions", &app.address))
+      .header("X-Forwarded-For", forwarded_for)
+      .json(&serde_json::json!({ "name": "Phil", "email": email, "form_token": form_token }))
+      .send()
+      .await
+      .unwrap();
+    statuses.push(resp.status().as_u16());
+  }
+
+  assert_eq!(statuses, vec![200, 429]);
+  assert_eq!(count_subscribers(&app).await, 1);
+}
+
+#[actix_rt::test]
+async fn subscriptions_to_a_single_domain_are_rate_limited() {
+  let app = spawn_protected_app(|s| s.domain_signups_per_hour = 1).await;
+
+  let (form_token, _) = get_challenge(&app).await;
+  let resp = subscribe_with_token(&app, "phil@example.com", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 200);
+
+  let (form_token, _) = get_challenge(&app).await;
+  let resp = subscribe_with_token(&app, "ursula@EXAMPLE.com", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 429);
+
+  let (form_token, _) = get_challenge(&app).await;
+  let resp = subscribe_with_token(&app, "octavia@example.org", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn the_proof_of_work_challenge_must_be_solved() {
+  let app = spawn_protected_app(|s| s.proof_of_work_difficulty = 8).await;
+
+  let (form_token, difficulty) = get_challenge(&app).await;
+  assert_eq!(difficulty, 8);
+  let resp = subscribe_with_token(&app, "phil@nadon.io", &form_token).await;
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(problem_code(resp).await, "invalid_proof_of_work");
+
+  // The first byte of the hash must be zero.
+  let answer = (0..)
+    .map(|n: u32| n.to_string())
+    .find(|answer| Sha256::digest(format!("{}:{}", form_token, answer).as_bytes())[0] == 0)
+    .unwrap();
+  let resp = post_json(
+    &app,
+    serde_json::json!({
+      "name": "Phil",
+      "email": "phil@nadon.io",
+      "form_token": form_token,
+      "proof_of_work": answer,
+    }),
+  )
+  .await;
+  assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn webmail_domains_are_not_rate_limited() {
+  let app = spawn_protected_app(|s| {
+    s.domain_signups_per_hour = 1;
+    s.unlimited_domains = vec!["gmail.com".to_string()];
+  })
+  .await;
+
+  for email in ["phil@gmail.com", "ursula@GMAIL.com"] {
+    let (form_token, _) = get_challenge(&app).await;
+    let resp = subscribe_with_token(&app, email, &form_token).await;
+    assert_eq!(resp.status().as_u16(), 200);
+  }
+  assert_eq!(count_subscribers(&app).await, 2);
+}
@@ -0,0 +1,87 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const ALLOWED_ORIGIN: &str = "https://www.example.com";
+
+async fn spawn_app_allowing_origin() -> TestApp {
+  spawn_app_with(|c| c.application.allowed_origins = vec![ALLOWED_ORIGIN.to_string()]).await
+}
+
+async fn preflight(app: &TestApp, origin: &str) -> reqwest::Response {
+  reqwest::Client::new()
+    .request(
+      reqwest::Method::OPTIONS,
+      format!("{}/subscriptions", &app.address),
+    )
+    .header("Origin", origin)
+    .header("Access-Control-Request-Method", "POST")
+    .header("Access-Control-Request-Headers", "content-type")
+    .send()
+    .await
+    .unwrap()
+}
+
+async fn post_from(app: &TestApp, origin: &str) -> reqwest::Response {
+  reqwest::Client::new()
+    .post(format!("{}/subscriptions", &app.address))
+    .header("Origin", origin)
+    .json(&serde_json::json!({ "name": "", "email": "phil@nadon.io" }))
+    .send()
+    .await
+    .unwrap()
+}
+
+#[actix_rt::test]
+async fn the_widget_script_is_served() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::get(format!("{}/subscriptions/widget.js", &app.address))
+    .await
+    .unwrap();
+
+  assert_eq!(resp.status().as_u16(), 200);
+  assert_eq!(
+    resp.headers()["Content-Type"],
+    "text/javascript; charset=utf-8"
+  );
+  let script = resp.text().await.unwrap();
+  assert!(script.contains(r#"new URL("/subscriptions", script.src)"#));
+}
+
+#[actix_rt::test]
+async fn allowed_origins_can_post_subscriptions_and_read_their_errors() {
+  let app = spawn_app_allowing_origin().await;
+
+  let resp = preflight(&app, ALLOWED_ORIGIN).await;
+  assert_eq!(resp.status().as_u16(), 204);
+  assert_eq!(
+    resp.headers()["Access-Control-Allow-Origin"],
+    ALLOWED_ORIGIN
+  );
+  assert_eq!(resp.headers()["Access-Control-Allow-Methods"], "GET, POST");
+  assert_eq!(
+    resp.headers()["Access-Control-Allow-Headers"],
+    "Accept, Content-Type"
+  );
+
+  let resp = post_from(&app, ALLOWED_ORIGIN).await;
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(
+    resp.headers()["Access-Control-Allow-Origin"],
+    ALLOWED_ORIGIN
+  );
+  assert_eq!(resp.headers()["Vary"], "Origin");
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(problem["errors"]["name"][0], "name cannot be empty!");
+}
+
+#[actix_rt::test]
+async fn other_origins_are_not_allowed() {
+  let app = spawn_app_allowing_origin().await;
+
+  let resp = preflight(&app, "https://spam.example.org").await;
+  assert_eq!(resp.status().as_u16(), 403);
+  assert!(resp.headers().get("Access-Control-Allow-Origin").is_none());
+
+  let resp = post_from(&app, "https://spam.example.org").await;
+  assert!(resp.headers().get("Access-Control-Allow-Origin").is_none());
+}
@@ -0,0 +1,259 @@
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{problem_body, spawn_app, TestApp};
+
+/// Subscribes with the given form and confirms, returning the subscriber's id.
+async fn create_confirmed_subscriber(app: &TestApp, body: &str) -> uuid::Uuid {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions(body.into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  sqlx::query!("SELECT id FROM subscriptions ORDER BY subscribed_at DESC LIMIT 1")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .id
+}
+
+async fn put_attributes(
+  app: &TestApp,
+  subscriber_id: uuid::Uuid,
+  attributes: serde_json::Value,
+) -> reqwest::Response {
+  reqwest::Client::new()
+    .put(format!(
+      "{}/admin/subscribers/{}/attributes",
+      &app.address, subscriber_id
+    ))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .json(&attributes)
+    .send()
+    .await
+    .expect("Failed to execute request.")
+}
+
+fn issue(title: &str, text: &str, html: &str) -> serde_json::Value {
+  serde_json::json!({
+    "title": title,
+    "content": { "text": text, "html": html }
+  })
+}
+
+#[actix_rt::test]
+async fn attributes_can_be_set_when_subscribing() {
+  let app = spawn_app().await;
+
+  create_confirmed_subscriber(
+    &app,
+    "name=phil%20nadon&email=phil%40nadon.io&attributes.city=Montreal&attributes.plan=pro",
+  )
+  .await;
+
+  let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(
+    saved.attributes,
+    serde_json::json!({ "city": "Montreal", "plan": "pro" })
+  );
+}
+
+#[actix_rt::test]
+async fn invalid_attribute_names_are_rejected_when_subscribing() {
+  let app = spawn_app().await;
+
+  let resp = app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io&attributes.City=Montreal".into())
+    .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn attributes_can_be_replaced_through_the_admin_api() {
+  let app = spawn_app().await;
+  let subscriber_id = create_confirmed_subscriber(
+    &app,
+    "name=phil%20nadon&email=phil%40nadon.io&attributes.city=Montreal",
+  )
+  .await;
+
+  let resp = put_attributes(
+    &app,
+    subscriber_id,
+    serde_json::json!({ "company": "Nadon & Co", "seats": 3 }),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(
+    saved.attributes,
+    serde_json::json!({ "company": "Nadon & Co", "seats": 3 })
+  );
+  let audited =
+    sqlx::query!("SELECT target FROM audit_log WHERE action = 'update_subscriber_attributes'")
+      .fetch_one(&app.db_pool)
+      .await
+      .unwrap();
+  assert_eq!(audited.target, Some(subscriber_id.to_string()));
+}
+
+#[actix_rt::test]
+async fn updating_attributes_of_an_unknown_subscriber_returns_not_found() {
+  let app = spawn_app().await;
+
+  let resp = put_attributes(
+    &app,
+    uuid::Uuid::new_v4(),
+    serde_json::json!({ "city": "Montreal" }),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+  assert_eq!(problem_body(resp).await["code"], "not_found");
+}
+
+#[actix_rt::test]
+async fn invalid_attributes_are_rejected_by_the_admin_api() {
+  let app = spawn_app().await;
+  let subscriber_id =
+    create_confirmed_subscriber(&app, "name=phil%20nadon&email=phil%40nadon.io").await;
+
+  let resp = put_attributes(
+    &app,
+    subscriber_id,
+    serde_json::json!({ "address": { "city": "Montreal" } }),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  let problem = problem_body(resp).await;
+  assert_eq!(problem["code"], "invalid_request");
+  assert!(problem["detail"].as_str().unwrap().contains("address"));
+}
+
+#[actix_rt::test]
+async fn merge_tags_are_filled_in_for_every_recipient() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(
+    &app,
+    "name=phil&email=phil%40nadon.io&attributes.city=Montreal",
+  )
+  .await;
+  create_confirmed_subscriber(&app, "name=ada&email=ada%40example.com").await;
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(2)
+    .mount(&app.email_server)
+    .await;
+  let before = app.email_server.received_requests().await.unwrap().len();
+  app
+    .post_newsletters(issue(
+      "News for {{ name }}",
+      r#"Hello from {{ attributes.city | default: "afar" }}"#,
+      r#"<p>Hello from {{ attributes.city | default: "afar" }}</p>"#,
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let mut sent: Vec<(String, String)> = app.email_server.received_requests().await.unwrap()
+    [before..]
+    .iter()
+    .map(|request| {
+      let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+      (
+        body["Subject"].as_str().unwrap().to_string(),
+        body["TextBody"]
+          .as_str()
+          .unwrap()
+          .lines()
+          .next()
+          .unwrap()
+          .to_string(),
+      )
+    })
+    .collect();
+  sent.sort();
+  assert_eq!(
+    sent,
+    vec![
+      ("News for ada".to_string(), "Hello from afar".to_string()),
+      (
+        "News for phil".to_string(),
+        "Hello from Montreal".to_string()
+      ),
+    ]
+  );
+}
+
+#[actix_rt::test]
+async fn issues_with_missing_required_variables_are_not_sent_to_anyone() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(
+    &app,
+    "name=phil&email=phil%40nadon.io&attributes.city=Montreal",
+  )
+  .await;
+  create_confirmed_subscriber(&app, "name=ada&email=ada%40example.com").await;
+
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+  let resp = app
+    .post_newsletters(issue(
+      "Newsletter title",
+      "Hello from {{ attributes.city }}",
+      "<p>Hello from {{ attributes.city }}</p>",
+    ))
+    .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn issues_with_invalid_merge_tags_are_rejected() {
+  let app = spawn_app().await;
+
+  let resp = app
+    .post_newsletters(issue(
+      "Newsletter title",
+      "Hello {{ company }}",
+      "<p>Hello</p>",
+    ))
+    .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+}
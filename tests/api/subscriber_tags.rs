@@ -0,0 +1,216 @@
+use wiremock::{
+  matchers::{method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{problem_body, spawn_app, TestApp};
+
+/// Subscribes with the given email and confirms, returning the subscriber's id.
+async fn create_confirmed_subscriber(app: &TestApp, email: &str) -> uuid::Uuid {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions(format!("name=someone&email={}", email))
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  sqlx::query!(
+    "SELECT id FROM subscriptions WHERE email = $1",
+    email.replace("%40", "@")
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap()
+  .id
+}
+
+async fn tag_request(
+  app: &TestApp,
+  method: reqwest::Method,
+  subscriber_id: uuid::Uuid,
+  tag: &str,
+) -> reqwest::Response {
+  reqwest::Client::new()
+    .request(
+      method,
+      format!(
+        "{}/admin/subscribers/{}/tags/{}",
+        &app.address, subscriber_id, tag
+      ),
+    )
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .expect("Failed to execute request.")
+}
+
+async fn tags_of(app: &TestApp, subscriber_id: uuid::Uuid) -> Vec<String> {
+  sqlx::query!(
+    "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+    subscriber_id
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap()
+  .into_iter()
+  .map(|r| r.tag)
+  .collect()
+}
+
+/// Publishes an issue to the segment, returning who it was sent to.
+async fn publish_to_segment(app: &TestApp, segment: serde_json::Value) -> Vec<String> {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .mount_as_scoped(&app.email_server)
+    .await;
+  let before = app.email_server.received_requests().await.unwrap().len();
+  app
+    .post_newsletters(serde_json::json!({
+      "title": "Product update",
+      "segment": segment,
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let mut recipients: Vec<String> = app.email_server.received_requests().await.unwrap()[before..]
+    .iter()
+    .map(|request| {
+      let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+      body["To"].as_str().unwrap().to_string()
+    })
+    .collect();
+  recipients.sort();
+  recipients
+}
+
+#[actix_rt::test]
+async fn subscribers_can_be_tagged_and_untagged() {
+  let app = spawn_app().await;
+  let subscriber_id = create_confirmed_subscriber(&app, "phil%40nadon.io").await;
+
+  for tag in ["beta", "beta", "staff"] {
+    let resp = tag_request(&app, reqwest::Method::PUT, subscriber_id, tag).await;
+    assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+  }
+  assert_eq!(tags_of(&app, subscriber_id).await, vec!["beta", "staff"]);
+
+  let resp = tag_request(&app, reqwest::Method::DELETE, subscriber_id, "staff").await;
+  assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+  assert_eq!(tags_of(&app, subscriber_id).await, vec!["beta"]);
+
+  let audited = sqlx::query!(
+    "SELECT COUNT(*) AS \"count!\" FROM audit_log WHERE action IN ('tag_subscriber', 'untag_subscriber')"
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(audited.count, 4);
+}
+
+#[actix_rt::test]
+async fn tagging_an_unknown_subscriber_returns_not_found() {
+  let app = spawn_app().await;
+
+  let subscriber_id = uuid::Uuid::new_v4();
+  let resp = tag_request(&app, reqwest::Method::PUT, subscriber_id, "beta").await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+  let problem = problem_body(resp).await;
+  assert_eq!(problem["code"], "not_found");
+  assert_eq!(
+    problem["detail"],
+    format!("There is no subscriber {}.", subscriber_id)
+  );
+}
+
+#[actix_rt::test]
+async fn invalid_tags_are_rejected() {
+  let app = spawn_app().await;
+  let subscriber_id = create_confirmed_subscriber(&app, "phil%40nadon.io").await;
+
+  let resp = tag_request(&app, reqwest::Method::PUT, subscriber_id, "Beta").await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  let problem = problem_body(resp).await;
+  assert_eq!(problem["code"], "invalid_request");
+  assert!(problem["detail"].as_str().unwrap().contains("Beta"));
+}
+
+#[actix_rt::test]
+async fn issues_are_only_sent_to_subscribers_in_the_segment() {
+  let app = spawn_app().await;
+  let tester = create_confirmed_subscriber(&app, "tester%40example.com").await;
+  create_confirmed_subscriber(&app, "newcomer%40example.com").await;
+  let veteran = create_confirmed_subscriber(&app, "veteran%40example.com").await;
+  tag_request(&app, reqwest::Method::PUT, tester, "beta")
+    .await
+    .error_for_status()
+    .unwrap();
+  sqlx::query!(
+    "UPDATE subscriptions SET subscribed_at = now() - interval '90 days' WHERE id = ANY($1)",
+    &[tester, veteran][..],
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+
+  let beta = publish_to_segment(&app, serde_json::json!({ "tag": "beta" })).await;
+  assert_eq!(beta, vec!["tester@example.com"]);
+
+  let beta_or_recent = publish_to_segment(
+    &app,
+    serde_json::json!({
+      "any": [{ "tag": "beta" }, { "subscribed_at": { "within_days": 30 } }]
+    }),
+  )
+  .await;
+  assert_eq!(
+    beta_or_recent,
+    vec!["newcomer@example.com", "tester@example.com"]
+  );
+}
+
+#[actix_rt::test]
+async fn invalid_segments_are_rejected() {
+  let app = spawn_app().await;
+
+  for segment in [
+    serde_json::json!({ "tag": "Not A Tag" }),
+    serde_json::json!({ "tags": "beta" }),
+  ] {
+    let resp = app
+      .post_newsletters(serde_json::json!({
+        "title": "Product update",
+        "segment": segment,
+        "content": {
+          "text": "Newsletter body as plain text",
+          "html": "<p>Newsletter body as HTML</p>",
+        }
+      }))
+      .await;
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  }
+}
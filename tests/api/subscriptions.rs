@@ -135,3 +135,177 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     reqwest::StatusCode::INTERNAL_SERVER_ERROR
   );
 }
+
+#[actix_rt::test]
+async fn the_subscribe_form_is_served() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::get(format!("{}/subscriptions", &app.address))
+    .await
+    .unwrap();
+
+  assert_eq!(resp.status().as_u16(), 200);
+  assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+  let page = resp.text().await.unwrap();
+  assert!(page.contains(r#"<form method="post" action="/subscriptions">"#));
+  assert!(page.contains(r#"<input type="hidden" name="list" value="default">"#));
+}
+
+#[actix_rt::test]
+async fn invalid_submissions_show_every_error_next_to_the_form() {
+  let app = spawn_app().await;
+  let name = "<".repeat(257);
+  let body = format!("name={}&email=not-an-email", "%3C".repeat(257));
+
+  let resp = app.post_subscriptions(body).await;
+
+  assert_eq!(resp.status().as_u16(), 400);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("<li>name cannot be more than 256 characters!</li>"));
+  assert!(page.contains("<li>name cannot contain special characters!</li>"));
+  assert!(page.contains("<li>not-an-email is not a valid subscriber email</li>"));
+  assert!(page.contains(r#"<input type="email" name="email" value="not-an-email" required>"#));
+  // What was submitted is filled in again, escaped.
+  assert!(page.contains(&"&lt;".repeat(257)));
+  assert!(!page.contains(&name));
+}
+
+#[actix_rt::test]
+async fn subscribers_are_told_to_check_their_inbox() {
+  let app = spawn_app().await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  let resp = app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await;
+
+  assert_eq!(resp.status().as_u16(), 200);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("<title>Check your inbox</title>"));
+  assert!(page.contains("the email we sent to phil@nadon.io"));
+}
+
+async fn post_json_subscription(
+  app: &crate::helpers::TestApp,
+  body: serde_json::Value,
+) -> reqwest::Response {
+  reqwest::Client::new()
+    .post(format!("{}/subscriptions", &app.address))
+    .json(&body)
+    .send()
+    .await
+    .unwrap()
+}
+
+#[actix_rt::test]
+async fn subscriptions_can_be_submitted_as_json() {
+  let app = spawn_app().await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let resp = post_json_subscription(
+    &app,
+    serde_json::json!({
+      "name": "phil nadon",
+      "email": "phil@nadon.io",
+      "attributes": { "city": "Montreal", "age": 42 },
+    }),
+  )
+  .await;
+
+  assert_eq!(resp.status().as_u16(), 200);
+  let body: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(body["list"], "default");
+  let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(
+    saved.attributes,
+    serde_json::json!({ "city": "Montreal", "age": 42 })
+  );
+}
+
+#[actix_rt::test]
+async fn invalid_subscriptions_are_problems_with_every_error_by_field() {
+  let app = spawn_app().await;
+
+  let resp = post_json_subscription(
+    &app,
+    serde_json::json!({ "name": "<".repeat(257), "email": "not-an-email", "list": "unknown" }),
+  )
+  .await;
+
+  assert_eq!(resp.status().as_u16(), 400);
+  assert_eq!(resp.headers()["Content-Type"], "application/problem+json");
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(problem["status"], 400);
+  assert_eq!(problem["code"], "invalid_subscription");
+  assert_eq!(problem["type"], "/problems/invalid-subscription");
+  assert_eq!(
+    problem["errors"],
+    serde_json::json!({
+      "name": [
+        "name cannot be more than 256 characters!",
+        "name cannot contain special characters!",
+      ],
+      "email": ["not-an-email is not a valid subscriber email"],
+      "list": ["There is no newsletter unknown."],
+    })
+  );
+}
+
+#[actix_rt::test]
+async fn form_submissions_get_problems_when_accepting_json() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::Client::new()
+    .post(format!("{}/subscriptions", &app.address))
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .header("Accept", "application/json")
+    .body("name=&email=phil%40nadon.io")
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(resp.status().as_u16(), 400);
+  let problem: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(problem["errors"]["name"][0], "name cannot be empty!");
+}
+
+#[actix_rt::test]
+async fn unreadable_subscriptions_are_problems() {
+  let app = spawn_app().await;
+
+  let test_cases = [
+    (
+      "application/json",
+      r#"{"name": "phil nadon"}"#,
+      400,
+      "malformed_request",
+    ),
+    ("application/json", "not json", 400, "malformed_request"),
+    ("text/plain", "phil@nadon.io", 415, "unsupported_media_type"),
+  ];
+  for (content_type, body, status, code) in test_cases {
+    let resp = reqwest::Client::new()
+      .post(format!("{}/subscriptions", &app.address))
+      .header("Content-Type", content_type)
+      .body(body)
+      .send()
+      .await
+      .unwrap();
+
+    assert_eq!(resp.status().as_u16(), status, "{}", body);
+    let problem: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(problem["code"], code, "{}", body);
+  }
+}
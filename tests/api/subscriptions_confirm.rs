@@ -66,3 +66,148 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
   assert_eq!(saved.name, "phil nadon");
   assert_eq!(saved.status, "confirmed");
 }
+
+#[actix_rt::test]
+async fn confirmed_subscribers_are_shown_their_preferences_link() {
+  let app = spawn_app().await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await;
+  let req = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(req);
+  let page = reqwest::get(confirmation_links.html)
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+
+  let token = sqlx::query!("SELECT preferences_token FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .preferences_token;
+  assert!(page.contains("You are now subscribed to Newsletter."));
+  assert!(page.contains(&format!(
+    r#"<a href="http://127.0.0.1/subscriptions/preferences?token={}">"#,
+    token
+  )));
+}
+
+#[actix_rt::test]
+async fn unknown_tokens_show_an_invalid_link_page() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::get(format!(
+    "{}/subscriptions/confirm?subscription_token=unknown",
+    &app.address
+  ))
+  .await
+  .unwrap();
+
+  assert_eq!(resp.status().as_u16(), 401);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("This link is invalid or has expired"));
+}
+
+#[actix_rt::test]
+async fn old_confirmation_links_do_not_subscribe_again_after_unsubscribing() {
+  let app = spawn_app().await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await;
+  let req = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(req);
+  reqwest::get(confirmation_links.html.clone())
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  let token = sqlx::query!("SELECT preferences_token FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .preferences_token;
+  reqwest::Client::new()
+    .post(format!("{}/subscriptions/unsubscribe", &app.address))
+    .form(&[("token", &token)])
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  let resp = reqwest::get(confirmation_links.html).await.unwrap();
+
+  assert_eq!(resp.status().as_u16(), 401);
+  let saved = sqlx::query!("SELECT status FROM newsletter_subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.status, "unsubscribed");
+}
+
+#[actix_rt::test]
+async fn confirming_again_keeps_the_confirmation_date() {
+  let app = spawn_app().await;
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await;
+  let req = &app.email_server.received_requests().await.unwrap()[0];
+  reqwest::get(app.get_confirmation_links(req).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  sqlx::query!("UPDATE newsletter_subscriptions SET confirmed_at = now() - interval '1 day'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  let before = sqlx::query!("SELECT confirmed_at FROM newsletter_subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .confirmed_at;
+
+  // A second request for a subscription which is already confirmed.
+  sqlx::query!(
+    r#"
+    INSERT INTO subscription_tokens (subscription_token, subscriber_id, newsletter_id)
+    SELECT 'again', subscriber_id, newsletter_id FROM newsletter_subscriptions
+    "#
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+  reqwest::get(format!(
+    "{}/subscriptions/confirm?subscription_token=again",
+    &app.address
+  ))
+  .await
+  .unwrap()
+  .error_for_status()
+  .unwrap();
+
+  let after = sqlx::query!("SELECT confirmed_at FROM newsletter_subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .confirmed_at;
+  assert_eq!(after, before);
+}
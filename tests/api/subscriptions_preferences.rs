@@ -0,0 +1,487 @@
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn issue() -> serde_json::Value {
+  serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  })
+}
+
+/// Subscribes and confirms, returning the subscriber's preferences token.
+async fn create_confirmed_subscriber(app: &TestApp) -> String {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .named("Create subscriber")
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  sqlx::query!("SELECT preferences_token FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .preferences_token
+}
+
+async fn post_form(app: &TestApp, endpoint: &str, body: &str) -> reqwest::Response {
+  reqwest::Client::new()
+    .post(format!("{}{}", &app.address, endpoint))
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .body(body.to_string())
+    .send()
+    .await
+    .expect("failed to execute request")
+}
+
+/// Publishes an issue, returning the number of emails which were sent.
+async fn publish_issue(app: &TestApp) -> usize {
+  let _mock_guard = Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .mount_as_scoped(&app.email_server)
+    .await;
+  let before = app.email_server.received_requests().await.unwrap().len();
+  app
+    .post_newsletters(issue())
+    .await
+    .error_for_status()
+    .unwrap();
+  app.email_server.received_requests().await.unwrap().len() - before
+}
+
+async fn recorded_changes(app: &TestApp) -> Vec<String> {
+  sqlx::query!("SELECT change FROM subscription_changes ORDER BY id")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.change)
+    .collect()
+}
+
+#[actix_rt::test]
+async fn every_issue_links_to_the_preferences_page() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  assert_eq!(publish_issue(&app).await, 1);
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  let link = format!("/subscriptions/preferences?token={}", token);
+  assert!(body["HtmlBody"].as_str().unwrap().contains(&link));
+  assert!(body["TextBody"].as_str().unwrap().contains(&link));
+}
+
+#[actix_rt::test]
+async fn the_preferences_page_shows_the_subscribers_settings() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  let resp = reqwest::get(format!(
+    "{}/subscriptions/preferences?token={}",
+    app.address, token
+  ))
+  .await
+  .unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains(r#"value="phil nadon""#));
+  assert!(page.contains(r#"value="default" checked"#));
+  assert!(page.contains("<title>Your subscription</title>"));
+}
+
+#[actix_rt::test]
+async fn unknown_preferences_tokens_are_rejected() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::get(format!(
+    "{}/subscriptions/preferences?token=nope",
+    app.address
+  ))
+  .await
+  .unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("This link is invalid or has expired"));
+}
+
+#[actix_rt::test]
+async fn subscribers_can_change_their_name() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  let resp = post_form(
+    &app,
+    "/subscriptions/preferences",
+    &format!(
+      "token={}&name=phil&frequency=every_issue&list=default",
+      token
+    ),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  let saved = sqlx::query!("SELECT name FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.name, "phil");
+  assert_eq!(recorded_changes(&app).await, vec!["name"]);
+}
+
+#[actix_rt::test]
+async fn invalid_names_are_rejected() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  let resp = post_form(
+    &app,
+    "/subscriptions/preferences",
+    &format!(
+      "token={}&name=%3Cphil%3E&frequency=every_issue&list=default",
+      token
+    ),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("name cannot contain special characters!"));
+  // What was submitted is shown again, so that it can be fixed.
+  assert!(page.contains(r#"value="&lt;phil&gt;""#));
+  assert!(recorded_changes(&app).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn subscribers_can_pick_their_lists() {
+  let app = spawn_app().await;
+  app
+    .post_admin_newsletters(serde_json::json!({
+      "slug": "rust-weekly",
+      "name": "Rust Weekly",
+      "sender_name": "Rust Weekly",
+      "confirmation_copy": "Welcome to Rust Weekly!",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+  let token = create_confirmed_subscriber(&app).await;
+
+  post_form(
+    &app,
+    "/subscriptions/preferences",
+    &format!(
+      "token={}&name=phil%20nadon&frequency=every_issue&list=rust-weekly",
+      token
+    ),
+  )
+  .await
+  .error_for_status()
+  .unwrap();
+
+  let memberships = sqlx::query!(
+    r#"
+    SELECT n.slug, ns.status
+    FROM newsletter_subscriptions ns
+    JOIN newsletters n ON n.id = ns.newsletter_id
+    ORDER BY n.slug
+    "#
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap();
+  let memberships: Vec<(&str, &str)> = memberships
+    .iter()
+    .map(|m| (m.slug.as_str(), m.status.as_str()))
+    .collect();
+  assert_eq!(
+    memberships,
+    vec![("default", "unsubscribed"), ("rust-weekly", "confirmed")]
+  );
+  assert_eq!(publish_issue(&app).await, 0);
+  assert_eq!(recorded_changes(&app).await, vec!["lists"]);
+}
+
+#[actix_rt::test]
+async fn paused_subscribers_receive_nothing_until_they_resume() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  post_form(
+    &app,
+    "/subscriptions/preferences/pause",
+    &format!("token={}&weeks=4", token),
+  )
+  .await
+  .error_for_status()
+  .unwrap();
+  assert_eq!(publish_issue(&app).await, 0);
+
+  post_form(
+    &app,
+    "/subscriptions/preferences/pause",
+    &format!("token={}&weeks=0", token),
+  )
+  .await
+  .error_for_status()
+  .unwrap();
+  assert_eq!(publish_issue(&app).await, 1);
+  assert_eq!(recorded_changes(&app).await, vec!["pause", "pause"]);
+}
+
+#[actix_rt::test]
+async fn pauses_longer_than_a_year_are_rejected() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  let resp = post_form(
+    &app,
+    "/subscriptions/preferences/pause",
+    &format!("token={}&weeks=53", token),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("A subscription can be paused for at most 52 weeks."));
+}
+
+#[actix_rt::test]
+async fn weekly_subscribers_receive_at_most_one_issue_a_week() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  post_form(
+    &app,
+    "/subscriptions/preferences",
+    &format!(
+      "token={}&name=phil%20nadon&frequency=weekly&list=default",
+      token
+    ),
+  )
+  .await
+  .error_for_status()
+  .unwrap();
+
+  assert_eq!(publish_issue(&app).await, 1);
+  assert_eq!(publish_issue(&app).await, 0);
+}
+
+#[actix_rt::test]
+async fn unsubscribed_subscribers_receive_nothing() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  let resp = post_form(
+    &app,
+    "/subscriptions/unsubscribe",
+    &format!("token={}", token),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  assert_eq!(publish_issue(&app).await, 0);
+  assert_eq!(recorded_changes(&app).await, vec!["unsubscribe"]);
+}
+
+/// Requests an address change and returns the confirmation link sent to the new address.
+async fn request_email_change(app: &TestApp, token: &str, new_email: &str) -> reqwest::Url {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  post_form(
+    app,
+    "/subscriptions/preferences/email",
+    &format!("token={}&email={}", token, new_email),
+  )
+  .await
+  .error_for_status()
+  .unwrap();
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert_eq!(body["To"], new_email.replace("%40", "@"));
+  app.get_confirmation_links(&email_request).html
+}
+
+#[actix_rt::test]
+async fn the_address_only_changes_once_the_new_address_confirms() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+
+  let confirmation_link = request_email_change(&app, &token, "phil%40example.com").await;
+  let saved = sqlx::query!("SELECT email FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.email, "phil@nadon.io");
+
+  Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let resp = reqwest::get(confirmation_link)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("Your address is now phil@example.com."));
+
+  let saved = sqlx::query!("SELECT email FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.email, "phil@example.com");
+  assert_eq!(recorded_changes(&app).await, vec!["email"]);
+
+  let notice = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let notice: serde_json::Value = serde_json::from_slice(&notice.body).unwrap();
+  assert_eq!(notice["To"], "phil@nadon.io");
+}
+
+#[actix_rt::test]
+async fn changing_to_an_address_which_is_already_subscribed_is_rejected() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=someone&email=someone%40example.com".into())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let resp = post_form(
+    &app,
+    "/subscriptions/preferences/email",
+    &format!("token={}&email=someone%40example.com", token),
+  )
+  .await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("That address is already subscribed."));
+  assert!(page.contains(r#"value="someone@example.com""#));
+}
+
+#[actix_rt::test]
+async fn confirming_after_the_new_address_was_taken_is_rejected() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  let confirmation_link = request_email_change(&app, &token, "someone%40example.com").await;
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=someone&email=someone%40example.com".into())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let resp = reqwest::get(confirmation_link).await.unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("someone@example.com was subscribed since you asked for the change"));
+  let saved = sqlx::query!("SELECT email FROM subscriptions WHERE name = 'phil nadon'")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.email, "phil@nadon.io");
+}
+
+#[actix_rt::test]
+async fn unknown_email_change_tokens_are_rejected() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::get(format!(
+    "{}/subscriptions/email/confirm?confirmation_token=nope",
+    app.address
+  ))
+  .await
+  .unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("This link is invalid or has expired"));
+}
+
+#[actix_rt::test]
+async fn email_change_links_expire_after_a_day() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  let confirmation_link = request_email_change(&app, &token, "phil%40example.com").await;
+  sqlx::query!("UPDATE email_change_requests SET requested_at = now() - interval '25 hours'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+  let resp = reqwest::get(confirmation_link.clone()).await.unwrap();
+  assert_eq!(resp.status(), reqwest::StatusCode::GONE);
+  let page = resp.text().await.unwrap();
+  assert!(page.contains("This link is invalid or has expired"));
+  let saved = sqlx::query!("SELECT email FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.email, "phil@nadon.io");
+
+  // The expired request is discarded, so the link doesn't work anymore.
+  let resp = reqwest::get(confirmation_link).await.unwrap();
+  assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
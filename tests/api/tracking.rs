@@ -0,0 +1,339 @@
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Subscribes and confirms, returning the subscriber's preferences token.
+async fn create_confirmed_subscriber(app: &TestApp) -> String {
+  let _mock_guard = Mock::given(path("/email"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200))
+    .named("Create subscriber")
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=phil%20nadon&email=phil%40nadon.io".into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  reqwest::get(app.get_confirmation_links(&email_request).html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  sqlx::query!("SELECT preferences_token FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .preferences_token
+}
+
+/// Publishes an issue, returning its id and the html sent for it.
+async fn publish_issue_with(app: &TestApp, html: &str) -> (String, String) {
+  let _mock_guard = Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  let report: serde_json::Value = app
+    .post_newsletters(serde_json::json!({
+      "title": "Newsletter title",
+      "content": { "html": html },
+    }))
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+
+  let email_request = app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert!(!body["TextBody"].as_str().unwrap().contains("/tracking/"));
+  (
+    report["issue_id"].as_str().unwrap().to_string(),
+    body["HtmlBody"].as_str().unwrap().to_string(),
+  )
+}
+
+/// Every URL of the app in the html which starts with the prefix, pointed at the test app.
+fn tracking_urls(app: &TestApp, html: &str, prefix: &str) -> Vec<reqwest::Url> {
+  let prefix = format!("http://127.0.0.1{}", prefix);
+  html
+    .match_indices(&prefix)
+    .map(|(start, _)| {
+      let end = start + html[start..].find('"').unwrap();
+      let mut url = reqwest::Url::parse(&html[start..end].replace("&amp;", "&")).unwrap();
+      url.set_port(Some(app.port)).unwrap();
+      url
+    })
+    .collect()
+}
+
+/// Publishes an issue, returning the URL of its tracking pixel, if it has one.
+async fn publish_issue(app: &TestApp) -> Option<reqwest::Url> {
+  let (_, html) = publish_issue_with(app, "<p>Newsletter body as HTML</p>").await;
+  tracking_urls(app, &html, "/tracking/open?").pop()
+}
+
+/// Follows a tracking link, without following the redirect it responds with.
+async fn click(url: reqwest::Url) -> reqwest::Response {
+  reqwest::Client::builder()
+    .redirect(reqwest::redirect::Policy::none())
+    .build()
+    .unwrap()
+    .get(url)
+    .send()
+    .await
+    .unwrap()
+}
+
+async fn get_issue_report(app: &TestApp, issue_id: &str) -> reqwest::Response {
+  reqwest::Client::new()
+    .get(format!("{}/admin/issues/{}/report", &app.address, issue_id))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .unwrap()
+}
+
+async fn open_counts(app: &TestApp) -> Vec<i32> {
+  sqlx::query!("SELECT open_count FROM issue_opens")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.open_count)
+    .collect()
+}
+
+#[actix_rt::test]
+async fn opens_are_recorded_by_the_tracking_pixel() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let pixel_url = publish_issue(&app).await.expect("no tracking pixel");
+
+  for _ in 0..2 {
+    let resp = reqwest::get(pixel_url.clone()).await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Type"], "image/gif");
+    assert_eq!(resp.headers()["Cache-Control"], "no-store");
+  }
+
+  let open = sqlx::query!("SELECT first_opened_at, last_opened_at, open_count FROM issue_opens")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(open.open_count, 2);
+  assert!(open.first_opened_at < open.last_opened_at);
+}
+
+#[actix_rt::test]
+async fn forged_tracking_pixels_are_rejected() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let mut pixel_url = publish_issue(&app).await.expect("no tracking pixel");
+
+  let query: Vec<(String, String)> = pixel_url
+    .query_pairs()
+    .map(|(key, value)| match key.as_ref() {
+      "signature" => (key.to_string(), "forged".to_string()),
+      _ => (key.to_string(), value.to_string()),
+    })
+    .collect();
+  pixel_url.query_pairs_mut().clear().extend_pairs(query);
+  let resp = reqwest::get(pixel_url).await.unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+  assert!(open_counts(&app).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn newsletters_can_turn_open_tracking_off() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let old_pixel_url = publish_issue(&app).await.expect("no tracking pixel");
+
+  let resp = reqwest::Client::new()
+    .put(format!(
+      "{}/admin/newsletters/default/tracking",
+      &app.address
+    ))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .json(&serde_json::json!({ "track_opens": false }))
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status(), reqwest::StatusCode::OK);
+  let newsletter: serde_json::Value = resp.json().await.unwrap();
+  assert_eq!(newsletter["track_opens"], false);
+
+  assert!(publish_issue(&app).await.is_none());
+  reqwest::get(old_pixel_url)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  assert!(open_counts(&app).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn unknown_newsletters_cannot_have_their_tracking_changed() {
+  let app = spawn_app().await;
+
+  let resp = reqwest::Client::new()
+    .put(format!("{}/admin/newsletters/nope/tracking", &app.address))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .json(&serde_json::json!({ "track_opens": false }))
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn subscribers_can_opt_out_of_open_tracking() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  let old_pixel_url = publish_issue(&app).await.expect("no tracking pixel");
+
+  let page = reqwest::Client::new()
+    .post(format!(
+      "{}/subscriptions/preferences/tracking",
+      &app.address
+    ))
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .body(format!("token={}&allow_tracking=false", token))
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+  assert!(page.contains("We don't know when you open our emails"));
+
+  assert!(publish_issue(&app).await.is_none());
+  // Issues sent before the subscriber opted out don't tell either.
+  reqwest::get(old_pixel_url)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  assert!(open_counts(&app).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn links_are_rewritten_to_record_clicks() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  let (issue_id, html) = publish_issue_with(
+    &app,
+    r#"<a href="https://example.com/post?a=1&b=2">Post</a> <a href="https://example.com/other">Other</a>
+    <a href="mailto:hi@example.com">Mail</a>"#,
+  )
+  .await;
+  assert!(html.contains(r#"href="mailto:hi@example.com""#));
+  // Managing the subscription doesn't depend on the tracking links.
+  assert!(html.contains(&format!("/subscriptions/preferences?token={}", token)));
+
+  let click_urls = tracking_urls(&app, &html, "/tracking/click?");
+  assert_eq!(click_urls.len(), 2);
+  for _ in 0..2 {
+    let resp = click(click_urls[0].clone()).await;
+    assert_eq!(resp.status(), reqwest::StatusCode::FOUND);
+    assert_eq!(
+      resp.headers()["Location"],
+      "https://example.com/post?a=1&b=2"
+    );
+  }
+  click(click_urls[1].clone()).await;
+
+  let report: serde_json::Value = get_issue_report(&app, &issue_id)
+    .await
+    .json()
+    .await
+    .unwrap();
+  assert_eq!(report["delivered"], 1);
+  assert_eq!(
+    report["links"],
+    serde_json::json!([
+      { "url": "https://example.com/post?a=1&b=2", "unique_clicks": 1, "clicks": 2 },
+      { "url": "https://example.com/other", "unique_clicks": 1, "clicks": 1 },
+    ])
+  );
+}
+
+#[actix_rt::test]
+async fn click_links_cannot_redirect_elsewhere() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  let (_, html) = publish_issue_with(&app, r#"<a href="https://example.com/post">Post</a>"#).await;
+  let mut click_url = tracking_urls(&app, &html, "/tracking/click?")
+    .pop()
+    .unwrap();
+
+  let query: Vec<(String, String)> = click_url
+    .query_pairs()
+    .map(|(key, value)| match key.as_ref() {
+      "url" => (key.to_string(), "https://evil.example.com".to_string()),
+      _ => (key.to_string(), value.to_string()),
+    })
+    .collect();
+  click_url.query_pairs_mut().clear().extend_pairs(query);
+  let resp = click(click_url).await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+  assert!(resp.headers().get("Location").is_none());
+}
+
+#[actix_rt::test]
+async fn links_are_not_rewritten_for_subscribers_who_opted_out() {
+  let app = spawn_app().await;
+  let token = create_confirmed_subscriber(&app).await;
+  reqwest::Client::new()
+    .post(format!(
+      "{}/subscriptions/preferences/tracking",
+      &app.address
+    ))
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .body(format!("token={}&allow_tracking=false", token))
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  let (_, html) = publish_issue_with(&app, r#"<a href="https://example.com/post">Post</a>"#).await;
+
+  assert!(html.contains(r#"href="https://example.com/post""#));
+  assert!(tracking_urls(&app, &html, "/tracking/").is_empty());
+}
+
+#[actix_rt::test]
+async fn unknown_issues_have_no_report() {
+  let app = spawn_app().await;
+
+  let resp = get_issue_report(&app, &uuid::Uuid::new_v4().to_string()).await;
+
+  assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}